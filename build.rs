@@ -1,3 +1,6 @@
 fn main() {
-    winres::WindowsResource::new().compile().unwrap();
+    // The icon and version info only apply to Windows builds.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
+        winres::WindowsResource::new().compile().unwrap();
+    }
 }
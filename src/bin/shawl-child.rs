@@ -1,4 +1,5 @@
 use log::info;
+use std::io::Write;
 
 use clap::Parser;
 
@@ -16,6 +17,14 @@ struct Cli {
     /// Test option, prints an extra line to stdout if received
     #[clap(long)]
     test: bool,
+
+    /// Print this many lines to stdout as fast as possible, then exit
+    #[clap(long)]
+    flood: Option<u64>,
+
+    /// Exit when this line is read from stdin or when stdin is closed
+    #[clap(long, value_name = "keyword")]
+    stop_on_stdin: Option<String>,
 }
 
 fn prepare_logging() -> Result<(), Box<dyn std::error::Error>> {
@@ -60,6 +69,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("shawl-child test option received");
     }
 
+    if let Some(lines) = cli.flood {
+        let start = std::time::Instant::now();
+        let mut stdout = std::io::stdout().lock();
+        for i in 0..lines {
+            writeln!(stdout, "shawl-child flood line {}", i)?;
+        }
+        drop(stdout);
+        info!("Flooded {} lines in {:?}", lines, start.elapsed());
+        return Ok(());
+    }
+
     if let Some(code) = cli.exit {
         std::process::exit(code);
     }
@@ -67,6 +87,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
     let running2 = running.clone();
 
+    if let Some(keyword) = cli.stop_on_stdin.clone() {
+        let running = running.clone();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim() == keyword {
+                    info!("Received {} on stdin", keyword);
+                    running.store(false, std::sync::atomic::Ordering::SeqCst);
+                    return;
+                }
+            }
+            info!("stdin was closed");
+            running.store(false, std::sync::atomic::Ordering::SeqCst);
+        });
+    }
+
     ctrlc::set_handler(move || {
         if cli.infinite {
             info!("Ignoring ctrl-C");
@@ -1,18 +1,141 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 
 pub fn evaluate_cli() -> Cli {
-    Cli::parse()
+    try_evaluate_cli(std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+/// Parse the arguments, including the checks between options that clap can't express.
+fn try_evaluate_cli<T: Into<std::ffi::OsString> + Clone>(
+    args: impl IntoIterator<Item = T>,
+) -> Result<Cli, clap::Error> {
+    let cli = Cli::try_parse_from(args)?;
+    let (subcommand, common) = match &cli.sub {
+        Subcommand::Add { common, .. } => ("add", common),
+        Subcommand::Run { common, .. } => ("run", common),
+        _ => return Ok(cli),
+    };
+    common.validate().map_err(|message| {
+        let mut command = Cli::command();
+        command.build();
+        command
+            .find_subcommand_mut(subcommand)
+            .expect("subcommand exists")
+            .error(clap::error::ErrorKind::ArgumentConflict, message)
+    })?;
+    Ok(cli)
 }
 
 fn parse_canonical_path(path: &str) -> Result<String, std::io::Error> {
     Ok(std::fs::canonicalize(path)?.to_string_lossy().to_string())
 }
 
+fn parse_regex(pattern: &str) -> Result<String, regex::Error> {
+    regex::Regex::new(pattern)?;
+    Ok(pattern.to_string())
+}
+
+/// Parse a duration like `30s`, `15m`, `1h`, or `2d`.
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a number followed by s, m, h, or d, but got: {}", value))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(format!("unknown unit in {} (expected s, m, h, or d)", value)),
+    };
+    number
+        .checked_mul(seconds)
+        .map(std::time::Duration::from_secs)
+        .ok_or_else(|| format!("duration is too long: {}", value))
+}
+
+/// Parse a duration that must be longer than 0, for limits that would otherwise act right away.
+fn parse_nonzero_duration(value: &str) -> Result<std::time::Duration, String> {
+    match parse_duration(value)? {
+        duration if duration.is_zero() => Err(format!("duration must be longer than 0, not: {}", value)),
+        duration => Ok(duration),
+    }
+}
+
+/// The inverse of `parse_duration`, using the largest unit that fits exactly.
+#[cfg(any(windows, test))]
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let seconds = duration.as_secs();
+    let (number, unit) = [(60 * 60 * 24, "d"), (60 * 60, "h"), (60, "m")]
+        .into_iter()
+        .find(|(unit, _)| seconds > 0 && seconds.is_multiple_of(*unit))
+        .map_or((seconds, "s"), |(unit, name)| (seconds / unit, name));
+    format!("{}{}", number, unit)
+}
+
+/// Parse a size like `512MB` or `2GB`, where KB, MB, and GB are powers of 1024.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a number followed by KB, MB, or GB, but got: {}", value))?;
+    let bytes = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown unit in {} (expected KB, MB, or GB)", value)),
+    };
+    number
+        .checked_mul(bytes)
+        .ok_or_else(|| format!("size is too large: {}", value))
+}
+
+/// Parse a size that must be larger than 0, for limits that would otherwise act right away.
+fn parse_nonzero_size(value: &str) -> Result<u64, String> {
+    match parse_size(value)? {
+        0 => Err(format!("size must be larger than 0, not: {}", value)),
+        size => Ok(size),
+    }
+}
+
+/// A CPU's index for `--cpu-affinity`, which has to fit in an affinity mask.
+fn parse_cpu(value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(cpu) if cpu < usize::BITS as usize => Ok(cpu),
+        Ok(cpu) => Err(format!("CPU {} is out of range (0 to {})", cpu, usize::BITS - 1)),
+        Err(_) => Err(format!("invalid CPU number: {}", value)),
+    }
+}
+
+fn parse_timestamp_format(format: &str) -> Result<String, String> {
+    if format != "rfc3339"
+        && chrono::format::StrftimeItems::new(format).any(|x| matches!(x, chrono::format::Item::Error))
+    {
+        return Err(format!("invalid timestamp format: {}", format));
+    }
+    Ok(format.to_string())
+}
+
 fn parse_ensured_directory(path: &str) -> Result<String, std::io::Error> {
     std::fs::create_dir_all(path)?;
     Ok(std::fs::canonicalize(path)?.to_string_lossy().to_string())
 }
 
+/// Like `parse_ensured_directory`, except that a directory with placeholders
+/// can only be created once they're filled in when the service starts.
+fn parse_log_dir(path: &str) -> Result<String, String> {
+    if !crate::template::is_template(path) {
+        return parse_ensured_directory(path).map_err(|e| e.to_string());
+    }
+    crate::template::validate(path)?;
+    if std::path::Path::new(path).is_absolute() || path.starts_with('%') {
+        Ok(path.to_string())
+    } else {
+        let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+        Ok(cwd.join(path).to_string_lossy().to_string())
+    }
+}
+
 macro_rules! possible_values {
     ($t: ty, $options: ident) => {{
         use clap::builder::{PossibleValuesParser, TypedValueParser};
@@ -53,6 +176,7 @@ impl Priority {
 }
 
 impl Priority {
+    #[cfg(any(windows, test))]
     pub fn to_cli(self) -> String {
         match self {
             Self::Realtime => "realtime",
@@ -65,6 +189,7 @@ impl Priority {
         .to_string()
     }
 
+    #[cfg(windows)]
     pub fn to_windows(self) -> windows::Win32::System::Threading::PROCESS_CREATION_FLAGS {
         match self {
             Self::Realtime => windows::Win32::System::Threading::REALTIME_PRIORITY_CLASS,
@@ -101,6 +226,7 @@ pub enum LogRotation {
 }
 
 impl LogRotation {
+    #[cfg(any(windows, test))]
     pub fn to_cli(self) -> String {
         match self {
             LogRotation::Bytes(bytes) => format!("bytes={}", bytes),
@@ -138,6 +264,514 @@ impl Default for LogRotation {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CarriageReturn {
+    #[default]
+    Keep,
+    Split,
+    Collapse,
+}
+
+impl CarriageReturn {
+    pub const ALL: &'static [&'static str] = &["keep", "split", "collapse"];
+}
+
+impl CarriageReturn {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Keep => "keep",
+            Self::Split => "split",
+            Self::Collapse => "collapse",
+        }
+        .to_string()
+    }
+}
+
+impl std::str::FromStr for CarriageReturn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "split" => Ok(Self::Split),
+            "collapse" => Ok(Self::Collapse),
+            _ => Err(format!("invalid carriage return mode: {}", s)),
+        }
+    }
+}
+
+/// Why a run of the command ended, beyond its exit code,
+/// for `--restart-on` and `--stop-on`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    /// The command reached one of its resource limits.
+    Limit,
+    /// The command ran longer than `--max-runtime`.
+    Timeout,
+}
+
+impl Condition {
+    pub const ALL: &'static [&'static str] = &["limit", "timeout"];
+}
+
+impl Condition {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Limit => "limit",
+            Self::Timeout => "timeout",
+        }
+        .to_string()
+    }
+}
+
+impl std::str::FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "limit" => Ok(Self::Limit),
+            "timeout" => Ok(Self::Timeout),
+            _ => Err(format!("invalid condition: {}", s)),
+        }
+    }
+}
+
+/// What to do when a job run is due but the previous one is still going, for `--job-overlap`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum JobOverlap {
+    /// Let the previous run finish and wait for the next time.
+    #[default]
+    Skip,
+    /// Stop the previous run and start a new one.
+    Replace,
+}
+
+impl JobOverlap {
+    pub const ALL: &'static [&'static str] = &["skip", "replace"];
+}
+
+impl JobOverlap {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Skip => "skip",
+            Self::Replace => "replace",
+        }
+        .to_string()
+    }
+}
+
+impl std::str::FromStr for JobOverlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "replace" => Ok(Self::Replace),
+            _ => Err(format!("invalid job overlap policy: {}", s)),
+        }
+    }
+}
+
+/// CPU use that's too high for too long, for `--max-cpu`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MaxCpu {
+    /// Percentage of all CPUs.
+    pub percent: u8,
+    pub window: std::time::Duration,
+}
+
+impl MaxCpu {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        format!("{}%:{}", self.percent, format_duration(self.window))
+    }
+}
+
+impl std::str::FromStr for MaxCpu {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((percent, window)) = s.split_once(':') else {
+            return Err(format!("CPU threshold must look like 90%:5m, not: {}", s));
+        };
+        let percent = match percent.trim_end_matches('%').parse::<u8>() {
+            Ok(percent) if (1..=100).contains(&percent) => percent,
+            _ => return Err(format!("CPU percentage must be from 1 to 100, not: {}", percent)),
+        };
+        let window = parse_duration(window)?;
+        if window.is_zero() {
+            return Err(format!("CPU window must be longer than 0, not: {}", s));
+        }
+        Ok(Self { percent, window })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Overflow {
+    #[default]
+    DropOldest,
+    DropNewest,
+}
+
+impl Overflow {
+    pub const ALL: &'static [&'static str] = &["drop-oldest", "drop-newest"];
+}
+
+impl Overflow {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::DropOldest => "drop-oldest",
+            Self::DropNewest => "drop-newest",
+        }
+        .to_string()
+    }
+}
+
+impl std::str::FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(Self::DropOldest),
+            "drop-newest" => Ok(Self::DropNewest),
+            _ => Err(format!("invalid overflow policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LogTimezone {
+    #[default]
+    Local,
+    Utc,
+}
+
+impl LogTimezone {
+    pub const ALL: &'static [&'static str] = &["local", "utc"];
+}
+
+impl LogTimezone {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Local => "local",
+            Self::Utc => "utc",
+        }
+        .to_string()
+    }
+}
+
+impl std::str::FromStr for LogTimezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            "utc" => Ok(Self::Utc),
+            _ => Err(format!("invalid time zone: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SyslogProtocol {
+    Udp,
+    /// TCP with octet-counting framing (RFC 6587).
+    Tcp,
+    /// TCP with each message terminated by a line feed.
+    TcpLf,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyslogTarget {
+    pub protocol: SyslogProtocol,
+    pub address: String,
+}
+
+impl SyslogTarget {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(&self) -> String {
+        let scheme = match self.protocol {
+            SyslogProtocol::Udp => "udp",
+            SyslogProtocol::Tcp => "tcp",
+            SyslogProtocol::TcpLf => "tcp-lf",
+        };
+        format!("{}://{}", scheme, self.address)
+    }
+}
+
+impl std::str::FromStr for SyslogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, address)) = s.split_once("://") else {
+            return Err(format!("Syslog target must look like udp://host:port, not: {}", s));
+        };
+        let protocol = match scheme {
+            "udp" => SyslogProtocol::Udp,
+            "tcp" => SyslogProtocol::Tcp,
+            "tcp-lf" => SyslogProtocol::TcpLf,
+            _ => return Err(format!("Unknown syslog protocol: {}", scheme)),
+        };
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Self {
+                protocol,
+                address: address.to_string(),
+            }),
+            _ => Err(format!("Syslog address must include a host and port: {}", address)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SyslogFacility {
+    Kern,
+    User,
+    Mail,
+    #[default]
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    pub const ALL: &'static [&'static str] = &[
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "local0",
+        "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+    ];
+}
+
+impl SyslogFacility {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Kern => "kern",
+            Self::User => "user",
+            Self::Mail => "mail",
+            Self::Daemon => "daemon",
+            Self::Auth => "auth",
+            Self::Syslog => "syslog",
+            Self::Lpr => "lpr",
+            Self::News => "news",
+            Self::Uucp => "uucp",
+            Self::Cron => "cron",
+            Self::Authpriv => "authpriv",
+            Self::Ftp => "ftp",
+            Self::Local0 => "local0",
+            Self::Local1 => "local1",
+            Self::Local2 => "local2",
+            Self::Local3 => "local3",
+            Self::Local4 => "local4",
+            Self::Local5 => "local5",
+            Self::Local6 => "local6",
+            Self::Local7 => "local7",
+        }
+        .to_string()
+    }
+
+    /// Numeric facility code from RFC 5424.
+    #[cfg(any(windows, test))]
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Kern => 0,
+            Self::User => 1,
+            Self::Mail => 2,
+            Self::Daemon => 3,
+            Self::Auth => 4,
+            Self::Syslog => 5,
+            Self::Lpr => 6,
+            Self::News => 7,
+            Self::Uucp => 8,
+            Self::Cron => 9,
+            Self::Authpriv => 10,
+            Self::Ftp => 11,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+impl std::str::FromStr for SyslogFacility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kern" => Ok(Self::Kern),
+            "user" => Ok(Self::User),
+            "mail" => Ok(Self::Mail),
+            "daemon" => Ok(Self::Daemon),
+            "auth" => Ok(Self::Auth),
+            "syslog" => Ok(Self::Syslog),
+            "lpr" => Ok(Self::Lpr),
+            "news" => Ok(Self::News),
+            "uucp" => Ok(Self::Uucp),
+            "cron" => Ok(Self::Cron),
+            "authpriv" => Ok(Self::Authpriv),
+            "ftp" => Ok(Self::Ftp),
+            "local0" => Ok(Self::Local0),
+            "local1" => Ok(Self::Local1),
+            "local2" => Ok(Self::Local2),
+            "local3" => Ok(Self::Local3),
+            "local4" => Ok(Self::Local4),
+            "local5" => Ok(Self::Local5),
+            "local6" => Ok(Self::Local6),
+            "local7" => Ok(Self::Local7),
+            _ => Err(format!("invalid syslog facility: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum StdinMode {
+    #[default]
+    Inherit,
+    Null,
+    File(String),
+    Pipe,
+}
+
+impl StdinMode {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(&self) -> String {
+        match self {
+            Self::Inherit => "inherit".to_string(),
+            Self::Null => "null".to_string(),
+            Self::File(path) => format!("file:{}", path),
+            Self::Pipe => "pipe".to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for StdinMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inherit" => Ok(Self::Inherit),
+            "null" => Ok(Self::Null),
+            "pipe" => Ok(Self::Pipe),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Self::File(path.to_string())),
+                _ => Err(format!("stdin must be inherit, null, file:<path>, or pipe, not: {}", s)),
+            },
+        }
+    }
+}
+
+/// When to restart the command after it exits,
+/// equivalent to `--restart`, `--no-restart`, `--restart-if`, or `--restart-if-not`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RestartPolicy {
+    pub restart: bool,
+    pub no_restart: bool,
+    pub restart_if: Vec<i32>,
+    pub restart_if_not: Vec<i32>,
+}
+
+impl RestartPolicy {
+    #[cfg(any(windows, test))]
+    pub fn from_opts(opts: &CommonOpts) -> Self {
+        Self {
+            restart: opts.restart,
+            no_restart: opts.no_restart,
+            restart_if: opts.restart_if.clone(),
+            restart_if_not: opts.restart_if_not.clone(),
+        }
+    }
+
+    pub fn to_cli(&self) -> String {
+        let codes = |codes: &[i32]| codes.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",");
+        if self.restart {
+            "always".to_string()
+        } else if self.no_restart {
+            "never".to_string()
+        } else if !self.restart_if.is_empty() {
+            format!("if={}", codes(&self.restart_if))
+        } else if !self.restart_if_not.is_empty() {
+            format!("if-not={}", codes(&self.restart_if_not))
+        } else {
+            "on-failure".to_string()
+        }
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let codes = |codes: &str| {
+            codes
+                .split(',')
+                .map(|x| x.trim().parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("invalid exit codes: {}", codes))
+        };
+        match s {
+            "always" => Ok(Self {
+                restart: true,
+                ..Default::default()
+            }),
+            "never" => Ok(Self {
+                no_restart: true,
+                ..Default::default()
+            }),
+            "on-failure" => Ok(Self::default()),
+            _ => {
+                if let Some(x) = s.strip_prefix("if=") {
+                    Ok(Self {
+                        restart_if: codes(x)?,
+                        ..Default::default()
+                    })
+                } else if let Some(x) = s.strip_prefix("if-not=") {
+                    Ok(Self {
+                        restart_if_not: codes(x)?,
+                        ..Default::default()
+                    })
+                } else {
+                    Err(format!(
+                        "restart policy must be always, never, on-failure, if=<codes>, or if-not=<codes>, not: {}",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The file has to exist already, and the service may run from a different directory.
+fn parse_stdin(value: &str) -> Result<StdinMode, String> {
+    match value.parse()? {
+        StdinMode::File(path) => Ok(StdinMode::File(
+            parse_canonical_path(&path).map_err(|e| format!("{}: {}", path, e))?,
+        )),
+        mode => Ok(mode),
+    }
+}
+
 fn parse_env_var(value: &str) -> Result<(String, String), CliError> {
     let parts: Vec<&str> = value.splitn(2, '=').collect();
     if parts.len() != 2 {
@@ -158,6 +792,18 @@ fn styles() -> clap::builder::styling::Styles {
         .placeholder(AnsiColor::Green.on_default())
 }
 
+/// Options that are about keeping the command running, so they don't make sense for jobs.
+const JOB_CONFLICTS: [&str; 8] = [
+    "restart",
+    "no_restart",
+    "restart_if",
+    "restart_if_not",
+    "restart_on",
+    "stop_on",
+    "restart_every",
+    "restart_at",
+];
+
 #[derive(clap::Parser, Clone, Debug, Default, PartialEq, Eq)]
 pub struct CommonOpts {
     /// Exit codes that should be considered successful (comma-separated) [default: 0]
@@ -214,11 +860,50 @@ pub struct CommonOpts {
     )]
     pub restart_if_not: Vec<i32>,
 
+    /// Always restart the command if its run ended this way, whatever the exit code (comma-separated).
+    /// `limit` means that it reached `--limit-memory` or `--limit-processes`,
+    /// and `timeout` means that Shawl stopped it because of `--max-runtime`
+    #[clap(
+        long,
+        value_name = "conditions",
+        value_delimiter = ',',
+        number_of_values = 1,
+        value_parser = possible_values!(Condition, ALL)
+    )]
+    pub restart_on: Vec<Condition>,
+
+    /// Never restart the command if its run ended this way, whatever the exit code (comma-separated).
+    /// This takes the same conditions as `--restart-on`
+    #[clap(
+        long,
+        value_name = "conditions",
+        value_delimiter = ',',
+        number_of_values = 1,
+        value_parser = possible_values!(Condition, ALL)
+    )]
+    pub stop_on: Vec<Condition>,
+
     /// How long to wait in milliseconds between sending the wrapped process
     /// a ctrl-C event and forcibly killing it [default: 3000]
     #[clap(long, value_name = "ms")]
     pub stop_timeout: Option<u64>,
 
+    /// Line to write to the command's stdin to ask it to stop, with `--stdin pipe`.
+    /// If it doesn't exit within `--stop-timeout`, Shawl goes on to send ctrl-C
+    #[clap(long, value_name = "text", requires = "stdin")]
+    pub stop_stdin: Option<String>,
+
+    /// Close the command's stdin to ask it to stop, with `--stdin pipe`.
+    /// This happens after writing `--stop-stdin`, if any
+    #[clap(long, requires = "stdin")]
+    pub stop_stdin_close: bool,
+
+    /// Only stop the command itself, instead of every process that it started.
+    /// Otherwise, the command runs in a job object so that any processes it leaves behind
+    /// are killed when it stops or exits
+    #[clap(long)]
+    pub no_kill_tree: bool,
+
     /// Disable all of Shawl's logging
     #[clap(long)]
     pub no_log: bool,
@@ -227,22 +912,36 @@ pub struct CommonOpts {
     #[clap(long)]
     pub no_log_cmd: bool,
 
+    /// Disable the journal of lifecycle events (`<log-as>_events.jsonl` next to the main log)
+    #[clap(long)]
+    pub no_log_events: bool,
+
+    /// How many runs of the command to remember in `history_<name>.json` in the log directory,
+    /// across restarts of the service. Use 0 to disable [default: 100]
+    #[clap(long, value_name = "count")]
+    pub history_size: Option<usize>,
+
     /// Write log file to a custom directory. This directory will be created if it doesn't exist.
-    #[clap(long, value_name = "path", value_parser = parse_ensured_directory)]
+    /// This may include environment variables like `%ProgramData%`
+    /// and the same placeholders as `--log-as`
+    #[clap(long, value_name = "path", value_parser = parse_log_dir)]
     pub log_dir: Option<String>,
 
     /// Use a different name for the main log file.
     /// Set this to just the desired base name of the log file.
     /// For example, `--log-as shawl` would result in a log file named `shawl_rCURRENT.log`
     /// instead of the normal `shawl_for_<name>_rCURRENT.log` pattern.
-    #[clap(long)]
+    /// This may include `{name}`, `{date}`, `{hostname}`, and `{pid}`,
+    /// as well as environment variables like `%COMPUTERNAME%`
+    #[clap(long, value_parser = crate::template::validate)]
     pub log_as: Option<String>,
 
     /// Use a separate log file for the wrapped command's stdout and stderr.
     /// Set this to just the desired base name of the log file.
     /// For example, `--log-cmd-as foo` would result in a log file named `foo_rCURRENT.log`.
     /// The output will be logged as-is without any additional log template.
-    #[clap(long)]
+    /// This may include the same placeholders as `--log-as`
+    #[clap(long, value_parser = crate::template::validate)]
     pub log_cmd_as: Option<String>,
 
     /// Threshold for rotating log files. Valid options:
@@ -255,17 +954,145 @@ pub struct CommonOpts {
     #[clap(long)]
     pub log_retain: Option<usize>,
 
-    /// Append the service start arguments to the command
-    #[clap(long)]
-    pub pass_start_args: bool,
+    /// Format for timestamps in the logs. Use `rfc3339` for something like
+    /// `2024-01-02T03:04:05.678+01:00`, or a strftime pattern
+    /// [default: %Y-%m-%d %H:%M:%S%.3f]
+    #[clap(long, value_name = "format", value_parser = parse_timestamp_format)]
+    pub log_timestamp: Option<String>,
 
-    /// Additional environment variable in the format 'KEY=value' (repeatable)
-    #[clap(long, number_of_values = 1, value_parser = parse_env_var)]
-    pub env: Vec<(String, String)>,
+    /// Time zone for timestamps in the logs [default: local]
+    #[clap(long, value_name = "zone", value_parser = possible_values!(LogTimezone, ALL))]
+    pub log_timezone: Option<LogTimezone>,
 
-    /// Additional directory to append to the PATH environment variable (repeatable)
-    #[clap(long, number_of_values = 1, value_parser = parse_canonical_path)]
-    pub path: Vec<String>,
+    /// Start each line in the `--log-cmd-as` file with a timestamp,
+    /// like the main log
+    #[clap(long, requires = "log_cmd_as")]
+    pub log_cmd_timestamp: bool,
+
+    /// Keep blank lines in the command's output instead of skipping them
+    #[clap(long)]
+    pub log_cmd_keep_blank: bool,
+
+    /// How to handle carriage returns in the command's output that are not part of a line ending,
+    /// such as from progress bars.
+    /// `keep` leaves them in the line,
+    /// `split` treats them as line breaks,
+    /// and `collapse` only logs the last update before each line break
+    /// [default: keep]
+    #[clap(long, value_name = "mode", value_parser = possible_values!(CarriageReturn, ALL))]
+    pub log_cmd_cr: Option<CarriageReturn>,
+
+    /// Log a partial line of the command's output
+    /// if no more output arrives within this many milliseconds
+    #[clap(long, value_name = "ms")]
+    pub log_cmd_flush: Option<u64>,
+
+    /// Maximum length in bytes of a single line of the command's output.
+    /// Longer lines are truncated and marked with how many bytes were removed
+    #[clap(long, value_name = "bytes")]
+    pub log_cmd_max_line: Option<usize>,
+
+    /// How many lines of the command's output to hold in memory while they wait to be logged.
+    /// If the command writes faster than the logs can keep up,
+    /// lines are dropped according to `--log-cmd-overflow`
+    /// [default: 10000]
+    #[clap(long, value_name = "lines")]
+    pub log_cmd_buffer: Option<usize>,
+
+    /// Which lines to drop when `--log-cmd-buffer` is full [default: drop-oldest]
+    #[clap(long, value_name = "policy", value_parser = possible_values!(Overflow, ALL))]
+    pub log_cmd_overflow: Option<Overflow>,
+
+    /// Maximum number of lines per second to log from each of the command's
+    /// stdout and stderr. Extra lines are skipped, and Shawl periodically
+    /// logs how many were suppressed
+    #[clap(long, value_name = "lines")]
+    pub log_cmd_rate: Option<u32>,
+
+    /// How many lines each stream may log in a quick burst before
+    /// `--log-cmd-rate` kicks in [default: same as --log-cmd-rate]
+    #[clap(long, value_name = "lines", requires = "log_cmd_rate")]
+    pub log_cmd_burst: Option<u32>,
+
+    /// Fold consecutive identical lines of the command's output
+    /// into a single "last line repeated N times" message
+    #[clap(long)]
+    pub log_cmd_fold: bool,
+
+    /// Regular expression for lines of the command's output that continue the previous line,
+    /// such as `^(\s|Caused by:)` for stack traces.
+    /// Each group of lines is logged as a single event
+    #[clap(long, value_name = "regex", value_parser = parse_regex)]
+    pub log_cmd_multiline: Option<String>,
+
+    /// Maximum number of lines in one `--log-cmd-multiline` event [default: 500]
+    #[clap(long, value_name = "lines", requires = "log_cmd_multiline")]
+    pub log_cmd_multiline_max: Option<usize>,
+
+    /// Log a `--log-cmd-multiline` event if no more lines arrive for it within this many milliseconds
+    /// [default: 1000]
+    #[clap(long, value_name = "ms", requires = "log_cmd_multiline")]
+    pub log_cmd_multiline_flush: Option<u64>,
+
+    /// How many of the most recent lines of output to keep from each run for a crash report,
+    /// which is logged when the command exits with a code outside of `--pass`.
+    /// Use 0 to disable crash reports
+    /// [default: 1000]
+    #[clap(long, value_name = "lines")]
+    pub crash_buffer: Option<usize>,
+
+    /// How many of the last lines of each of stdout and stderr to include in a crash report
+    /// [default: 20]
+    #[clap(long, value_name = "lines")]
+    pub crash_tail: Option<usize>,
+
+    /// Also save the whole `--crash-buffer` to `crash_<name>_<timestamp>.log` in the log directory
+    #[clap(long)]
+    pub crash_save: bool,
+
+    /// Also send the logs to a syslog collector in RFC 5424 format.
+    /// Use `udp://host:port`, `tcp://host:port` (octet-counting framing),
+    /// or `tcp-lf://host:port` (newline framing, with newlines in a message sent as `#012`)
+    #[clap(long, value_name = "target")]
+    pub syslog: Option<SyslogTarget>,
+
+    /// Facility for messages sent with `--syslog` [default: daemon]
+    #[clap(long, value_name = "facility", requires = "syslog", value_parser = possible_values!(SyslogFacility, ALL))]
+    pub syslog_facility: Option<SyslogFacility>,
+
+    /// How many messages to hold while the syslog collector is unreachable.
+    /// Beyond this, the oldest messages are dropped and counted [default: 1000]
+    #[clap(long, value_name = "count", requires = "syslog")]
+    pub syslog_buffer: Option<usize>,
+
+    /// Serve Prometheus metrics at `http://<address>/metrics`, such as `127.0.0.1:9100`.
+    /// There's no authentication, so only listen on a trusted interface
+    #[clap(long, value_name = "address")]
+    pub metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Append the service start arguments to the command
+    #[clap(long)]
+    pub pass_start_args: bool,
+
+    /// Additional environment variable in the format 'KEY=value' (repeatable)
+    #[clap(long, number_of_values = 1, value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// Additional pattern for names of environment variables whose values
+    /// should be hidden when Shawl logs its configuration (repeatable).
+    /// `*` matches any text, and matching ignores case.
+    /// Names like `*PASSWORD*`, `*SECRET*`, and `*TOKEN*` are always hidden
+    #[clap(long, value_name = "pattern", number_of_values = 1)]
+    pub redact_env: Vec<String>,
+
+    /// Regular expression for text to hide in the command's output before it is logged (repeatable).
+    /// If the expression has capture groups, then only those parts are hidden
+    #[clap(long, value_name = "regex", number_of_values = 1, value_parser = parse_regex)]
+    pub redact: Vec<String>,
+
+    /// Additional directory to append to the PATH environment variable (repeatable)
+    #[clap(long, number_of_values = 1, value_parser = parse_canonical_path)]
+    pub path: Vec<String>,
 
     /// Additional directory to prepend to the PATH environment variable (repeatable)
     #[clap(long, value_name = "path", number_of_values = 1, value_parser = parse_canonical_path)]
@@ -275,11 +1102,194 @@ pub struct CommonOpts {
     #[clap(long, value_parser = possible_values!(Priority, ALL))]
     pub priority: Option<Priority>,
 
+    /// Most memory that the command and every process it starts may commit together,
+    /// like `512MB` or `2GB`. Allocations beyond this fail, which usually ends the command
+    #[clap(long, value_name = "size", value_parser = parse_nonzero_size)]
+    pub limit_memory: Option<u64>,
+
+    /// Most CPU time that the command's processes may use together,
+    /// as a percentage of all CPUs (1 to 100)
+    #[clap(long, value_name = "percent", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub limit_cpu: Option<u8>,
+
+    /// Most processes that may run at once in the command's tree, including the command itself
+    #[clap(long, value_name = "count", value_parser = clap::value_parser!(u32).range(1..))]
+    pub limit_processes: Option<u32>,
+
+    /// Gracefully restart the command once its processes' combined working set exceeds this,
+    /// like `2GB`. Shawl checks this every second
+    #[clap(long, value_name = "size", value_parser = parse_nonzero_size)]
+    pub max_memory: Option<u64>,
+
+    /// Gracefully restart the command once its processes have used more than this percentage
+    /// of all CPUs on average over this long, like `90%:5m`
+    #[clap(long, value_name = "percent:duration")]
+    pub max_cpu: Option<MaxCpu>,
+
+    /// Gracefully restart the command once it has been running this long, like `24h`
+    #[clap(long, value_name = "duration", value_parser = parse_nonzero_duration)]
+    pub restart_every: Option<std::time::Duration>,
+
+    /// Gracefully restart the command on a schedule in local time, written like a cron job,
+    /// such as `"0 3 * * *"` for every day at 3 AM.
+    /// The fields are minute, hour, day of month, month, and day of week (0 or 7 is Sunday),
+    /// each of which can be `*`, a number, a range like `1-5`, a step like `*/15`, or a list of those
+    #[clap(long, value_name = "schedule")]
+    pub restart_at: Option<crate::schedule::Cron>,
+
+    /// Stop the command if a single run is still going after this long, like `2h`,
+    /// and count it as a timeout. It's restarted unless `--no-restart` or `--restart-if` is used,
+    /// or `--restart-on timeout` or `--stop-on timeout` decides instead.
+    /// Jobs wait for their next run
+    #[clap(long, value_name = "duration", value_parser = parse_nonzero_duration)]
+    pub max_runtime: Option<std::time::Duration>,
+
+    /// Run the command as a job every so often, like `15m`, instead of keeping it running.
+    /// Shawl stays running between runs, and it doesn't restart the command when it exits.
+    /// The first run starts right away
+    #[clap(
+        long,
+        value_name = "duration",
+        value_parser = parse_nonzero_duration,
+        group = "job",
+        conflicts_with_all = JOB_CONFLICTS
+    )]
+    pub job_every: Option<std::time::Duration>,
+
+    /// Run the command as a job on a schedule in local time, like `"*/5 * * * *"`,
+    /// instead of keeping it running. This takes the same format as `--restart-at`
+    #[clap(long, value_name = "schedule", group = "job", conflicts_with_all = JOB_CONFLICTS)]
+    pub job_at: Option<crate::schedule::Cron>,
+
+    /// What to do when a job run is due but the previous one is still going.
+    /// Runs never overlap: `skip` lets the previous one finish,
+    /// while `replace` stops it and starts a new one
+    /// [default: skip]
+    #[clap(long, value_name = "policy", requires = "job", value_parser = possible_values!(JobOverlap, ALL))]
+    pub job_overlap: Option<JobOverlap>,
+
+    /// Only run the command's processes on these CPUs (comma-separated, starting from 0)
+    #[clap(long, value_name = "cpus", value_delimiter = ',', number_of_values = 1, value_parser = parse_cpu)]
+    pub cpu_affinity: Vec<usize>,
+
+    /// Where the command's stdin comes from.
+    /// `inherit` shares Shawl's own (a console when running as a service),
+    /// `null` gives it no input,
+    /// `file:<path>` reads from a file,
+    /// and `pipe` keeps a pipe open for the whole run,
+    /// so the command never sees the end of its input
+    /// [default: inherit]
+    #[clap(long, value_name = "mode", value_parser = parse_stdin)]
+    pub stdin: Option<StdinMode>,
+
+    /// Line to write to the command's stdin after launching it, with `--stdin pipe` (repeatable)
+    #[clap(long, value_name = "text", number_of_values = 1, requires = "stdin")]
+    pub stdin_line: Vec<String>,
+
     /// Command to run as a service
     #[clap(required(true), last(true))]
     pub command: Vec<String>,
 }
 
+#[derive(clap::Parser, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogsOpts {
+    /// Name of the service whose logs to show
+    #[clap(long, default_value = "Shawl")]
+    pub name: String,
+
+    /// Directory containing the logs, if the service uses `--log-dir`
+    #[clap(long, value_name = "path")]
+    pub log_dir: Option<String>,
+
+    /// Base name of the main log files, if the service uses `--log-as`
+    #[clap(long, value_name = "name")]
+    pub log_as: Option<String>,
+
+    /// Base name of the command's log files, if the service uses `--log-cmd-as`
+    #[clap(long, value_name = "name")]
+    pub log_cmd_as: Option<String>,
+
+    /// Format of the timestamps in the logs, if the service uses `--log-timestamp`
+    #[clap(long, value_name = "format", value_parser = parse_timestamp_format)]
+    pub log_timestamp: Option<String>,
+
+    /// Time zone of the timestamps in the logs, if the service uses `--log-timezone`
+    #[clap(long, value_name = "zone", value_parser = possible_values!(LogTimezone, ALL))]
+    pub log_timezone: Option<LogTimezone>,
+
+    /// Only show the command's output, not Shawl's own messages
+    #[clap(long)]
+    pub cmd: bool,
+
+    /// Only show lines from this far back, such as `30s`, `15m`, `1h`, or `2d`
+    #[clap(long, value_name = "duration", value_parser = parse_duration)]
+    pub since: Option<std::time::Duration>,
+
+    /// Only show lines matching this regular expression
+    #[clap(long, value_name = "regex", value_parser = parse_regex)]
+    pub grep: Option<String>,
+
+    /// Keep watching for new lines, including across log rotations
+    #[clap(long, short)]
+    pub follow: bool,
+}
+
+#[derive(clap::Parser, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistoryOpts {
+    /// Name of the service whose history to show
+    #[clap(long, default_value = "Shawl")]
+    pub name: String,
+
+    /// Directory containing the history, if the service uses `--log-dir`
+    #[clap(long, value_name = "path")]
+    pub log_dir: Option<String>,
+
+    /// Print the history as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(clap::Parser, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatusOpts {
+    /// Name of the service to check
+    #[clap(long, default_value = "Shawl")]
+    pub name: String,
+
+    /// Print the status as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(clap::Subcommand, Clone, Debug, PartialEq, Eq)]
+pub enum CtlCommand {
+    /// Show what the service is doing, as JSON
+    Status,
+    /// Stop the command if it's running, then launch it again
+    RestartChild,
+    /// Stop the command, but leave the service running until `start-child`
+    StopChild,
+    /// Launch the command again after `stop-child`
+    StartChild,
+    /// Start new log files now
+    RotateLogs,
+    /// Change when to restart the command after it exits, until the service stops
+    SetRestartPolicy {
+        /// `always`, `never`, `on-failure` (any nonzero exit code),
+        /// `if=<codes>`, or `if-not=<codes>` (comma-separated)
+        policy: RestartPolicy,
+    },
+}
+
+#[derive(clap::Parser, Clone, Debug, PartialEq, Eq)]
+pub struct CtlOpts {
+    /// Name of the service to control
+    #[clap(long, default_value = "Shawl")]
+    pub name: String,
+
+    #[clap(subcommand)]
+    pub command: CtlCommand,
+}
+
 #[derive(clap::Subcommand, Clone, Debug, PartialEq, Eq)]
 pub enum Subcommand {
     #[clap(about = "Add a new service")]
@@ -313,6 +1323,26 @@ pub enum Subcommand {
         #[clap(long, default_value = "Shawl")]
         name: String,
     },
+    #[clap(about = "Show the logs of a service, including rotated files")]
+    Logs {
+        #[clap(flatten)]
+        opts: LogsOpts,
+    },
+    #[clap(about = "Show what a running service is doing, including recent exits of the command")]
+    Status {
+        #[clap(flatten)]
+        opts: StatusOpts,
+    },
+    #[clap(about = "Show how past runs of a service's command ended, oldest first")]
+    History {
+        #[clap(flatten)]
+        opts: HistoryOpts,
+    },
+    #[clap(about = "Send a command to a running service")]
+    Ctl {
+        #[clap(flatten)]
+        opts: CtlOpts,
+    },
 }
 
 #[derive(clap::Parser, Clone, Debug, PartialEq, Eq)]
@@ -330,17 +1360,30 @@ pub struct Cli {
     pub sub: Subcommand,
 }
 
+impl CommonOpts {
+    /// Check how options fit together where clap can't express it.
+    fn validate(&self) -> Result<(), String> {
+        if self.stdin != Some(StdinMode::Pipe) && !self.stdin_line.is_empty() {
+            return Err("--stdin-line only works with --stdin pipe".to_string());
+        }
+        if self.stdin != Some(StdinMode::Pipe) && (self.stop_stdin.is_some() || self.stop_stdin_close) {
+            return Err("--stop-stdin and --stop-stdin-close only work with --stdin pipe".to_string());
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 speculate::speculate! {
     fn check_args(args: &[&str], expected: Cli) {
         assert_eq!(
             expected,
-            Cli::parse_from(args)
+            try_evaluate_cli(args).unwrap()
         );
     }
 
     fn check_args_err(args: &[&str], error: clap::error::ErrorKind) {
-        let result = Cli::try_parse_from(args);
+        let result = try_evaluate_cli(args);
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), error);
     }
@@ -350,7 +1393,7 @@ speculate::speculate! {
     }
 
     fn p(path: &str) -> String {
-        std::fs::canonicalize(&path).unwrap().to_string_lossy().to_string()
+        std::fs::canonicalize(path).unwrap().to_string_lossy().to_string()
     }
 
     describe "run subcommand" {
@@ -724,16 +1767,624 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --restart-if-not" {
+        it "accepts --restart-if-not" {
+            check_args(
+                &["shawl", "add", "--restart-if-not", "1,2", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        common: CommonOpts {
+                            restart_if_not: vec![1, 2],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stop-timeout" {
+            check_args(
+                &["shawl", "add", "--stop-timeout", "500", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        common: CommonOpts {
+                            stop_timeout: Some(500),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --no-log" {
+            check_args(
+                &["shawl", "run", "--no-log", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            no_log: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --no-log-cmd" {
+            check_args(
+                &["shawl", "run", "--no-log-cmd", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            no_log_cmd: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --no-log-events" {
+            check_args(
+                &["shawl", "run", "--no-log-events", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            no_log_events: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --history-size" {
+            check_args(
+                &["shawl", "run", "--history-size", "20", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            history_size: Some(20),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-as" {
+            check_args(
+                &["shawl", "run", "--log-as", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_as: Some("foo".to_string()),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-as" {
+            check_args(
+                &["shawl", "run", "--log-cmd-as", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_as: Some("foo".to_string()),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-rotate bytes=n" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "bytes=123", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::Bytes(123)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-rotate daily" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "daily", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::Daily),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-rotate hourly" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "hourly", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::Hourly),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-retain" {
+            check_args(
+                &["shawl", "run", "--log-retain", "5", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_retain: Some(5),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-keep-blank" {
+            check_args(
+                &["shawl", "run", "--log-cmd-keep-blank", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_keep_blank: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-cr" {
+            check_args(
+                &["shawl", "run", "--log-cmd-cr", "collapse", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_cr: Some(CarriageReturn::Collapse),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --log-cmd-cr with an invalid mode" {
+            check_args_err(
+                &["shawl", "run", "--log-cmd-cr", "foo", "--", "foo"],
+                clap::error::ErrorKind::InvalidValue,
+            );
+        }
+
+        it "accepts --log-cmd-flush" {
+            check_args(
+                &["shawl", "run", "--log-cmd-flush", "500", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_flush: Some(500),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-max-line" {
+            check_args(
+                &["shawl", "run", "--log-cmd-max-line", "4096", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_max_line: Some(4096),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-dir" {
+            let path = env!("CARGO_MANIFEST_DIR");
+            check_args(
+                &["shawl", "run", "--log-dir", path, "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_dir: Some(p(path)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts timestamp options" {
+            check_args(
+                &[
+                    "shawl", "run", "--log-timestamp", "rfc3339", "--log-timezone", "utc", "--log-cmd-as", "cmd",
+                    "--log-cmd-timestamp", "--", "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_timestamp: Some(s("rfc3339")),
+                            log_timezone: Some(LogTimezone::Utc),
+                            log_cmd_as: Some(s("cmd")),
+                            log_cmd_timestamp: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts a strftime pattern for --log-timestamp" {
+            check_args(
+                &["shawl", "run", "--log-timestamp", "%d/%m/%Y %H:%M:%S%.6f", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_timestamp: Some(s("%d/%m/%Y %H:%M:%S%.6f")),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects an invalid --log-timestamp" {
+            check_args_err(
+                &["shawl", "run", "--log-timestamp", "%Q", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "rejects --log-cmd-timestamp without --log-cmd-as" {
+            check_args_err(
+                &["shawl", "run", "--log-cmd-timestamp", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts placeholders in log names and directories" {
+            check_args(
+                &[
+                    "shawl", "run", "--log-dir", "%ProgramData%/shawl/{name}", "--log-as", "{name}_{hostname}",
+                    "--log-cmd-as", "{name}_cmd_{pid}", "--", "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_dir: Some(s("%ProgramData%/shawl/{name}")),
+                            log_as: Some(s("{name}_{hostname}")),
+                            log_cmd_as: Some(s("{name}_cmd_{pid}")),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects unknown placeholders in log names" {
+            check_args_err(
+                &["shawl", "run", "--log-as", "{user}", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --pass-start-args" {
+            check_args(
+                &["shawl", "run", "--pass-start-args", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            pass_start_args: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stdin" {
+            let path = format!("{}/Cargo.toml", env!("CARGO_MANIFEST_DIR"));
+            for (value, mode) in [
+                (s("inherit"), StdinMode::Inherit),
+                (s("null"), StdinMode::Null),
+                (s("pipe"), StdinMode::Pipe),
+                (format!("file:{}", path), StdinMode::File(p(&path))),
+            ] {
+                check_args(
+                    &["shawl", "run", "--stdin", &value, "--", "foo"],
+                    Cli {
+                        sub: Subcommand::Run {
+                            name: s("Shawl"),
+                            cwd: None,
+                            common: CommonOpts {
+                                stdin: Some(mode),
+                                command: vec![s("foo")],
+                                ..Default::default()
+                            }
+                        }
+                    },
+                );
+            }
+        }
+
+        it "rejects an invalid --stdin" {
+            for value in ["console", "file:", "file:does-not-exist.txt"] {
+                check_args_err(
+                    &["shawl", "run", "--stdin", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --stdin-line multiple times" {
+            check_args(
+                &["shawl", "run", "--stdin", "pipe", "--stdin-line", "yes", "--stdin-line", "", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            stdin: Some(StdinMode::Pipe),
+                            stdin_line: vec![s("yes"), s("")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --stdin-line without --stdin" {
+            check_args_err(
+                &["shawl", "run", "--stdin-line", "yes", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "rejects --stdin-line without --stdin pipe" {
+            for mode in ["inherit", "null", "file:Cargo.toml"] {
+                check_args_err(
+                    &["shawl", "run", "--stdin", mode, "--stdin-line", "yes", "--", "foo"],
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+            }
+        }
+
+        it "accepts stdin stop options" {
+            check_args(
+                &["shawl", "run", "--stdin", "pipe", "--stop-stdin", "stop", "--stop-stdin-close", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            stdin: Some(StdinMode::Pipe),
+                            stop_stdin: Some(s("stop")),
+                            stop_stdin_close: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects stdin stop options without --stdin pipe" {
+            for mode in ["inherit", "null", "file:Cargo.toml"] {
+                check_args_err(
+                    &["shawl", "run", "--stdin", mode, "--stop-stdin", "quit", "--", "foo"],
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+                check_args_err(
+                    &["shawl", "add", "--name", "foo", "--stdin", mode, "--stop-stdin-close", "--", "foo"],
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+            }
+        }
+
+        it "accepts --no-kill-tree" {
+            check_args(
+                &["shawl", "run", "--no-kill-tree", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            no_kill_tree: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --stop-stdin without --stdin" {
+            check_args_err(
+                &["shawl", "run", "--stop-stdin", "stop", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --env" {
+            check_args(
+                &["shawl", "add", "--env", "FOO=bar", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        common: CommonOpts {
+                            env: vec![(s("FOO"), s("bar"))],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --env multiple times" {
+            check_args(
+                &["shawl", "add", "--env", "FOO=1", "--env", "BAR=2", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        common: CommonOpts {
+                            env: vec![(s("FOO"), s("1")), (s("BAR"), s("2"))],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-buffer and --log-cmd-overflow" {
+            check_args(
+                &["shawl", "run", "--log-cmd-buffer", "500", "--log-cmd-overflow", "drop-newest", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_buffer: Some(500),
+                            log_cmd_overflow: Some(Overflow::DropNewest),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-rate, --log-cmd-burst, and --log-cmd-fold" {
+            check_args(
+                &["shawl", "run", "--log-cmd-rate", "100", "--log-cmd-burst", "500", "--log-cmd-fold", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        common: CommonOpts {
+                            log_cmd_rate: Some(100),
+                            log_cmd_burst: Some(500),
+                            log_cmd_fold: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-multiline options" {
             check_args(
-                &["shawl", "add", "--restart-if-not", "1,2", "--name", "foo", "--", "foo"],
+                &[
+                    "shawl",
+                    "run",
+                    "--log-cmd-multiline",
+                    r"^(\s|Caused by:)",
+                    "--log-cmd-multiline-max",
+                    "50",
+                    "--log-cmd-multiline-flush",
+                    "200",
+                    "--",
+                    "foo",
+                ],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
                         common: CommonOpts {
-                            restart_if_not: vec![1, 2],
+                            log_cmd_multiline: Some(s(r"^(\s|Caused by:)")),
+                            log_cmd_multiline_max: Some(50),
+                            log_cmd_multiline_flush: Some(200),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -742,16 +2393,27 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --stop-timeout" {
+        it "accepts crash report options" {
             check_args(
-                &["shawl", "add", "--stop-timeout", "500", "--name", "foo", "--", "foo"],
+                &[
+                    "shawl",
+                    "run",
+                    "--crash-buffer",
+                    "500",
+                    "--crash-tail",
+                    "10",
+                    "--crash-save",
+                    "--",
+                    "foo",
+                ],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
                         common: CommonOpts {
-                            stop_timeout: Some(500),
+                            crash_buffer: Some(500),
+                            crash_tail: Some(10),
+                            crash_save: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -760,15 +2422,72 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --no-log" {
+        it "rejects an invalid --log-cmd-multiline" {
+            check_args_err(
+                &["shawl", "run", "--log-cmd-multiline", "(", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "rejects --log-cmd-burst without --log-cmd-rate" {
+            check_args_err(
+                &["shawl", "run", "--log-cmd-burst", "500", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --syslog" {
+            for (value, protocol) in [
+                ("udp://127.0.0.1:514", SyslogProtocol::Udp),
+                ("tcp://logs.example.com:601", SyslogProtocol::Tcp),
+                ("tcp-lf://[::1]:601", SyslogProtocol::TcpLf),
+            ] {
+                check_args(
+                    &["shawl", "run", "--syslog", value, "--", "foo"],
+                    Cli {
+                        sub: Subcommand::Run {
+                            name: s("Shawl"),
+                            cwd: None,
+                            common: CommonOpts {
+                                syslog: Some(SyslogTarget {
+                                    protocol,
+                                    address: s(value.split_once("://").unwrap().1),
+                                }),
+                                command: vec![s("foo")],
+                                ..Default::default()
+                            }
+                        }
+                    },
+                );
+            }
+        }
+
+        it "rejects --syslog with an invalid target" {
+            for value in ["127.0.0.1:514", "http://127.0.0.1:514", "udp://127.0.0.1", "udp://:514"] {
+                check_args_err(
+                    &["shawl", "run", "--syslog", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --syslog-facility and --syslog-buffer" {
             check_args(
-                &["shawl", "run", "--no-log", "--", "foo"],
+                &[
+                    "shawl", "run", "--syslog", "udp://127.0.0.1:514", "--syslog-facility", "local3",
+                    "--syslog-buffer", "50", "--", "foo",
+                ],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            no_log: true,
+                            syslog: Some(SyslogTarget {
+                                protocol: SyslogProtocol::Udp,
+                                address: s("127.0.0.1:514"),
+                            }),
+                            syslog_facility: Some(SyslogFacility::Local3),
+                            syslog_buffer: Some(50),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -777,15 +2496,22 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --no-log-cmd" {
+        it "rejects --syslog-facility without --syslog" {
+            check_args_err(
+                &["shawl", "run", "--syslog-facility", "local3", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --metrics-listen" {
             check_args(
-                &["shawl", "run", "--no-log-cmd", "--", "foo"],
+                &["shawl", "run", "--metrics-listen", "127.0.0.1:9100", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            no_log_cmd: true,
+                            metrics_listen: Some("127.0.0.1:9100".parse().unwrap()),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -794,15 +2520,38 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-as" {
+        it "rejects --metrics-listen without a port" {
+            check_args_err(
+                &["shawl", "run", "--metrics-listen", "127.0.0.1", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts resource limits" {
             check_args(
-                &["shawl", "run", "--log-as", "foo", "--", "foo"],
+                &[
+                    "shawl",
+                    "run",
+                    "--limit-memory",
+                    "512MB",
+                    "--limit-cpu",
+                    "25",
+                    "--limit-processes",
+                    "10",
+                    "--cpu-affinity",
+                    "0,2",
+                    "--",
+                    "foo",
+                ],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_as: Some("foo".to_string()),
+                            limit_memory: Some(512 * 1024 * 1024),
+                            limit_cpu: Some(25),
+                            limit_processes: Some(10),
+                            cpu_affinity: vec![0, 2],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -811,32 +2560,48 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-cmd-as" {
+        it "rejects invalid resource limits" {
+            for args in [
+                ["--limit-memory", "2TB"],
+                ["--limit-memory", "GB"],
+                ["--limit-memory", "0"],
+                ["--limit-memory", "0MB"],
+                ["--limit-cpu", "0"],
+                ["--limit-cpu", "101"],
+                ["--limit-processes", "0"],
+                ["--cpu-affinity", "64"],
+            ] {
+                check_args_err(
+                    &["shawl", "run", args[0], args[1], "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --restart-on and --stop-on" {
             check_args(
-                &["shawl", "run", "--log-cmd-as", "foo", "--", "foo"],
+                &["shawl", "run", "--restart-on", "limit", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_cmd_as: Some("foo".to_string()),
+                            restart_on: vec![Condition::Limit],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
                     }
                 },
             );
-        }
-
-        it "accepts --log-rotate bytes=n" {
             check_args(
-                &["shawl", "run", "--log-rotate", "bytes=123", "--", "foo"],
+                &["shawl", "run", "--no-restart", "--stop-on", "limit", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_rotate: Some(LogRotation::Bytes(123)),
+                            no_restart: true,
+                            stop_on: vec![Condition::Limit],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -845,15 +2610,25 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-rotate daily" {
+        it "accepts sizes for --limit-memory" {
+            for (value, bytes) in [("4096", 4096), ("64KB", 65_536), ("512mb", 536_870_912), ("2GB", 2_147_483_648)] {
+                assert_eq!(parse_size(value), Ok(bytes));
+            }
+        }
+
+        it "accepts --max-memory and --max-cpu" {
             check_args(
-                &["shawl", "run", "--log-rotate", "daily", "--", "foo"],
+                &["shawl", "run", "--max-memory", "2GB", "--max-cpu", "90%:5m", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_rotate: Some(LogRotation::Daily),
+                            max_memory: Some(2 * 1024 * 1024 * 1024),
+                            max_cpu: Some(MaxCpu {
+                                percent: 90,
+                                window: std::time::Duration::from_secs(300),
+                            }),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -862,15 +2637,25 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-rotate hourly" {
+        it "rejects a --max-memory of 0" {
+            for value in ["0", "0MB"] {
+                check_args_err(
+                    &["shawl", "run", "--max-memory", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --restart-every and --restart-at" {
             check_args(
-                &["shawl", "run", "--log-rotate", "hourly", "--", "foo"],
+                &["shawl", "run", "--restart-every", "24h", "--restart-at", "0 3 * * *", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_rotate: Some(LogRotation::Hourly),
+                            restart_every: Some(std::time::Duration::from_secs(24 * 60 * 60)),
+                            restart_at: Some("0 3 * * *".parse().unwrap()),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -879,33 +2664,49 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-retain" {
+        it "rejects invalid schedules for --restart-at" {
+            check_args_err(
+                &["shawl", "run", "--restart-at", "0 3 * *", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts job options" {
             check_args(
-                &["shawl", "run", "--log-retain", "5", "--", "foo"],
+                &[
+                    "shawl",
+                    "run",
+                    "--job-every",
+                    "15m",
+                    "--job-overlap",
+                    "replace",
+                    "--max-runtime",
+                    "10m",
+                    "--",
+                    "foo",
+                ],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_retain: Some(5),
+                            job_every: Some(std::time::Duration::from_secs(15 * 60)),
+                            job_overlap: Some(JobOverlap::Replace),
+                            max_runtime: Some(std::time::Duration::from_secs(10 * 60)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
                     }
                 },
             );
-        }
-
-        it "accepts --log-dir" {
-            let path = env!("CARGO_MANIFEST_DIR");
             check_args(
-                &["shawl", "run", "--log-dir", path, "--", "foo"],
+                &["shawl", "run", "--job-at", "*/5 * * * *", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            log_dir: Some(p(path)),
+                            job_at: Some("*/5 * * * *".parse().unwrap()),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -914,15 +2715,53 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --pass-start-args" {
+        it "rejects --job-every with --job-at" {
+            check_args_err(
+                &["shawl", "run", "--job-every", "15m", "--job-at", "0 3 * * *", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "rejects a --job-every of 0" {
+            for value in ["0s", "0m"] {
+                check_args_err(
+                    &["shawl", "run", "--job-every", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "rejects job options with restart options" {
+            for args in [
+                &["--restart"][..],
+                &["--restart-if", "1"],
+                &["--restart-every", "1h"],
+                &["--stop-on", "limit"],
+            ] {
+                let mut all = vec!["shawl", "run", "--job-every", "15m"];
+                all.extend(args);
+                all.extend(["--", "foo"]);
+                check_args_err(&all, clap::error::ErrorKind::ArgumentConflict);
+            }
+        }
+
+        it "rejects --job-overlap without a job" {
+            check_args_err(
+                &["shawl", "run", "--job-overlap", "skip", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --max-runtime without a job" {
             check_args(
-                &["shawl", "run", "--pass-start-args", "--", "foo"],
+                &["shawl", "run", "--max-runtime", "2h", "--stop-on", "timeout", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
                         common: CommonOpts {
-                            pass_start_args: true,
+                            max_runtime: Some(std::time::Duration::from_secs(2 * 60 * 60)),
+                            stop_on: vec![Condition::Timeout],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -931,16 +2770,56 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --env" {
+        it "rejects a --max-runtime of 0" {
+            for value in ["0s", "0m"] {
+                check_args_err(
+                    &["shawl", "run", "--max-runtime", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "rejects a --restart-every of 0" {
+            for value in ["0s", "0h"] {
+                check_args_err(
+                    &["shawl", "run", "--restart-every", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "rejects invalid values for --max-cpu" {
+            for value in ["90%", "0%:5m", "101%:5m", "90%:0s", "90%:5", "x:5m"] {
+                check_args_err(
+                    &["shawl", "run", "--max-cpu", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "formats durations for forwarding" {
+            for (seconds, value) in [(0, "0s"), (45, "45s"), (300, "5m"), (5400, "90m"), (7200, "2h"), (172_800, "2d")] {
+                assert_eq!(format_duration(std::time::Duration::from_secs(seconds)), value);
+                assert_eq!(parse_duration(value), Ok(std::time::Duration::from_secs(seconds)));
+            }
+        }
+
+        it "rejects unknown conditions for --restart-on" {
+            check_args_err(
+                &["shawl", "run", "--restart-on", "oops", "--", "foo"],
+                clap::error::ErrorKind::InvalidValue,
+            );
+        }
+
+        it "accepts --redact-env" {
             check_args(
-                &["shawl", "add", "--env", "FOO=bar", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--redact-env", "LICENSE_*", "--redact-env", "SERIAL", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
                         common: CommonOpts {
-                            env: vec![(s("FOO"), s("bar"))],
+                            redact_env: vec![s("LICENSE_*"), s("SERIAL")],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -949,16 +2828,15 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --env multiple times" {
+        it "accepts --redact" {
             check_args(
-                &["shawl", "add", "--env", "FOO=1", "--env", "BAR=2", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--redact", r"password=(\S+)", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
                         common: CommonOpts {
-                            env: vec![(s("FOO"), s("1")), (s("BAR"), s("2"))],
+                            redact: vec![s(r"password=(\S+)")],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -967,6 +2845,13 @@ speculate::speculate! {
             );
         }
 
+        it "rejects --redact with an invalid expression" {
+            check_args_err(
+                &["shawl", "run", "--redact", "(", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
         it "accepts --path" {
             let path = env!("CARGO_MANIFEST_DIR");
             check_args(
@@ -1045,4 +2930,203 @@ speculate::speculate! {
             );
         }
     }
+
+    describe "logs subcommand" {
+        it "works with minimal arguments" {
+            check_args(
+                &["shawl", "logs"],
+                Cli {
+                    sub: Subcommand::Logs {
+                        opts: LogsOpts {
+                            name: s("Shawl"),
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts all options" {
+            check_args(
+                &[
+                    "shawl", "logs", "--name", "foo", "--log-dir", "logs", "--log-as", "main", "--log-cmd-as", "cmd",
+                    "--log-timestamp", "rfc3339", "--log-timezone", "utc", "--cmd", "--since", "90m", "--grep", "error",
+                    "--follow",
+                ],
+                Cli {
+                    sub: Subcommand::Logs {
+                        opts: LogsOpts {
+                            name: s("foo"),
+                            log_dir: Some(s("logs")),
+                            log_as: Some(s("main")),
+                            log_cmd_as: Some(s("cmd")),
+                            log_timestamp: Some(s("rfc3339")),
+                            log_timezone: Some(LogTimezone::Utc),
+                            cmd: true,
+                            since: Some(std::time::Duration::from_secs(90 * 60)),
+                            grep: Some(s("error")),
+                            follow: true,
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts durations for --since" {
+            for (value, seconds) in [("30s", 30), ("15m", 900), ("1h", 3600), ("2d", 172_800)] {
+                assert_eq!(parse_duration(value), Ok(std::time::Duration::from_secs(seconds)));
+            }
+        }
+
+        it "rejects invalid durations for --since" {
+            for value in ["", "1", "h", "1w", "-1h", "1.5h", "99999999999999999999s", "99999999999999999d"] {
+                assert!(parse_duration(value).is_err());
+            }
+        }
+    }
+
+    describe "status subcommand" {
+        it "works with minimal arguments" {
+            check_args(
+                &["shawl", "status"],
+                Cli {
+                    sub: Subcommand::Status {
+                        opts: StatusOpts {
+                            name: s("Shawl"),
+                            json: false,
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts all options" {
+            check_args(
+                &["shawl", "status", "--name", "foo", "--json"],
+                Cli {
+                    sub: Subcommand::Status {
+                        opts: StatusOpts {
+                            name: s("foo"),
+                            json: true,
+                        }
+                    }
+                },
+            );
+        }
+    }
+
+    describe "history subcommand" {
+        it "works with minimal arguments" {
+            check_args(
+                &["shawl", "history"],
+                Cli {
+                    sub: Subcommand::History {
+                        opts: HistoryOpts {
+                            name: s("Shawl"),
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts all options" {
+            check_args(
+                &["shawl", "history", "--name", "foo", "--log-dir", "logs", "--json"],
+                Cli {
+                    sub: Subcommand::History {
+                        opts: HistoryOpts {
+                            name: s("foo"),
+                            log_dir: Some(s("logs")),
+                            json: true,
+                        }
+                    }
+                },
+            );
+        }
+    }
+
+    describe "ctl subcommand" {
+        it "defaults to the Shawl service" {
+            check_args(
+                &["shawl", "ctl", "status"],
+                Cli {
+                    sub: Subcommand::Ctl {
+                        opts: CtlOpts {
+                            name: s("Shawl"),
+                            command: CtlCommand::Status,
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts each command" {
+            for (command, expected) in [
+                ("restart-child", CtlCommand::RestartChild),
+                ("stop-child", CtlCommand::StopChild),
+                ("start-child", CtlCommand::StartChild),
+                ("rotate-logs", CtlCommand::RotateLogs),
+            ] {
+                check_args(
+                    &["shawl", "ctl", "--name", "foo", command],
+                    Cli {
+                        sub: Subcommand::Ctl {
+                            opts: CtlOpts {
+                                name: s("foo"),
+                                command: expected,
+                            }
+                        }
+                    },
+                );
+            }
+        }
+
+        it "accepts set-restart-policy" {
+            check_args(
+                &["shawl", "ctl", "--name", "foo", "set-restart-policy", "if=1,-2"],
+                Cli {
+                    sub: Subcommand::Ctl {
+                        opts: CtlOpts {
+                            name: s("foo"),
+                            command: CtlCommand::SetRestartPolicy {
+                                policy: RestartPolicy {
+                                    restart_if: vec![1, -2],
+                                    ..Default::default()
+                                },
+                            },
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects an invalid restart policy" {
+            check_args_err(
+                &["shawl", "ctl", "set-restart-policy", "sometimes"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+    }
+
+    describe "restart_policy" {
+        it "round trips through the CLI format" {
+            for value in ["always", "never", "on-failure", "if=1,2", "if-not=0,-1"] {
+                assert_eq!(value.parse::<RestartPolicy>().unwrap().to_cli(), value);
+            }
+        }
+
+        it "matches the restart options" {
+            let opts = CommonOpts {
+                restart_if_not: vec![0, 3],
+                ..Default::default()
+            };
+            assert_eq!(RestartPolicy::from_opts(&opts), "if-not=0,3".parse().unwrap());
+        }
+
+        it "rejects invalid codes" {
+            assert!("if=1,x".parse::<RestartPolicy>().is_err());
+            assert!("if=".parse::<RestartPolicy>().is_err());
+        }
+    }
 }
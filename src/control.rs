@@ -1,8 +1,23 @@
 use crate::cli::CommonOpts;
+#[cfg(windows)]
 use log::error;
+#[cfg(windows)]
 use std::io::Write;
 
+#[cfg(windows)]
 pub fn add_service(name: String, cwd: Option<String>, dependencies: &[String], opts: CommonOpts) -> Result<(), ()> {
+    // Catch mistakes like a missing environment variable now, rather than when the service starts.
+    if let Err(e) = crate::logs::LogNames::resolve(
+        &name,
+        opts.log_dir.as_deref(),
+        opts.log_as.as_deref(),
+        opts.log_cmd_as.as_deref(),
+        &crate::template::Vars::current(&name),
+    ) {
+        error!("Invalid log location: {}", e);
+        return Err(());
+    }
+
     let shawl_path = quote(
         &std::env::current_exe()
             .expect("Unable to determine Shawl location")
@@ -52,6 +67,16 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push("--stop-timeout".to_string());
         shawl_args.push(st.to_string());
     }
+    if let Some(stop_stdin) = &opts.stop_stdin {
+        shawl_args.push("--stop-stdin".to_string());
+        shawl_args.push(quote(stop_stdin));
+    }
+    if opts.stop_stdin_close {
+        shawl_args.push("--stop-stdin-close".to_string());
+    }
+    if opts.no_kill_tree {
+        shawl_args.push("--no-kill-tree".to_string());
+    }
     if opts.restart {
         shawl_args.push("--restart".to_string());
     }
@@ -78,6 +103,16 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
                 .join(","),
         );
     };
+    let conditions =
+        |conditions: &[crate::cli::Condition]| conditions.iter().map(|x| x.to_cli()).collect::<Vec<_>>().join(",");
+    if !opts.restart_on.is_empty() {
+        shawl_args.push("--restart-on".to_string());
+        shawl_args.push(conditions(&opts.restart_on));
+    }
+    if !opts.stop_on.is_empty() {
+        shawl_args.push("--stop-on".to_string());
+        shawl_args.push(conditions(&opts.stop_on));
+    }
     if let Some(pass) = &opts.pass {
         shawl_args.push("--pass".to_string());
         shawl_args.push(pass.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(","));
@@ -92,6 +127,13 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
     if opts.no_log_cmd {
         shawl_args.push("--no-log-cmd".to_string());
     }
+    if opts.no_log_events {
+        shawl_args.push("--no-log-events".to_string());
+    }
+    if let Some(history_size) = opts.history_size {
+        shawl_args.push("--history-size".to_string());
+        shawl_args.push(history_size.to_string());
+    }
     if let Some(log_dir) = &opts.log_dir {
         shawl_args.push("--log-dir".to_string());
         shawl_args.push(quote(log_dir));
@@ -112,6 +154,90 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push("--log-retain".to_string());
         shawl_args.push(log_retain.to_string());
     }
+    if let Some(log_timestamp) = &opts.log_timestamp {
+        shawl_args.push("--log-timestamp".to_string());
+        shawl_args.push(quote(log_timestamp));
+    }
+    if let Some(log_timezone) = opts.log_timezone {
+        shawl_args.push("--log-timezone".to_string());
+        shawl_args.push(log_timezone.to_cli());
+    }
+    if opts.log_cmd_timestamp {
+        shawl_args.push("--log-cmd-timestamp".to_string());
+    }
+    if opts.log_cmd_keep_blank {
+        shawl_args.push("--log-cmd-keep-blank".to_string());
+    }
+    if let Some(log_cmd_cr) = opts.log_cmd_cr {
+        shawl_args.push("--log-cmd-cr".to_string());
+        shawl_args.push(log_cmd_cr.to_cli());
+    }
+    if let Some(log_cmd_flush) = &opts.log_cmd_flush {
+        shawl_args.push("--log-cmd-flush".to_string());
+        shawl_args.push(log_cmd_flush.to_string());
+    }
+    if let Some(log_cmd_max_line) = &opts.log_cmd_max_line {
+        shawl_args.push("--log-cmd-max-line".to_string());
+        shawl_args.push(log_cmd_max_line.to_string());
+    }
+    if let Some(log_cmd_buffer) = &opts.log_cmd_buffer {
+        shawl_args.push("--log-cmd-buffer".to_string());
+        shawl_args.push(log_cmd_buffer.to_string());
+    }
+    if let Some(log_cmd_overflow) = opts.log_cmd_overflow {
+        shawl_args.push("--log-cmd-overflow".to_string());
+        shawl_args.push(log_cmd_overflow.to_cli());
+    }
+    if let Some(log_cmd_rate) = &opts.log_cmd_rate {
+        shawl_args.push("--log-cmd-rate".to_string());
+        shawl_args.push(log_cmd_rate.to_string());
+    }
+    if let Some(log_cmd_burst) = &opts.log_cmd_burst {
+        shawl_args.push("--log-cmd-burst".to_string());
+        shawl_args.push(log_cmd_burst.to_string());
+    }
+    if opts.log_cmd_fold {
+        shawl_args.push("--log-cmd-fold".to_string());
+    }
+    if let Some(log_cmd_multiline) = &opts.log_cmd_multiline {
+        shawl_args.push("--log-cmd-multiline".to_string());
+        shawl_args.push(quote(log_cmd_multiline));
+    }
+    if let Some(log_cmd_multiline_max) = &opts.log_cmd_multiline_max {
+        shawl_args.push("--log-cmd-multiline-max".to_string());
+        shawl_args.push(log_cmd_multiline_max.to_string());
+    }
+    if let Some(log_cmd_multiline_flush) = &opts.log_cmd_multiline_flush {
+        shawl_args.push("--log-cmd-multiline-flush".to_string());
+        shawl_args.push(log_cmd_multiline_flush.to_string());
+    }
+    if let Some(crash_buffer) = &opts.crash_buffer {
+        shawl_args.push("--crash-buffer".to_string());
+        shawl_args.push(crash_buffer.to_string());
+    }
+    if let Some(crash_tail) = &opts.crash_tail {
+        shawl_args.push("--crash-tail".to_string());
+        shawl_args.push(crash_tail.to_string());
+    }
+    if opts.crash_save {
+        shawl_args.push("--crash-save".to_string());
+    }
+    if let Some(syslog) = &opts.syslog {
+        shawl_args.push("--syslog".to_string());
+        shawl_args.push(syslog.to_cli());
+    }
+    if let Some(syslog_facility) = opts.syslog_facility {
+        shawl_args.push("--syslog-facility".to_string());
+        shawl_args.push(syslog_facility.to_cli());
+    }
+    if let Some(syslog_buffer) = &opts.syslog_buffer {
+        shawl_args.push("--syslog-buffer".to_string());
+        shawl_args.push(syslog_buffer.to_string());
+    }
+    if let Some(metrics_listen) = &opts.metrics_listen {
+        shawl_args.push("--metrics-listen".to_string());
+        shawl_args.push(metrics_listen.to_string());
+    }
     if opts.pass_start_args {
         shawl_args.push("--pass-start-args".to_string());
     }
@@ -121,6 +247,18 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
             shawl_args.push(quote(&format!("{}={}", x, y)));
         }
     }
+    if !opts.redact_env.is_empty() {
+        for pattern in &opts.redact_env {
+            shawl_args.push("--redact-env".to_string());
+            shawl_args.push(quote(pattern));
+        }
+    }
+    if !opts.redact.is_empty() {
+        for pattern in &opts.redact {
+            shawl_args.push("--redact".to_string());
+            shawl_args.push(quote(pattern));
+        }
+    }
     if !opts.path.is_empty() {
         for path in &opts.path {
             shawl_args.push("--path".to_string());
@@ -137,6 +275,68 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push("--priority".to_string());
         shawl_args.push(priority.to_cli());
     }
+    if let Some(bytes) = opts.limit_memory {
+        shawl_args.push("--limit-memory".to_string());
+        shawl_args.push(bytes.to_string());
+    }
+    if let Some(percent) = opts.limit_cpu {
+        shawl_args.push("--limit-cpu".to_string());
+        shawl_args.push(percent.to_string());
+    }
+    if let Some(count) = opts.limit_processes {
+        shawl_args.push("--limit-processes".to_string());
+        shawl_args.push(count.to_string());
+    }
+    if !opts.cpu_affinity.is_empty() {
+        shawl_args.push("--cpu-affinity".to_string());
+        shawl_args.push(
+            opts.cpu_affinity
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+    }
+    if let Some(bytes) = opts.max_memory {
+        shawl_args.push("--max-memory".to_string());
+        shawl_args.push(bytes.to_string());
+    }
+    if let Some(max_cpu) = opts.max_cpu {
+        shawl_args.push("--max-cpu".to_string());
+        shawl_args.push(max_cpu.to_cli());
+    }
+    if let Some(every) = opts.restart_every {
+        shawl_args.push("--restart-every".to_string());
+        shawl_args.push(crate::cli::format_duration(every));
+    }
+    if let Some(schedule) = &opts.restart_at {
+        shawl_args.push("--restart-at".to_string());
+        shawl_args.push(quote(&schedule.to_cli()));
+    }
+    if let Some(every) = opts.job_every {
+        shawl_args.push("--job-every".to_string());
+        shawl_args.push(crate::cli::format_duration(every));
+    }
+    if let Some(schedule) = &opts.job_at {
+        shawl_args.push("--job-at".to_string());
+        shawl_args.push(quote(&schedule.to_cli()));
+    }
+    if let Some(overlap) = opts.job_overlap {
+        shawl_args.push("--job-overlap".to_string());
+        shawl_args.push(overlap.to_cli());
+    }
+    if let Some(max) = opts.max_runtime {
+        shawl_args.push("--max-runtime".to_string());
+        shawl_args.push(crate::cli::format_duration(max));
+    }
+    if let Some(stdin) = &opts.stdin {
+        shawl_args.push("--stdin".to_string());
+        shawl_args.push(quote(&stdin.to_cli()));
+    }
+    for line in &opts.stdin_line {
+        shawl_args.push("--stdin-line".to_string());
+        shawl_args.push(quote(line));
+    }
     shawl_args
 }
 
@@ -145,7 +345,7 @@ fn prepare_command(command: &[String]) -> Vec<String> {
 }
 
 fn quote(text: &str) -> String {
-    if text.contains(' ') {
+    if text.is_empty() || text.contains(' ') {
         format!("\"{}\"", text)
     } else {
         text.to_owned()
@@ -321,6 +521,42 @@ speculate::speculate! {
             );
         }
 
+        it "handles --no-kill-tree" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        no_kill_tree: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--no-kill-tree"],
+            );
+        }
+
+        it "handles stdin stop options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        stop_stdin: Some(s("save and quit")),
+                        stop_stdin_close: true,
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--stop-stdin",
+                    "\"save and quit\"",
+                    "--stop-stdin-close",
+                ],
+            );
+        }
+
         it "handles --cwd without spaces" {
             assert_eq!(
                 construct_shawl_run_args(
@@ -370,6 +606,34 @@ speculate::speculate! {
             );
         }
 
+        it "handles --no-log-events" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        no_log_events: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--no-log-events"],
+            );
+        }
+
+        it "handles --history-size" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        history_size: Some(0),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--history-size", "0"],
+            );
+        }
+
         it "handles --log-as" {
             assert_eq!(
                 construct_shawl_run_args(
@@ -398,6 +662,49 @@ speculate::speculate! {
             );
         }
 
+        it "handles --log-cmd-keep-blank" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_keep_blank: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-keep-blank"],
+            );
+        }
+
+        it "handles --log-cmd-cr" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_cr: Some(crate::cli::CarriageReturn::Split),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-cr", "split"],
+            );
+        }
+
+        it "handles --log-cmd-flush and --log-cmd-max-line" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_flush: Some(500),
+                        log_cmd_max_line: Some(4096),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-flush", "500", "--log-cmd-max-line", "4096"],
+            );
+        }
+
         it "handles --log-dir without spaces" {
             assert_eq!(
                 construct_shawl_run_args(
@@ -454,6 +761,31 @@ speculate::speculate! {
             );
         }
 
+        it "handles timestamp options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_timestamp: Some(s("%H:%M:%S %d/%m/%Y")),
+                        log_timezone: Some(crate::cli::LogTimezone::Utc),
+                        log_cmd_timestamp: true,
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-timestamp",
+                    "\"%H:%M:%S %d/%m/%Y\"",
+                    "--log-timezone",
+                    "utc",
+                    "--log-cmd-timestamp",
+                ],
+            );
+        }
+
         it "handles --pass-start-args" {
             assert_eq!(
                 construct_shawl_run_args(
@@ -510,6 +842,179 @@ speculate::speculate! {
             );
         }
 
+        it "handles --log-cmd-buffer and --log-cmd-overflow" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_buffer: Some(500),
+                        log_cmd_overflow: Some(crate::cli::Overflow::DropNewest),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-buffer", "500", "--log-cmd-overflow", "drop-newest"],
+            );
+        }
+
+        it "handles --log-cmd-rate, --log-cmd-burst, and --log-cmd-fold" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_rate: Some(100),
+                        log_cmd_burst: Some(500),
+                        log_cmd_fold: true,
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-cmd-rate",
+                    "100",
+                    "--log-cmd-burst",
+                    "500",
+                    "--log-cmd-fold",
+                ],
+            );
+        }
+
+        it "handles --log-cmd-multiline options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_multiline: Some(s(r"^\s")),
+                        log_cmd_multiline_max: Some(50),
+                        log_cmd_multiline_flush: Some(200),
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-cmd-multiline",
+                    r"^\s",
+                    "--log-cmd-multiline-max",
+                    "50",
+                    "--log-cmd-multiline-flush",
+                    "200",
+                ],
+            );
+        }
+
+        it "quotes a --log-cmd-multiline pattern with spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_multiline: Some(s(r"^(\s|Caused by:)")),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-multiline", r#""^(\s|Caused by:)""#],
+            );
+        }
+
+        it "handles crash report options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        crash_buffer: Some(500),
+                        crash_tail: Some(10),
+                        crash_save: true,
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--crash-buffer",
+                    "500",
+                    "--crash-tail",
+                    "10",
+                    "--crash-save",
+                ],
+            );
+        }
+
+        it "handles --syslog options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        syslog: Some(crate::cli::SyslogTarget {
+                            protocol: crate::cli::SyslogProtocol::Tcp,
+                            address: s("logs:601"),
+                        }),
+                        syslog_facility: Some(crate::cli::SyslogFacility::Local0),
+                        syslog_buffer: Some(50),
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--syslog",
+                    "tcp://logs:601",
+                    "--syslog-facility",
+                    "local0",
+                    "--syslog-buffer",
+                    "50",
+                ],
+            );
+        }
+
+        it "handles --metrics-listen" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        metrics_listen: Some("[::1]:9100".parse().unwrap()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--metrics-listen", "[::1]:9100"],
+            );
+        }
+
+        it "handles --redact-env and --redact" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        redact_env: vec![s("LICENSE_*")],
+                        redact: vec![s(r"password=(\S+)"), s("secret value")],
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--redact-env",
+                    "LICENSE_*",
+                    "--redact",
+                    r"password=(\S+)",
+                    "--redact",
+                    "\"secret value\"",
+                ],
+            );
+        }
+
         it "handles --path without spaces" {
             assert_eq!(
                 construct_shawl_run_args(
@@ -565,6 +1070,144 @@ speculate::speculate! {
                 vec!["run", "--name", "shawl", "--priority", "above-normal"],
             );
         }
+
+        it "handles resource limits" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        limit_memory: Some(1024),
+                        limit_cpu: Some(50),
+                        limit_processes: Some(4),
+                        cpu_affinity: vec![1, 3],
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--limit-memory",
+                    "1024",
+                    "--limit-cpu",
+                    "50",
+                    "--limit-processes",
+                    "4",
+                    "--cpu-affinity",
+                    "1,3"
+                ],
+            );
+        }
+
+        it "handles --max-memory and --max-cpu" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        max_memory: Some(2048),
+                        max_cpu: Some("90%:5m".parse().unwrap()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--max-memory", "2048", "--max-cpu", "90%:5m"],
+            );
+        }
+
+        it "handles --restart-every and --restart-at" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_every: Some(std::time::Duration::from_secs(36 * 60 * 60)),
+                        restart_at: Some("0 3 * * 1-5".parse().unwrap()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-every", "36h", "--restart-at", "\"0 3 * * 1-5\""],
+            );
+        }
+
+        it "handles job options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        job_at: Some("*/15 * * * *".parse().unwrap()),
+                        job_overlap: Some(crate::cli::JobOverlap::Replace),
+                        max_runtime: Some(std::time::Duration::from_secs(10 * 60)),
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--job-at",
+                    "\"*/15 * * * *\"",
+                    "--job-overlap",
+                    "replace",
+                    "--max-runtime",
+                    "10m"
+                ],
+            );
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        job_every: Some(std::time::Duration::from_secs(90)),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--job-every", "90s"],
+            );
+        }
+
+        it "handles --restart-on and --stop-on" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_on: vec![crate::cli::Condition::Limit],
+                        stop_on: vec![crate::cli::Condition::Limit],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-on", "limit", "--stop-on", "limit"],
+            );
+        }
+
+        it "handles --stdin options" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        stdin: Some(crate::cli::StdinMode::File(s(r"C:\my app\input.txt"))),
+                        stdin_line: vec![s("yes"), s(""), s("two words")],
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--stdin",
+                    "\"file:C:\\my app\\input.txt\"",
+                    "--stdin-line",
+                    "yes",
+                    "--stdin-line",
+                    "\"\"",
+                    "--stdin-line",
+                    "\"two words\"",
+                ],
+            );
+        }
     }
 
     describe "prepare_command" {
@@ -0,0 +1,941 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+/// How long either end waits on the other before giving up.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How many exits `Status` remembers.
+#[cfg(any(windows, test))]
+const RECENT_EXITS: usize = 10;
+
+/// A request from `shawl ctl` to a running service, sent as one line of JSON.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    RestartChild,
+    StopChild,
+    StartChild,
+    RotateLogs,
+    SetRestartPolicy {
+        /// In the same format as the CLI, like `if=1,2`.
+        policy: String,
+    },
+}
+
+impl From<&crate::cli::CtlCommand> for Request {
+    fn from(command: &crate::cli::CtlCommand) -> Self {
+        use crate::cli::CtlCommand;
+        match command {
+            CtlCommand::Status => Self::Status,
+            CtlCommand::RestartChild => Self::RestartChild,
+            CtlCommand::StopChild => Self::StopChild,
+            CtlCommand::StartChild => Self::StartChild,
+            CtlCommand::RotateLogs => Self::RotateLogs,
+            CtlCommand::SetRestartPolicy { policy } => Self::SetRestartPolicy {
+                policy: policy.to_cli(),
+            },
+        }
+    }
+}
+
+/// The service's answer, also sent as one line of JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+#[cfg(any(windows, test))]
+impl Response {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn error(error: impl std::fmt::Display) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn status(status: Status) -> Self {
+        Self {
+            ok: true,
+            status: Some(status),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChildState {
+    #[default]
+    Starting,
+    Running,
+    Stopping,
+    /// Stopped by `stop-child` and waiting for `start-child`.
+    Stopped,
+    /// Waiting for the next job run.
+    Waiting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    Exited,
+    Terminated,
+    /// Still running when the stop timeout expired.
+    Killed,
+    /// Shawl couldn't tell what happened.
+    Lost,
+    /// Reached a resource limit.
+    Limit,
+    /// Ran longer than `--max-runtime`.
+    Timeout,
+}
+
+impl std::fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Exited => "exited",
+            Self::Terminated => "was terminated",
+            Self::Killed => "was killed",
+            Self::Lost => "was lost",
+            Self::Limit => "reached a resource limit",
+            Self::Timeout => "timed out",
+        })
+    }
+}
+
+/// How one run of the command ended.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Exit {
+    /// RFC 3339 with milliseconds.
+    pub time: String,
+    pub generation: u64,
+    pub reason: ExitReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+    pub decision: crate::events::Decision,
+    /// Only for job runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+}
+
+/// How job runs have gone with `--job-every` or `--job-at`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Jobs {
+    /// Like `every 15m` or `at 0 3 * * *`.
+    pub schedule: String,
+    /// RFC 3339 with milliseconds, while waiting for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<String>,
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+/// What the service is doing right now.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Status {
+    pub name: String,
+    pub shawl_pid: u32,
+    pub state: ChildState,
+    /// How many times the command has been launched since the service started.
+    pub generation: u64,
+    /// Runs that ended with Shawl deciding to restart the command.
+    pub restarts: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// RFC 3339 with milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launched_at: Option<String>,
+    pub restart_policy: String,
+    /// Most recent first.
+    #[serde(default)]
+    pub recent_exits: Vec<Exit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<Jobs>,
+}
+
+impl Status {
+    /// Keep up with a lifecycle event from the service loop.
+    #[cfg(any(windows, test))]
+    pub fn observe(&mut self, event: &crate::events::Event) {
+        use crate::events::Event;
+        let now = || chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+        let (generation, reason, code, decision) = match event {
+            Event::Launched { generation, pid } => {
+                self.state = ChildState::Running;
+                self.generation = *generation;
+                self.pid = Some(*pid);
+                self.launched_at = Some(now());
+                if let Some(jobs) = &mut self.jobs {
+                    jobs.next_run = None;
+                }
+                return;
+            }
+            Event::StopRequested { .. } => {
+                self.state = ChildState::Stopping;
+                return;
+            }
+            Event::ThresholdExceeded { .. } | Event::RestartScheduled { .. } => return,
+            Event::TimedOut { generation, .. } => {
+                if let Some(exit) = self.recent_exits.first_mut().filter(|x| x.generation == *generation) {
+                    exit.reason = ExitReason::Timeout;
+                }
+                return;
+            }
+            Event::JobFinished {
+                generation, success, ..
+            } => {
+                if let Some(jobs) = &mut self.jobs {
+                    if *success {
+                        jobs.succeeded += 1;
+                    } else {
+                        jobs.failed += 1;
+                    }
+                }
+                if let Some(exit) = self.recent_exits.first_mut().filter(|x| x.generation == *generation) {
+                    exit.success = Some(*success);
+                }
+                return;
+            }
+            Event::Exited {
+                generation,
+                code,
+                decision,
+                ..
+            } => (*generation, ExitReason::Exited, Some(*code), *decision),
+            Event::Terminated {
+                generation, decision, ..
+            } => (*generation, ExitReason::Terminated, None, *decision),
+            Event::Killed {
+                generation, decision, ..
+            } => (*generation, ExitReason::Killed, None, *decision),
+            Event::LimitReached {
+                generation,
+                code,
+                decision,
+                ..
+            } => (*generation, ExitReason::Limit, *code, *decision),
+            Event::Lost {
+                generation, decision, ..
+            } => (*generation, ExitReason::Lost, None, *decision),
+            Event::ServiceStarted { .. } | Event::LaunchFailed { .. } | Event::ServiceStopped {} => return,
+        };
+
+        if decision == crate::events::Decision::Restart {
+            self.restarts += 1;
+        }
+        self.state = ChildState::Starting;
+        self.pid = None;
+        self.launched_at = None;
+        self.recent_exits.insert(
+            0,
+            Exit {
+                time: now(),
+                generation,
+                reason,
+                code,
+                decision,
+                success: None,
+            },
+        );
+        self.recent_exits.truncate(RECENT_EXITS);
+    }
+
+    /// Summarize for `shawl status`.
+    pub fn describe(&self, now: chrono::DateTime<chrono::FixedOffset>) -> String {
+        let mut lines = vec![
+            format!("Service:        {} (Shawl PID {})", self.name, self.shawl_pid),
+            format!("State:          {}", self.state),
+        ];
+        if let Some(pid) = self.pid {
+            lines.push(format!("PID:            {}", pid));
+        }
+        lines.push(format!("Generation:     {}", self.generation));
+        if let Some(launched_at) = &self.launched_at {
+            match chrono::DateTime::parse_from_rfc3339(launched_at) {
+                Ok(x) => lines.push(format!(
+                    "Uptime:         {} (since {})",
+                    format_uptime((now - x).to_std().unwrap_or_default()),
+                    launched_at
+                )),
+                Err(_) => lines.push(format!("Launched at:    {}", launched_at)),
+            }
+        }
+        match &self.jobs {
+            Some(jobs) => {
+                lines.push(format!("Schedule:       {}", jobs.schedule));
+                if let Some(next_run) = &jobs.next_run {
+                    lines.push(format!("Next run:       {}", next_run));
+                }
+                lines.push(format!(
+                    "Job runs:       {} succeeded, {} failed",
+                    jobs.succeeded, jobs.failed
+                ));
+            }
+            None => {
+                lines.push(format!("Restarts:       {}", self.restarts));
+                lines.push(format!("Restart policy: {}", self.restart_policy));
+            }
+        }
+        if self.recent_exits.is_empty() {
+            lines.push("Recent exits:   none".to_string());
+        } else {
+            lines.push("Recent exits:".to_string());
+            for exit in &self.recent_exits {
+                lines.push(format!(
+                    "  {}  #{} {}",
+                    exit.time,
+                    exit.generation,
+                    outcome(exit.reason, exit.code, Some(exit.decision), exit.success)
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+impl std::fmt::Display for ChildState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Starting => "starting",
+            Self::Running => "running",
+            Self::Stopping => "stopping",
+            Self::Stopped => "stopped (waiting for start-child)",
+            Self::Waiting => "waiting for the next job run",
+        })
+    }
+}
+
+/// Like `exited with code 1, restarted`, or `exited with code 1, failed` for a job run.
+pub fn outcome(
+    reason: ExitReason,
+    code: Option<i32>,
+    decision: Option<crate::events::Decision>,
+    success: Option<bool>,
+) -> String {
+    let mut outcome = reason.to_string();
+    // After a timeout, the code only says how the command took being stopped.
+    if let Some(code) = code.filter(|_| reason != ExitReason::Timeout) {
+        outcome.push_str(&format!(" with code {}", code));
+    }
+    match decision {
+        Some(crate::events::Decision::Restart) => outcome.push_str(", restarted"),
+        Some(crate::events::Decision::Stop) => outcome.push_str(", stopped"),
+        Some(crate::events::Decision::Wait) | None => (),
+    }
+    match success {
+        Some(true) => outcome.push_str(", succeeded"),
+        Some(false) => outcome.push_str(", failed"),
+        None => (),
+    }
+    outcome
+}
+
+/// Like `1d 2h 3m 4s`, leaving out leading zeros.
+fn format_uptime(duration: std::time::Duration) -> String {
+    let seconds = duration.as_secs();
+    let parts = [
+        (seconds / 86_400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let first = parts.iter().position(|(x, _)| *x > 0).unwrap_or(parts.len() - 1);
+    parts[first..]
+        .iter()
+        .map(|(x, unit)| format!("{}{}", x, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Where the service listens for requests.
+#[cfg(windows)]
+pub fn endpoint(name: &str) -> PathBuf {
+    PathBuf::from(format!(r"\\.\pipe\shawl-{}", name))
+}
+
+/// Where the service listens for requests.
+#[cfg(unix)]
+pub fn endpoint(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("shawl-{}.sock", name))
+}
+
+/// Read one request and write one response.
+#[cfg(any(windows, test))]
+fn exchange(stream: impl std::io::Read + Write, handle: &impl Fn(Request) -> Response) -> std::io::Result<()> {
+    let mut reader = std::io::BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => handle(request),
+        Err(e) => Response::error(format!("invalid request: {}", e)),
+    };
+
+    let mut stream = reader.into_inner();
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    stream.flush()
+}
+
+/// Answer requests in a background thread, one at a time, until the program exits.
+/// Only the tests serve requests on Unix.
+#[cfg(all(unix, test))]
+pub fn serve(name: &str, handle: impl Fn(Request) -> Response + Send + 'static) -> std::io::Result<()> {
+    let path = endpoint(name);
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("another instance is already listening at {:?}", path),
+            ));
+        }
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = std::os::unix::net::UnixListener::bind(&path)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                exchange(stream, &handle)
+            });
+            if let Err(e) = result {
+                log::debug!("Control request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The service's end of the control pipe. It uses overlapped I/O
+/// so that a client that goes quiet can't hold up everyone else.
+#[cfg(windows)]
+struct Pipe {
+    pipe: std::os::windows::io::OwnedHandle,
+    /// Signaled when an operation on the pipe finishes.
+    event: std::os::windows::io::OwnedHandle,
+}
+
+#[cfg(windows)]
+impl Pipe {
+    fn handle(&self) -> windows::Win32::Foundation::HANDLE {
+        use std::os::windows::io::AsRawHandle;
+        windows::Win32::Foundation::HANDLE(self.pipe.as_raw_handle())
+    }
+
+    /// Wait for an operation that `start` began, giving up after `timeout` if there is one.
+    /// Returns how many bytes it transferred.
+    fn complete(
+        &self,
+        start: impl FnOnce(*mut windows::Win32::System::IO::OVERLAPPED) -> windows::core::Result<()>,
+        timeout: Option<std::time::Duration>,
+    ) -> windows::core::Result<usize> {
+        use std::os::windows::io::AsRawHandle;
+        use windows::Win32::{
+            Foundation::{ERROR_IO_PENDING, ERROR_TIMEOUT, HANDLE, WAIT_OBJECT_0},
+            System::{
+                Threading::{WaitForSingleObject, INFINITE},
+                IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+            },
+        };
+
+        let event = HANDLE(self.event.as_raw_handle());
+        let mut overlapped = OVERLAPPED {
+            hEvent: event,
+            ..Default::default()
+        };
+        let mut transferred = 0;
+        if let Err(e) = start(&mut overlapped) {
+            if e.code() != ERROR_IO_PENDING.to_hresult() {
+                return Err(e);
+            }
+            let millis = timeout.map_or(INFINITE, |x| x.as_millis().try_into().unwrap_or(INFINITE));
+            if unsafe { WaitForSingleObject(event, millis) } != WAIT_OBJECT_0 {
+                // The operation still uses `overlapped` until it's done being cancelled.
+                let _ = unsafe { CancelIoEx(self.handle(), Some(&overlapped)) };
+                let _ = unsafe { GetOverlappedResult(self.handle(), &overlapped, &mut transferred, true) };
+                return Err(ERROR_TIMEOUT.to_hresult().into());
+            }
+        }
+        unsafe { GetOverlappedResult(self.handle(), &overlapped, &mut transferred, false) }?;
+        Ok(transferred as usize)
+    }
+
+    /// Wait as long as it takes for the next client.
+    fn accept(&self) -> std::io::Result<()> {
+        use windows::Win32::{Foundation::ERROR_PIPE_CONNECTED, System::Pipes::ConnectNamedPipe};
+        match self.complete(|x| unsafe { ConnectNamedPipe(self.handle(), Some(x)) }, None) {
+            Ok(_) => Ok(()),
+            // It connected between creating the pipe and calling this.
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Let the client read the response before disconnecting it, so that it's ready for the next one.
+    fn disconnect(&self) -> std::io::Result<()> {
+        // The client closes its end once it has the response.
+        let _ = std::io::Read::read(&mut &*self, &mut [0; 1]);
+        unsafe { windows::Win32::System::Pipes::DisconnectNamedPipe(self.handle()) }?;
+        Ok(())
+    }
+
+    fn transfer(
+        &self,
+        start: impl FnOnce(*mut windows::Win32::System::IO::OVERLAPPED) -> windows::core::Result<()>,
+    ) -> std::io::Result<usize> {
+        match self.complete(start, Some(TIMEOUT)) {
+            Ok(count) => Ok(count),
+            Err(e) if e.code() == windows::Win32::Foundation::ERROR_BROKEN_PIPE.to_hresult() => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl std::io::Read for &Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let handle = self.handle();
+        self.transfer(|x| unsafe { windows::Win32::Storage::FileSystem::ReadFile(handle, Some(buf), None, Some(x)) })
+    }
+}
+
+#[cfg(windows)]
+impl Write for &Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let handle = self.handle();
+        self.transfer(|x| unsafe { windows::Win32::Storage::FileSystem::WriteFile(handle, Some(buf), None, Some(x)) })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Answer requests in a background thread, one at a time, until the program exits.
+/// Only administrators, the local system, and the service's own account may connect.
+#[cfg(windows)]
+pub fn serve(name: &str, handle_request: impl Fn(Request) -> Response + Send + 'static) -> std::io::Result<()> {
+    use std::os::windows::io::{FromRawHandle, OwnedHandle};
+    use windows::Win32::{
+        Foundation::{LocalFree, HLOCAL},
+        Security::{
+            Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1},
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX},
+        System::{
+            Pipes::{CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT},
+            Threading::CreateEventW,
+        },
+    };
+
+    let path = windows::core::HSTRING::from(endpoint(name).as_os_str());
+    let create = move || -> std::io::Result<Pipe> {
+        // The default security would also let everyone read from the pipe.
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                windows::core::w!("D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;OW)"),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )
+        }?;
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: descriptor.0,
+            bInheritHandle: false.into(),
+        };
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                &path,
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE | FILE_FLAG_OVERLAPPED,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                4096,
+                4096,
+                0,
+                Some(&attributes),
+            )
+        };
+        let error = std::io::Error::last_os_error();
+        unsafe { LocalFree(HLOCAL(descriptor.0)) };
+        if pipe.is_invalid() {
+            return Err(error);
+        }
+        // These take care of closing the handles.
+        let pipe = unsafe { OwnedHandle::from_raw_handle(pipe.0) };
+        let event = unsafe { CreateEventW(None, true, false, windows::core::PCWSTR::null()) }?;
+        let event = unsafe { OwnedHandle::from_raw_handle(event.0) };
+        Ok(Pipe { pipe, event })
+    };
+
+    // Create the pipe here so that we can report a conflict right away.
+    let mut pipe = Some(create()?);
+    std::thread::spawn(move || loop {
+        // Only one instance may exist at a time, so it's reused for each client,
+        // unless something went wrong and it had to be closed.
+        let server = match pipe.take().map_or_else(&create, Ok) {
+            Ok(server) => server,
+            Err(e) => {
+                log::warn!("Unable to listen for control requests, so trying again: {}", e);
+                std::thread::sleep(TIMEOUT);
+                continue;
+            }
+        };
+        match server.accept() {
+            Ok(()) => {
+                if let Err(e) = exchange(&server, &handle_request) {
+                    log::debug!("Control request failed: {}", e);
+                }
+            }
+            Err(e) => log::debug!("Unable to accept a control request: {}", e),
+        }
+        match server.disconnect() {
+            Ok(()) => pipe = Some(server),
+            Err(e) => log::debug!("Unable to disconnect a control client: {}", e),
+        }
+    });
+    Ok(())
+}
+
+/// Send a request to the running service with this name.
+pub fn send(name: &str, request: &Request) -> std::io::Result<Response> {
+    let path = endpoint(name);
+    let not_running = |e: std::io::Error| {
+        std::io::Error::new(
+            e.kind(),
+            format!("unable to reach service {:?} (is it running?): {}", name, e),
+        )
+    };
+
+    #[cfg(unix)]
+    let stream = {
+        let stream = std::os::unix::net::UnixStream::connect(&path).map_err(not_running)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream
+    };
+
+    #[cfg(windows)]
+    let stream = {
+        // The service handles one request at a time, so wait for a turn.
+        let start = std::time::Instant::now();
+        loop {
+            match std::fs::OpenOptions::new().read(true).write(true).open(&path) {
+                Ok(file) => break file,
+                Err(e)
+                    if e.raw_os_error() == Some(windows::Win32::Foundation::ERROR_PIPE_BUSY.0 as i32)
+                        && start.elapsed() < TIMEOUT =>
+                {
+                    std::thread::sleep(std::time::Duration::from_millis(50))
+                }
+                Err(e) => return Err(not_running(e)),
+            }
+        }
+    };
+
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(request)?)?;
+    writer.flush()?;
+
+    let mut line = String::new();
+    std::io::BufReader::new(&stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Run `shawl status`.
+pub fn show_status(opts: &crate::cli::StatusOpts, out: &mut impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let response = send(&opts.name, &Request::Status)?;
+    if let Some(error) = response.error {
+        return Err(error.into());
+    }
+    let status = response.status.ok_or("the service didn't report its status")?;
+    if opts.json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&status)?)?;
+    } else {
+        writeln!(out, "{}", status.describe(chrono::Local::now().fixed_offset()))?;
+    }
+    Ok(())
+}
+
+/// Run `shawl ctl`.
+pub fn run(opts: &crate::cli::CtlOpts, out: &mut impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let response = send(&opts.name, &Request::from(&opts.command))?;
+    if let Some(error) = response.error {
+        return Err(error.into());
+    }
+    if let Some(status) = response.status {
+        writeln!(out, "{}", serde_json::to_string_pretty(&status)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "protocol" {
+        it "uses one JSON object per request" {
+            assert_eq!(serde_json::to_string(&Request::RotateLogs).unwrap(), r#"{"command":"rotate-logs"}"#);
+            assert_eq!(
+                serde_json::to_string(&Request::SetRestartPolicy { policy: "never".to_string() }).unwrap(),
+                r#"{"command":"set-restart-policy","policy":"never"}"#,
+            );
+        }
+
+        it "leaves out empty parts of the response" {
+            assert_eq!(serde_json::to_string(&Response::ok()).unwrap(), r#"{"ok":true}"#);
+            assert_eq!(
+                serde_json::to_string(&Response::error("nope")).unwrap(),
+                r#"{"ok":false,"error":"nope"}"#,
+            );
+        }
+
+        it "converts CLI commands" {
+            let command = crate::cli::CtlCommand::SetRestartPolicy { policy: "if=3".parse().unwrap() };
+            assert_eq!(Request::from(&command), Request::SetRestartPolicy { policy: "if=3".to_string() });
+        }
+    }
+
+    describe "status" {
+        fn exited(generation: u64, code: i32) -> crate::events::Event {
+            crate::events::Event::Exited {
+                generation,
+                pid: 10 + generation as u32,
+                code,
+                usage: Default::default(),
+                decision: crate::events::Decision::Restart,
+            }
+        }
+
+        it "follows launches and exits" {
+            let mut status = Status::default();
+            status.observe(&crate::events::Event::Launched { generation: 1, pid: 11 });
+            assert_eq!((status.state, status.pid, status.restarts), (ChildState::Running, Some(11), 0));
+            assert!(status.launched_at.is_some());
+
+            status.observe(&exited(1, 3));
+            assert_eq!((status.state, status.pid, status.launched_at.clone()), (ChildState::Starting, None, None));
+
+            status.observe(&crate::events::Event::Launched { generation: 2, pid: 12 });
+            status.observe(&crate::events::Event::StopRequested { generation: 2, pid: 12 });
+            assert_eq!(status.state, ChildState::Stopping);
+            status.observe(&crate::events::Event::Killed {
+                generation: 2,
+                pid: 12,
+                usage: Default::default(),
+                decision: crate::events::Decision::Restart,
+            });
+
+            assert_eq!(status.restarts, 2);
+            assert_eq!(
+                status.recent_exits.iter().map(|x| (x.generation, x.reason, x.code)).collect::<Vec<_>>(),
+                vec![(2, ExitReason::Killed, None), (1, ExitReason::Exited, Some(3))],
+            );
+        }
+
+        it "marks runs that timed out" {
+            let mut status = Status::default();
+            status.observe(&crate::events::Event::Launched { generation: 1, pid: 11 });
+            status.observe(&crate::events::Event::StopRequested { generation: 1, pid: 11 });
+            status.observe(&crate::events::Event::Killed {
+                generation: 1,
+                pid: 11,
+                usage: Default::default(),
+                decision: crate::events::Decision::Restart,
+            });
+            status.observe(&crate::events::Event::TimedOut { generation: 1, pid: 11, max_runtime_ms: 60_000 });
+            assert_eq!(status.recent_exits[0].reason, ExitReason::Timeout);
+        }
+
+        it "only counts restarts that Shawl decided on" {
+            let mut status = Status::default();
+            status.observe(&crate::events::Event::Launched { generation: 1, pid: 11 });
+            status.observe(&crate::events::Event::StopRequested { generation: 1, pid: 11 });
+            status.observe(&crate::events::Event::Terminated {
+                generation: 1,
+                pid: 11,
+                usage: Default::default(),
+                decision: crate::events::Decision::Stop,
+            });
+            status.observe(&crate::events::Event::Launched { generation: 2, pid: 12 });
+            assert_eq!((status.generation, status.restarts), (2, 0));
+
+            status.observe(&exited(2, 1));
+            status.observe(&crate::events::Event::Launched { generation: 3, pid: 13 });
+            assert_eq!((status.generation, status.restarts), (3, 1));
+        }
+
+        it "only remembers the latest exits" {
+            let mut status = Status::default();
+            for generation in 1..=15 {
+                status.observe(&exited(generation, 1));
+            }
+            assert_eq!(status.recent_exits.len(), RECENT_EXITS);
+            assert_eq!(status.recent_exits[0].generation, 15);
+        }
+
+        it "describes itself" {
+            let status = Status {
+                name: "my-app".to_string(),
+                shawl_pid: 100,
+                state: ChildState::Running,
+                generation: 3,
+                restarts: 2,
+                pid: Some(200),
+                launched_at: Some("2024-01-02T03:00:00.000+00:00".to_string()),
+                restart_policy: "on-failure".to_string(),
+                recent_exits: vec![
+                    Exit {
+                        time: "2024-01-02T02:59:59.000+00:00".to_string(),
+                        generation: 2,
+                        reason: ExitReason::Exited,
+                        code: Some(1),
+                        decision: crate::events::Decision::Restart,
+                        success: None,
+                    },
+                    Exit {
+                        time: "2024-01-01T12:00:00.000+00:00".to_string(),
+                        generation: 1,
+                        reason: ExitReason::Terminated,
+                        code: None,
+                        decision: crate::events::Decision::Restart,
+                        success: None,
+                    },
+                ],
+                jobs: None,
+            };
+            let now = chrono::DateTime::parse_from_rfc3339("2024-01-03T04:05:06+00:00").unwrap();
+            assert_eq!(
+                status.describe(now),
+                [
+                    "Service:        my-app (Shawl PID 100)",
+                    "State:          running",
+                    "PID:            200",
+                    "Generation:     3",
+                    "Uptime:         1d 1h 5m 6s (since 2024-01-02T03:00:00.000+00:00)",
+                    "Restarts:       2",
+                    "Restart policy: on-failure",
+                    "Recent exits:",
+                    "  2024-01-02T02:59:59.000+00:00  #2 exited with code 1, restarted",
+                    "  2024-01-01T12:00:00.000+00:00  #1 was terminated, restarted",
+                ]
+                .join("\n"),
+            );
+        }
+
+        it "describes job runs" {
+            let mut status = Status {
+                name: "my-app".to_string(),
+                shawl_pid: 100,
+                state: ChildState::Waiting,
+                jobs: Some(Jobs {
+                    schedule: "every 15m".to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            status.observe(&crate::events::Event::Launched { generation: 1, pid: 11 });
+            status.observe(&crate::events::Event::Exited {
+                generation: 1,
+                pid: 11,
+                code: 2,
+                usage: Default::default(),
+                decision: crate::events::Decision::Wait,
+            });
+            status.observe(&crate::events::Event::JobFinished { generation: 1, pid: 11, success: false });
+            status.state = ChildState::Waiting;
+            status.jobs.as_mut().unwrap().next_run = Some("2024-01-02T03:15:00.000+00:00".to_string());
+
+            let now = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:05:00+00:00").unwrap();
+            let description = status.describe(now);
+            let lines: Vec<_> = description.lines().collect();
+            assert_eq!(
+                lines[..6],
+                [
+                    "Service:        my-app (Shawl PID 100)",
+                    "State:          waiting for the next job run",
+                    "Generation:     1",
+                    "Schedule:       every 15m",
+                    "Next run:       2024-01-02T03:15:00.000+00:00",
+                    "Job runs:       0 succeeded, 1 failed",
+                ],
+            );
+            assert!(lines[7].ends_with("  #1 exited with code 2, failed"));
+        }
+
+        it "formats uptime" {
+            assert_eq!(format_uptime(std::time::Duration::from_secs(0)), "0s");
+            assert_eq!(format_uptime(std::time::Duration::from_secs(61)), "1m 1s");
+            assert_eq!(format_uptime(std::time::Duration::from_secs(7200)), "2h 0m 0s");
+        }
+    }
+
+    describe "channel" {
+        #[cfg(unix)]
+        it "answers requests" {
+            let name = format!("test-answers-{}", std::process::id());
+            serve(&name, |request| match request {
+                Request::Status => Response::status(Status {
+                    name: "test".to_string(),
+                    generation: 2,
+                    pid: Some(5),
+                    restart_policy: "always".to_string(),
+                    ..Default::default()
+                }),
+                Request::StopChild => Response::ok(),
+                _ => Response::error("not supported"),
+            })
+            .unwrap();
+
+            let status = send(&name, &Request::Status).unwrap().status.unwrap();
+            assert_eq!((status.generation, status.pid), (2, Some(5)));
+            assert_eq!(send(&name, &Request::StopChild).unwrap(), Response::ok());
+            assert_eq!(send(&name, &Request::RotateLogs).unwrap(), Response::error("not supported"));
+            std::fs::remove_file(endpoint(&name)).unwrap();
+        }
+
+        #[cfg(unix)]
+        it "rejects malformed requests" {
+            let name = format!("test-malformed-{}", std::process::id());
+            serve(&name, |_| Response::ok()).unwrap();
+
+            let mut stream = std::os::unix::net::UnixStream::connect(endpoint(&name)).unwrap();
+            writeln!(stream, r#"{{"command":"dance"}}"#).unwrap();
+            let mut line = String::new();
+            std::io::BufReader::new(&stream).read_line(&mut line).unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert!(!response.ok);
+            assert!(response.error.unwrap().starts_with("invalid request"));
+            std::fs::remove_file(endpoint(&name)).unwrap();
+        }
+
+        #[cfg(unix)]
+        it "refuses to take over a live endpoint" {
+            let name = format!("test-conflict-{}", std::process::id());
+            serve(&name, |_| Response::ok()).unwrap();
+            assert!(serve(&name, |_| Response::ok()).is_err());
+            std::fs::remove_file(endpoint(&name)).unwrap();
+        }
+
+        #[cfg(unix)]
+        it "reports when the service isn't running" {
+            let error = send("test-nobody-home", &Request::Status).unwrap_err();
+            assert!(error.to_string().contains("is it running?"));
+        }
+    }
+}
@@ -0,0 +1,339 @@
+#[cfg(any(windows, test))]
+use crate::usage::Usage;
+#[cfg(any(windows, test))]
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Once the journal grows past this, it's moved aside to the `.old` file,
+/// replacing any previous one.
+#[cfg(any(windows, test))]
+const MAX_BYTES: u64 = 1024 * 1024;
+
+#[cfg(windows)]
+static GLOBAL: std::sync::OnceLock<Journal> = std::sync::OnceLock::new();
+
+/// The event journal, unless disabled by `--no-log` or `--no-log-events`.
+#[cfg(windows)]
+pub fn global() -> Option<&'static Journal> {
+    GLOBAL.get()
+}
+
+#[cfg(windows)]
+pub fn install(journal: Journal) -> &'static Journal {
+    GLOBAL.get_or_init(|| journal)
+}
+
+/// Add an event to the global journal, if there is one.
+#[cfg(windows)]
+pub fn record(event: Event) {
+    if let Some(journal) = global() {
+        journal.record(event);
+    }
+}
+
+/// What Shawl decided to do after the command exited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Restart,
+    Stop,
+    /// Wait for the next job run.
+    Wait,
+}
+
+/// Something that happened in the life of the service.
+/// The generation counts launches of the command, starting from 1
+/// each time the service starts.
+#[cfg(any(windows, test))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ServiceStarted {
+        shawl_pid: u32,
+    },
+    Launched {
+        generation: u64,
+        pid: u32,
+    },
+    LaunchFailed {
+        generation: u64,
+        error: String,
+    },
+    StopRequested {
+        generation: u64,
+        pid: u32,
+    },
+    Exited {
+        generation: u64,
+        pid: u32,
+        code: i32,
+        #[serde(flatten)]
+        usage: Usage,
+        decision: Decision,
+    },
+    Terminated {
+        generation: u64,
+        pid: u32,
+        #[serde(flatten)]
+        usage: Usage,
+        decision: Decision,
+    },
+    /// The command used more memory or CPU than `--max-memory` or `--max-cpu` allow,
+    /// so Shawl is restarting it.
+    ThresholdExceeded {
+        generation: u64,
+        pid: u32,
+        reason: String,
+    },
+    /// The command is due for `--restart-every` or `--restart-at`, so Shawl is restarting it.
+    RestartScheduled {
+        generation: u64,
+        pid: u32,
+        reason: String,
+    },
+    /// Shawl stopped the command because it ran longer than `--max-runtime`,
+    /// right after the event for how it ended.
+    TimedOut {
+        generation: u64,
+        pid: u32,
+        max_runtime_ms: u64,
+    },
+    /// A job run is over, right after the event for how the command ended.
+    JobFinished {
+        generation: u64,
+        pid: u32,
+        success: bool,
+    },
+    /// The command ended after reaching a resource limit.
+    LimitReached {
+        generation: u64,
+        pid: u32,
+        limit: crate::tree::Limit,
+        /// Missing if the command was terminated.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<i32>,
+        #[serde(flatten)]
+        usage: Usage,
+        decision: Decision,
+    },
+    /// The command was still running when the stop timeout expired.
+    Killed {
+        generation: u64,
+        pid: u32,
+        #[serde(flatten)]
+        usage: Usage,
+        decision: Decision,
+    },
+    /// Shawl couldn't tell what happened to the command.
+    Lost {
+        generation: u64,
+        pid: u32,
+        error: String,
+        decision: Decision,
+    },
+    ServiceStopped {},
+}
+
+/// One line in the journal.
+#[cfg(any(windows, test))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Record {
+    /// RFC 3339 with milliseconds.
+    pub time: String,
+    pub service: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[cfg(any(windows, test))]
+impl Record {
+    pub fn now(service: &str, event: Event) -> Self {
+        Self {
+            time: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            service: service.to_string(),
+            event,
+        }
+    }
+}
+
+/// Append-only JSONL file of lifecycle events, one `Record` per line.
+#[cfg(any(windows, test))]
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    service: String,
+    lock: std::sync::Mutex<()>,
+}
+
+#[cfg(any(windows, test))]
+impl Journal {
+    pub fn new(path: PathBuf, service: &str) -> Self {
+        Self {
+            path,
+            service: service.to_string(),
+            lock: std::sync::Mutex::new(()),
+        }
+    }
+
+    /// The journal for a main log file with the given base name.
+    pub fn path(dir: &str, basename: &str) -> PathBuf {
+        Path::new(dir).join(format!("{}_events.jsonl", basename))
+    }
+
+    /// Where the journal is moved once it reaches `MAX_BYTES`.
+    pub fn old_path(&self) -> PathBuf {
+        self.path.with_extension("old.jsonl")
+    }
+
+    pub fn record(&self, event: Event) {
+        if let Err(e) = self.write(&Record::now(&self.service, event), MAX_BYTES) {
+            log::error!("Unable to write to the event journal {:?}: {}", &self.path, e);
+        }
+    }
+
+    fn write(&self, record: &Record, max_bytes: u64) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if std::fs::metadata(&self.path).is_ok_and(|x| x.len() >= max_bytes) {
+            std::fs::rename(&self.path, self.old_path())?;
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn record(event: Event) -> Record {
+        Record {
+            time: "2024-01-02T03:04:05.678+00:00".to_string(),
+            service: "my-app".to_string(),
+            event,
+        }
+    }
+
+    fn journal(label: &str) -> (PathBuf, Journal) {
+        let dir = std::env::temp_dir().join(format!("shawl-events-{}-{}", std::process::id(), label));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = Journal::path(dir.to_str().unwrap(), "shawl_for_my-app");
+        (dir, Journal::new(path, "my-app"))
+    }
+
+    fn read(path: &Path) -> Vec<Record> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect()
+    }
+
+    describe "record" {
+        it "is one flat JSON object" {
+            let event = Event::Exited {
+                generation: 2,
+                pid: 123,
+                code: 1,
+                usage: Usage {
+                    duration_ms: 4500,
+                    user_cpu_ms: 1200,
+                    kernel_cpu_ms: 300,
+                    peak_memory_bytes: Some(1048576),
+                },
+                decision: Decision::Restart,
+            };
+            assert_eq!(
+                serde_json::to_string(&record(event)).unwrap(),
+                concat!(
+                    r#"{"time":"2024-01-02T03:04:05.678+00:00","service":"my-app","event":"exited","#,
+                    r#""generation":2,"pid":123,"code":1,"duration_ms":4500,"user_cpu_ms":1200,"kernel_cpu_ms":300,"#,
+                    r#""peak_memory_bytes":1048576,"decision":"restart"}"#,
+                ),
+            );
+        }
+
+        it "handles events without fields" {
+            let json = serde_json::to_string(&record(Event::ServiceStopped {})).unwrap();
+            assert_eq!(
+                json,
+                r#"{"time":"2024-01-02T03:04:05.678+00:00","service":"my-app","event":"service_stopped"}"#,
+            );
+            assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record(Event::ServiceStopped {}));
+        }
+
+        it "round trips" {
+            let original = record(Event::Killed {
+                generation: 1,
+                pid: 5,
+                usage: Usage { duration_ms: 3000, ..Default::default() },
+                decision: Decision::Stop,
+            });
+            let json = serde_json::to_string(&original).unwrap();
+            assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), original);
+        }
+        it "names the limit that was reached" {
+            let event = Event::LimitReached {
+                generation: 1,
+                pid: 5,
+                limit: crate::tree::Limit::Memory,
+                code: None,
+                usage: Usage { duration_ms: 3000, ..Default::default() },
+                decision: Decision::Stop,
+            };
+            let json = serde_json::to_string(&record(event.clone())).unwrap();
+            assert!(json.contains(r#""event":"limit_reached","generation":1,"pid":5,"limit":"memory","duration_ms":3000"#));
+            assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record(event));
+        }
+    }
+
+    describe "journal" {
+        it "names the file after the main log" {
+            let (dir, journal) = journal("names");
+            assert_eq!(journal.path, dir.join("shawl_for_my-app_events.jsonl"));
+            assert_eq!(journal.old_path(), dir.join("shawl_for_my-app_events.old.jsonl"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "appends one line per event" {
+            let (dir, journal) = journal("append");
+            journal.write(&record(Event::ServiceStarted { shawl_pid: 1 }), MAX_BYTES).unwrap();
+            journal.write(&record(Event::Launched { generation: 1, pid: 2 }), MAX_BYTES).unwrap();
+            assert_eq!(
+                read(&journal.path),
+                vec![
+                    record(Event::ServiceStarted { shawl_pid: 1 }),
+                    record(Event::Launched { generation: 1, pid: 2 }),
+                ],
+            );
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "stamps each event with the time and the service" {
+            let (dir, journal) = journal("stamp");
+            journal.record(Event::ServiceStarted { shawl_pid: 1 });
+            let records = read(&journal.path);
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].service, "my-app");
+            assert_eq!(records[0].event, Event::ServiceStarted { shawl_pid: 1 });
+            assert!(chrono::DateTime::parse_from_rfc3339(&records[0].time).is_ok());
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "moves a full journal aside" {
+            let (dir, journal) = journal("full");
+            journal.write(&record(Event::ServiceStarted { shawl_pid: 1 }), 1).unwrap();
+            journal.write(&record(Event::Launched { generation: 1, pid: 2 }), 1).unwrap();
+            journal.write(&record(Event::ServiceStopped {}), 1).unwrap();
+            assert_eq!(read(&journal.old_path()), vec![record(Event::Launched { generation: 1, pid: 2 })]);
+            assert_eq!(read(&journal.path), vec![record(Event::ServiceStopped {})]);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,396 @@
+#[cfg(any(windows, test))]
+use crate::events::Event;
+use crate::{cli::HistoryOpts, ctl::ExitReason, events::Decision, template};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// How many runs to remember unless `--history-size` says otherwise.
+#[cfg(windows)]
+pub const DEFAULT_SIZE: usize = 100;
+
+#[cfg(windows)]
+static GLOBAL: std::sync::OnceLock<History> = std::sync::OnceLock::new();
+
+/// The run history, unless disabled by `--no-log` or `--history-size 0`.
+#[cfg(windows)]
+pub fn global() -> Option<&'static History> {
+    GLOBAL.get()
+}
+
+#[cfg(windows)]
+pub fn install(history: History) -> &'static History {
+    GLOBAL.get_or_init(|| history)
+}
+
+/// Add an event to the global history, if there is one.
+#[cfg(windows)]
+pub fn observe(event: &Event) {
+    if let Some(history) = global() {
+        history.observe(event);
+    }
+}
+
+/// How one run of the command went.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Run {
+    /// Tells apart runs from different starts of the service.
+    pub shawl_pid: u32,
+    pub generation: u64,
+    pub pid: u32,
+    /// RFC 3339 with milliseconds.
+    pub started_at: String,
+    pub ended_at: String,
+    pub reason: ExitReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<Decision>,
+    /// Only for job runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+}
+
+impl std::fmt::Display for Run {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} to {}  #{} (PID {})  {}",
+            self.started_at,
+            self.ended_at,
+            self.generation,
+            self.pid,
+            crate::ctl::outcome(self.reason, self.code, self.decision, self.success)
+        )
+    }
+}
+
+#[cfg(any(windows, test))]
+#[derive(Debug, Default)]
+struct Current {
+    generation: u64,
+    pid: u32,
+    started_at: String,
+}
+
+/// JSON file of the most recent runs, oldest first, which outlives the service.
+#[cfg(any(windows, test))]
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    size: usize,
+    current: std::sync::Mutex<Option<Current>>,
+}
+
+#[cfg(any(windows, test))]
+fn now() -> String {
+    chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false)
+}
+
+#[cfg(any(windows, test))]
+impl History {
+    pub fn new(path: PathBuf, size: usize) -> Self {
+        Self {
+            path,
+            size,
+            current: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn observe(&self, event: &Event) {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        let (reason, code, decision) = match event {
+            Event::Launched { generation, pid } => {
+                *current = Some(Current {
+                    generation: *generation,
+                    pid: *pid,
+                    started_at: now(),
+                });
+                return;
+            }
+            Event::Exited { code, decision, .. } => (ExitReason::Exited, Some(*code), Some(*decision)),
+            Event::Terminated { decision, .. } => (ExitReason::Terminated, None, Some(*decision)),
+            Event::Killed { decision, .. } => (ExitReason::Killed, None, Some(*decision)),
+            Event::LimitReached { code, decision, .. } => (ExitReason::Limit, *code, Some(*decision)),
+            Event::Lost { decision, .. } => (ExitReason::Lost, None, Some(*decision)),
+            Event::TimedOut { generation, .. } => {
+                self.amend(*generation, |run| run.reason = ExitReason::Timeout);
+                return;
+            }
+            Event::JobFinished {
+                generation, success, ..
+            } => {
+                let success = *success;
+                self.amend(*generation, |run| run.success = Some(success));
+                return;
+            }
+            Event::ServiceStarted { .. }
+            | Event::LaunchFailed { .. }
+            | Event::StopRequested { .. }
+            | Event::ThresholdExceeded { .. }
+            | Event::RestartScheduled { .. }
+            | Event::ServiceStopped {} => return,
+        };
+        let Some(current) = current.take() else {
+            return;
+        };
+
+        let run = Run {
+            shawl_pid: std::process::id(),
+            generation: current.generation,
+            pid: current.pid,
+            started_at: current.started_at,
+            ended_at: now(),
+            reason,
+            code,
+            decision,
+            success: None,
+        };
+        if let Err(e) = self.append(run) {
+            log::error!("Unable to update the run history {:?}: {}", &self.path, e);
+        }
+    }
+
+    /// Change a run that was already written when the command ended.
+    fn amend(&self, generation: u64, change: impl FnOnce(&mut Run)) {
+        let result = self.update(|runs| {
+            let run = runs
+                .iter_mut()
+                .rev()
+                .find(|x| x.shawl_pid == std::process::id() && x.generation == generation);
+            if let Some(run) = run {
+                change(run);
+            }
+        });
+        if let Err(e) = result {
+            log::error!("Unable to update the run history {:?}: {}", &self.path, e);
+        }
+    }
+
+    fn append(&self, run: Run) -> std::io::Result<()> {
+        self.update(|runs| {
+            runs.push(run);
+            let excess = runs.len().saturating_sub(self.size);
+            runs.drain(..excess);
+        })
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Vec<Run>)) -> std::io::Result<()> {
+        let mut runs = match read(&self.path) {
+            Ok(runs) => runs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                log::warn!(
+                    "Starting a new run history because {:?} is unreadable: {}",
+                    &self.path,
+                    e
+                );
+                vec![]
+            }
+        };
+        change(&mut runs);
+
+        // Replace the file in one step so that a crash can't leave half of it behind.
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(&runs)?)?;
+        std::fs::rename(&temp, &self.path)
+    }
+}
+
+/// The history for the service with this name.
+pub fn path(dir: &str, name: &str) -> PathBuf {
+    Path::new(dir).join(format!("history_{}.json", name))
+}
+
+pub fn read(path: &Path) -> std::io::Result<Vec<Run>> {
+    let text = std::fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Run `shawl history`.
+pub fn show(opts: &HistoryOpts, out: &mut impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = crate::logs::LogNames::resolve(
+        &opts.name,
+        opts.log_dir.as_deref(),
+        None,
+        None,
+        &template::Vars::any(&opts.name),
+    )?
+    .dir;
+    if dir.contains(template::WILDCARD) {
+        return Err("The log directory depends on when the service started, so please specify it directly".into());
+    }
+
+    let path = path(&dir, &opts.name);
+    let runs = read(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    if opts.json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&runs)?)?;
+    } else {
+        for run in runs {
+            writeln!(out, "{}", run)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn history(label: &str, size: usize) -> (PathBuf, History) {
+        let dir = std::env::temp_dir().join(format!("shawl-history-{}-{}", std::process::id(), label));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = path(dir.to_str().unwrap(), "my-app");
+        (dir, History::new(path, size))
+    }
+
+    fn exited(generation: u64, code: i32) -> Event {
+        Event::Exited {
+            generation,
+            pid: 10 + generation as u32,
+            code,
+            usage: Default::default(),
+            decision: Decision::Restart,
+        }
+    }
+
+    describe "history" {
+        it "is named after the service" {
+            let (dir, history) = history("names", 5);
+            assert_eq!(history.path, dir.join("history_my-app.json"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "records each run" {
+            let (dir, history) = history("records", 5);
+            history.observe(&Event::Launched { generation: 1, pid: 11 });
+            history.observe(&exited(1, 3));
+            history.observe(&Event::Launched { generation: 2, pid: 12 });
+            history.observe(&Event::StopRequested { generation: 2, pid: 12 });
+            history.observe(&Event::Killed {
+                generation: 2,
+                pid: 12,
+                usage: Default::default(),
+                decision: Decision::Stop,
+            });
+
+            let runs = read(&history.path).unwrap();
+            assert_eq!(
+                runs.iter().map(|x| (x.generation, x.pid, x.reason, x.code, x.decision)).collect::<Vec<_>>(),
+                vec![
+                    (1, 11, ExitReason::Exited, Some(3), Some(Decision::Restart)),
+                    (2, 12, ExitReason::Killed, None, Some(Decision::Stop)),
+                ],
+            );
+            assert!(runs.iter().all(|x| x.started_at <= x.ended_at && x.shawl_pid == std::process::id()));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "survives a restart of the service" {
+            let (dir, history) = history("survives", 5);
+            history.observe(&Event::Launched { generation: 1, pid: 11 });
+            history.observe(&exited(1, 1));
+
+            let again = History::new(history.path.clone(), 5);
+            again.observe(&Event::Launched { generation: 1, pid: 21 });
+            again.observe(&exited(1, 2));
+
+            let codes: Vec<_> = read(&history.path).unwrap().iter().map(|x| x.code).collect();
+            assert_eq!(codes, vec![Some(1), Some(2)]);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "keeps only the latest runs" {
+            let (dir, history) = history("caps", 3);
+            for generation in 1..=5 {
+                history.observe(&Event::Launched { generation, pid: 1 });
+                history.observe(&exited(generation, 0));
+            }
+            let generations: Vec<_> = read(&history.path).unwrap().iter().map(|x| x.generation).collect();
+            assert_eq!(generations, vec![3, 4, 5]);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "marks how job runs went" {
+            let (dir, history) = history("jobs", 3);
+            for (generation, code) in [(1, 0), (2, 1)] {
+                history.observe(&Event::Launched { generation, pid: 10 + generation as u32 });
+                history.observe(&Event::Exited {
+                    generation,
+                    pid: 10 + generation as u32,
+                    code,
+                    usage: Default::default(),
+                    decision: Decision::Wait,
+                });
+                history.observe(&Event::JobFinished {
+                    generation,
+                    pid: 10 + generation as u32,
+                    success: code == 0,
+                });
+            }
+
+            let runs = read(&history.path).unwrap();
+            assert_eq!(runs.iter().map(|x| x.success).collect::<Vec<_>>(), vec![Some(true), Some(false)]);
+            assert!(runs[1].to_string().ends_with("  #2 (PID 12)  exited with code 1, failed"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "marks runs that timed out" {
+            let (dir, history) = history("timeouts", 3);
+            history.observe(&Event::Launched { generation: 1, pid: 11 });
+            history.observe(&Event::StopRequested { generation: 1, pid: 11 });
+            history.observe(&exited(1, 1));
+            history.observe(&Event::TimedOut { generation: 1, pid: 11, max_runtime_ms: 7_200_000 });
+
+            let runs = read(&history.path).unwrap();
+            assert_eq!((runs[0].reason, runs[0].code), (ExitReason::Timeout, Some(1)));
+            assert!(runs[0].to_string().ends_with("  #1 (PID 11)  timed out, restarted"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "ignores exits without a launch" {
+            let (dir, history) = history("orphans", 3);
+            history.observe(&exited(1, 0));
+            assert!(!history.path.exists());
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "shows runs oldest first" {
+            let (dir, history) = history("shows", 3);
+            history.observe(&Event::Launched { generation: 1, pid: 11 });
+            history.observe(&exited(1, 3));
+            history.observe(&Event::Launched { generation: 2, pid: 12 });
+            history.observe(&Event::Lost {
+                generation: 2,
+                pid: 12,
+                error: "oops".to_string(),
+                decision: Decision::Stop,
+            });
+
+            let opts = HistoryOpts {
+                name: "my-app".to_string(),
+                log_dir: Some(dir.to_string_lossy().to_string()),
+                json: false,
+            };
+            let mut out = vec![];
+            show(&opts, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let lines: Vec<_> = out.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].ends_with("  #1 (PID 11)  exited with code 3, restarted"));
+            assert!(lines[1].ends_with("  #2 (PID 12)  was lost, stopped"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        it "reports a missing history" {
+            let (dir, _) = history("missing", 3);
+            let opts = HistoryOpts {
+                name: "my-app".to_string(),
+                log_dir: Some(dir.to_string_lossy().to_string()),
+                json: true,
+            };
+            assert!(show(&opts, &mut vec![]).unwrap_err().to_string().starts_with("Unable to read"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,672 @@
+use crate::{
+    cli::{LogTimezone, LogsOpts},
+    template,
+};
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Infix of the file that is currently being written.
+const CURRENT_INFIX: &str = "CURRENT";
+/// Infix of rotated files, which flexi_logger names after their creation time.
+const ROTATED_INFIX_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Directory for the log files: `--log-dir`, or else next to the Shawl executable.
+pub fn log_dir(log_dir: Option<&str>) -> std::io::Result<String> {
+    Ok(crate::simplify_path(&match log_dir {
+        Some(log_dir) => log_dir.to_string(),
+        None => {
+            let mut exe_dir = std::env::current_exe()?;
+            exe_dir.pop();
+            exe_dir.to_string_lossy().to_string()
+        }
+    }))
+}
+
+/// Base name of the main log files: `--log-as`, or else the name of the
+/// executable (like flexi_logger's default) followed by `_for_<name>`.
+pub fn main_basename(name: &str, log_as: Option<&str>) -> String {
+    match log_as {
+        Some(log_as) => log_as.to_string(),
+        None => {
+            let arg0 = std::env::args().next().unwrap_or_default();
+            let exe = Path::new(&arg0)
+                .file_stem()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_else(|| "shawl".to_string());
+            format!("{}_for_{}", exe, name)
+        }
+    }
+}
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+/// Older versions of Shawl didn't include milliseconds.
+const LEGACY_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const RFC3339_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+#[cfg(windows)]
+static TIMESTAMPS: std::sync::OnceLock<Timestamps> = std::sync::OnceLock::new();
+
+/// How timestamps are written in the logs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timestamps {
+    format: String,
+    timezone: LogTimezone,
+}
+
+impl Default for Timestamps {
+    fn default() -> Self {
+        Self::new(None, LogTimezone::Local)
+    }
+}
+
+impl Timestamps {
+    pub fn new(format: Option<&str>, timezone: LogTimezone) -> Self {
+        let format = match format {
+            None => DEFAULT_TIMESTAMP_FORMAT,
+            Some("rfc3339") => RFC3339_FORMAT,
+            Some(format) => format,
+        };
+        Self {
+            format: format.to_string(),
+            timezone,
+        }
+    }
+
+    /// Use these settings for the rest of the program.
+    /// The log formatters can't hold any state of their own.
+    #[cfg(windows)]
+    pub fn install(self) {
+        let _ = TIMESTAMPS.set(self);
+    }
+
+    #[cfg(windows)]
+    pub fn installed() -> &'static Self {
+        TIMESTAMPS.get_or_init(Self::default)
+    }
+
+    #[cfg(any(windows, test))]
+    pub fn format(&self, time: &chrono::DateTime<chrono::Local>) -> String {
+        match self.timezone {
+            LogTimezone::Local => time.format(&self.format).to_string(),
+            LogTimezone::Utc => time.with_timezone(&chrono::Utc).format(&self.format).to_string(),
+        }
+    }
+
+    /// Parse the timestamp at the start of a line in the main log.
+    /// This also understands the default and RFC 3339 formats,
+    /// in case the settings changed since the line was written.
+    pub fn parse(&self, line: &str) -> Option<chrono::DateTime<chrono::Local>> {
+        if line.starts_with(char::is_whitespace) {
+            return None;
+        }
+        [
+            self.format.as_str(),
+            DEFAULT_TIMESTAMP_FORMAT,
+            LEGACY_TIMESTAMP_FORMAT,
+            RFC3339_FORMAT,
+        ]
+        .into_iter()
+        .find_map(|format| self.parse_with(line, format))
+    }
+
+    fn parse_with(&self, line: &str, format: &str) -> Option<chrono::DateTime<chrono::Local>> {
+        use chrono::TimeZone;
+
+        // The timestamp takes up as many words as it does when formatted.
+        let words = chrono::Local::now()
+            .format(format)
+            .to_string()
+            .split_whitespace()
+            .count();
+        let mut end = 0;
+        for _ in 0..words.max(1) {
+            let start = end + line[end..].find(|c: char| !c.is_whitespace())?;
+            end = line[start..]
+                .find(char::is_whitespace)
+                .map_or(line.len(), |x| start + x);
+        }
+        let text = &line[..end];
+
+        if let Ok(time) = chrono::DateTime::parse_from_str(text, format) {
+            return Some(time.with_timezone(&chrono::Local));
+        }
+        let time = chrono::NaiveDateTime::parse_from_str(text, format).ok()?;
+        match self.timezone {
+            LogTimezone::Local => chrono::Local.from_local_datetime(&time).earliest(),
+            LogTimezone::Utc => Some(chrono::Utc.from_utc_datetime(&time).with_timezone(&chrono::Local)),
+        }
+    }
+}
+
+/// Where a service's logs go, with any placeholders filled in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogNames {
+    pub dir: String,
+    pub main: String,
+    pub cmd: Option<String>,
+}
+
+impl LogNames {
+    pub fn resolve(
+        name: &str,
+        dir: Option<&str>,
+        log_as: Option<&str>,
+        log_cmd_as: Option<&str>,
+        vars: &template::Vars,
+    ) -> Result<Self, String> {
+        let expand = |x: &str| template::expand(x, vars);
+        Ok(Self {
+            dir: log_dir(dir.map(expand).transpose()?.as_deref()).map_err(|e| e.to_string())?,
+            main: main_basename(name, log_as.map(expand).transpose()?.as_deref()),
+            cmd: log_cmd_as.map(expand).transpose()?,
+        })
+    }
+}
+
+/// Find the log files with the given base name, oldest first.
+/// Rotated files look like `<basename>_r2024-01-02_03-04-05.log`,
+/// possibly with `.restart-0001` before the extension or `.gz` after it,
+/// and the file that is still being written is `<basename>_rCURRENT.log`.
+/// The base name may contain `template::WILDCARD`, in which case there may be
+/// several current files, and the most recently modified one comes last.
+pub fn log_files(dir: &Path, basename: &str) -> std::io::Result<Vec<PathBuf>> {
+    let basename = basename
+        .split(template::WILDCARD)
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+    let pattern = regex::Regex::new(&format!(
+        r"^{}_r({}|\d{{4}}-\d{{2}}-\d{{2}}_\d{{2}}-\d{{2}}-\d{{2}}(\.restart-\d+)?)\.log(\.gz)?$",
+        basename, CURRENT_INFIX
+    ))
+    .unwrap();
+    let mut rotated = vec![];
+    let mut current = vec![];
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|x| x.to_str()) else {
+            continue;
+        };
+        let Some(infix) = pattern.captures(file_name).and_then(|x| x.get(1)) else {
+            continue;
+        };
+
+        if infix.as_str() == CURRENT_INFIX && !file_name.ends_with(".gz") {
+            let modified = std::fs::metadata(&path).and_then(|x| x.modified()).ok();
+            current.push((modified, path));
+        } else if let Some(key) = rotation_key(infix.as_str()) {
+            rotated.push((key, path));
+        }
+    }
+
+    rotated.sort();
+    current.sort();
+    Ok(rotated
+        .into_iter()
+        .map(|(_, path)| path)
+        .chain(current.into_iter().map(|(_, path)| path))
+        .collect())
+}
+
+fn rotation_key(infix: &str) -> Option<(chrono::NaiveDateTime, u32)> {
+    let (timestamp, restart) = match infix.split_once(".restart-") {
+        Some((timestamp, restart)) => (timestamp, restart.parse::<u32>().ok()? + 1),
+        None => (infix, 0),
+    };
+    let timestamp = chrono::NaiveDateTime::parse_from_str(timestamp, ROTATED_INFIX_FORMAT).ok()?;
+    Some((timestamp, restart))
+}
+
+fn is_current(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| x.ends_with(&format!("_r{}.log", CURRENT_INFIX)))
+}
+
+fn open(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let file = std::fs::File::open(path)?;
+    if path.extension().is_some_and(|x| x == "gz") {
+        Ok(Box::new(std::io::BufReader::new(flate2::read::GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(std::io::BufReader::new(file)))
+    }
+}
+
+/// Pass each line after `offset` to `emit`, and return the new offset.
+/// With `complete_only`, a final line without a newline is left for next time,
+/// since the service may still be in the middle of writing it.
+fn read_lines(path: &Path, offset: u64, complete_only: bool, mut emit: impl FnMut(&str)) -> std::io::Result<u64> {
+    let mut reader = open(path)?;
+    let skipped = std::io::copy(&mut (&mut reader).take(offset), &mut std::io::sink())?;
+    let mut offset = skipped;
+    let mut buffer = vec![];
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 || (complete_only && buffer.last() != Some(&b'\n')) {
+            return Ok(offset);
+        }
+        offset += read as u64;
+        let line = String::from_utf8_lossy(&buffer);
+        emit(line.trim_end_matches(['\r', '\n']));
+    }
+}
+
+/// Whether a line in the main log is the command's output, like
+/// `2024-01-02 03:04:05.678 [DEBUG] #12 stdout: "hello"`.
+fn is_cmd_output(line: &str) -> bool {
+    let Some((_, message)) = line.split_once("] ") else {
+        return false;
+    };
+    let message = match message.strip_prefix('#') {
+        Some(rest) => rest.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start(),
+        None => message,
+    };
+    message.starts_with("stdout: ") || message.starts_with("stderr: ")
+}
+
+/// Decides which lines to show. A line without its own timestamp,
+/// like the rest of a multi-line message, goes along with the one before it.
+#[derive(Debug)]
+pub struct Filter {
+    timestamps: Timestamps,
+    since: Option<chrono::DateTime<chrono::Local>>,
+    grep: Option<regex::Regex>,
+    cmd: bool,
+    in_range: bool,
+}
+
+impl Filter {
+    pub fn new(
+        timestamps: Timestamps,
+        since: Option<chrono::DateTime<chrono::Local>>,
+        grep: Option<regex::Regex>,
+        cmd: bool,
+    ) -> Self {
+        Self {
+            timestamps,
+            since,
+            grep,
+            cmd,
+            in_range: true,
+        }
+    }
+
+    pub fn matches(&mut self, line: &str) -> bool {
+        if let Some(timestamp) = self.timestamps.parse(line) {
+            self.in_range = self.since.is_none_or(|since| timestamp >= since) && (!self.cmd || is_cmd_output(line));
+        }
+        self.in_range && self.grep.as_ref().is_none_or(|grep| grep.is_match(line))
+    }
+}
+
+/// Reads a set of log files in order and then keeps up with new lines,
+/// including when the current file is rotated.
+#[derive(Debug)]
+pub struct Follower {
+    dir: PathBuf,
+    basename: String,
+    /// Skip files that haven't been modified since this time.
+    since: Option<std::time::SystemTime>,
+    seen: std::collections::HashSet<PathBuf>,
+    offset: u64,
+}
+
+impl Follower {
+    pub fn new(dir: PathBuf, basename: String, since: Option<std::time::SystemTime>) -> Self {
+        Self {
+            dir,
+            basename,
+            since,
+            seen: Default::default(),
+            offset: 0,
+        }
+    }
+
+    /// Pass every line that hasn't been read yet to `emit`.
+    pub fn poll(&mut self, complete_only: bool, mut emit: impl FnMut(&str)) -> std::io::Result<()> {
+        let files = log_files(&self.dir, &self.basename)?;
+        let active = files.last().filter(|x| is_current(x));
+
+        let new: Vec<_> = files
+            .iter()
+            .filter(|x| Some(*x) != active && !self.seen.contains(*x))
+            .collect();
+        let mut rotated = new.into_iter();
+        // The oldest new rotated file is the one that we were reading as the current file.
+        if let Some(previous) = rotated.next() {
+            if self.should_read(previous) {
+                read_lines(previous, self.offset, false, &mut emit)?;
+            }
+            self.seen.insert(previous.clone());
+            self.offset = 0;
+        }
+        for path in rotated {
+            if self.should_read(path) {
+                read_lines(path, 0, false, &mut emit)?;
+            }
+            self.seen.insert(path.clone());
+        }
+
+        if let Some(current) = active {
+            if std::fs::metadata(current).is_ok_and(|x| x.len() < self.offset) {
+                self.offset = 0;
+            }
+            if self.offset > 0 || self.should_read(current) {
+                self.offset = read_lines(current, self.offset, complete_only, &mut emit)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn should_read(&self, path: &Path) -> bool {
+        match self.since {
+            Some(since) => std::fs::metadata(path)
+                .and_then(|x| x.modified())
+                .map_or(true, |modified| modified >= since),
+            None => true,
+        }
+    }
+}
+
+/// Print the logs for `shawl logs`.
+pub fn show(opts: &LogsOpts, out: &mut impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let names = LogNames::resolve(
+        &opts.name,
+        opts.log_dir.as_deref(),
+        opts.log_as.as_deref(),
+        opts.log_cmd_as.as_deref(),
+        &template::Vars::any(&opts.name),
+    )?;
+    if names.dir.contains(template::WILDCARD) {
+        return Err("The log directory depends on when the service started, so please specify it directly".into());
+    }
+    let dir = PathBuf::from(names.dir);
+    // The separate command log only has the raw output, without timestamps.
+    let (basename, raw) = match (names.cmd, opts.cmd) {
+        (Some(cmd), true) => (cmd, true),
+        _ => (names.main, false),
+    };
+
+    if log_files(&dir, &basename)?.is_empty() && !opts.follow {
+        return Err(format!("No log files named {}_r*.log in {}", basename, dir.display()).into());
+    }
+
+    // A time before the clocks can count back to leaves everything in.
+    let since = opts.since.and_then(|x| std::time::SystemTime::now().checked_sub(x));
+    let since_time = opts
+        .since
+        .and_then(|x| chrono::Duration::from_std(x).ok())
+        .and_then(|x| chrono::Local::now().checked_sub_signed(x));
+    let mut filter = Filter::new(
+        Timestamps::new(opts.log_timestamp.as_deref(), opts.log_timezone.unwrap_or_default()),
+        since_time.filter(|_| !raw),
+        opts.grep.as_ref().and_then(|x| regex::Regex::new(x).ok()),
+        opts.cmd && !raw,
+    );
+    let mut follower = Follower::new(dir, basename, since);
+
+    let mut complete_only = opts.follow;
+    loop {
+        let mut lines = vec![];
+        follower.poll(complete_only, |line| {
+            if filter.matches(line) {
+                lines.push(line.to_string());
+            }
+        })?;
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()?;
+
+        if !opts.follow {
+            return Ok(());
+        }
+        complete_only = true;
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shawl-logs-{}-{}", std::process::id(), label));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, content: &str) {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+
+    fn write_gz(dir: &Path, name: &str, content: &str) {
+        let file = std::fs::File::create(dir.join(name)).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        files.iter().map(|x| x.file_name().unwrap().to_string_lossy().to_string()).collect()
+    }
+
+    fn collect(follower: &mut Follower) -> Vec<String> {
+        let mut lines = vec![];
+        follower.poll(true, |line| lines.push(line.to_string())).unwrap();
+        lines
+    }
+
+    describe "log_files" {
+        it "orders rotated files by time with the current file last" {
+            let dir = temp_dir("order");
+            write(&dir, "shawl_for_foo_rCURRENT.log", "");
+            write(&dir, "shawl_for_foo_r2024-01-02_10-00-00.restart-0000.log", "");
+            write_gz(&dir, "shawl_for_foo_r2024-01-02_09-00-00.log.gz", "");
+            write(&dir, "shawl_for_foo_r2024-01-02_10-00-00.log", "");
+            write(&dir, "shawl_for_foo_r2023-12-31_23-59-59.log", "");
+            write(&dir, "shawl_for_foobar_rCURRENT.log", "");
+            write(&dir, "shawl_for_foo_rCURRENT.txt", "");
+
+            assert_eq!(
+                names(log_files(&dir, "shawl_for_foo").unwrap()),
+                vec![
+                    "shawl_for_foo_r2023-12-31_23-59-59.log",
+                    "shawl_for_foo_r2024-01-02_09-00-00.log.gz",
+                    "shawl_for_foo_r2024-01-02_10-00-00.log",
+                    "shawl_for_foo_r2024-01-02_10-00-00.restart-0000.log",
+                    "shawl_for_foo_rCURRENT.log",
+                ],
+            );
+        }
+    }
+
+    describe "timestamps" {
+        it "formats in local time by default" {
+            let time = chrono::Local::now();
+            assert_eq!(Timestamps::default().format(&time), time.format("%Y-%m-%d %H:%M:%S%.3f").to_string());
+        }
+
+        it "formats RFC 3339 in UTC" {
+            let time = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678+02:00")
+                .unwrap()
+                .with_timezone(&chrono::Local);
+            assert_eq!(
+                Timestamps::new(Some("rfc3339"), LogTimezone::Utc).format(&time),
+                "2024-01-02T01:04:05.678+00:00",
+            );
+        }
+
+        it "formats a custom pattern" {
+            let time = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678+00:00")
+                .unwrap()
+                .with_timezone(&chrono::Local);
+            assert_eq!(Timestamps::new(Some("%d/%m/%Y %H:%M"), LogTimezone::Utc).format(&time), "02/01/2024 03:04");
+        }
+    }
+
+    describe "log_files with placeholders" {
+        it "matches any value for placeholders that vary between runs" {
+            let dir = temp_dir("wildcard");
+            write(&dir, "app_101_r2024-01-01_00-00-00.log", "");
+            write(&dir, "app_101_rCURRENT.log", "");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            write(&dir, "app_102_rCURRENT.log", "");
+            write(&dir, "other_102_rCURRENT.log", "");
+
+            assert_eq!(
+                names(log_files(&dir, "app_*").unwrap()),
+                vec!["app_101_r2024-01-01_00-00-00.log", "app_101_rCURRENT.log", "app_102_rCURRENT.log"],
+            );
+        }
+    }
+
+    describe "filter" {
+        it "selects lines since a time" {
+            let since = local("2024-01-02 03:04:05");
+            let mut filter = Filter::new(Timestamps::default(), Some(since), None, false);
+            assert!(!filter.matches("2024-01-02 03:04:04.999 [INFO] old"));
+            assert!(!filter.matches("  continuation of old"));
+            assert!(filter.matches("2024-01-02 03:04:05.000 [INFO] new"));
+            assert!(filter.matches("  continuation of new"));
+            assert!(filter.matches("2024-01-02 03:05:00 [INFO] from an older version"));
+        }
+
+        it "understands RFC 3339 timestamps" {
+            let since = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z").unwrap();
+            let mut filter = Filter::new(Timestamps::default(), Some(since.with_timezone(&chrono::Local)), None, false);
+            assert!(!filter.matches("2024-01-02T05:04:04.999+02:00 [INFO] old"));
+            assert!(filter.matches("2024-01-02T03:04:05.000+00:00 [INFO] new"));
+        }
+
+        it "understands a custom format" {
+            let timestamps = Timestamps::new(Some("%d/%m/%Y %H:%M:%S"), LogTimezone::Local);
+            let mut filter = Filter::new(timestamps, Some(local("2024-01-02 03:04:05")), None, true);
+            assert!(!filter.matches("02/01/2024 03:04:04 [DEBUG] #1 stdout: \"old\""));
+            assert!(!filter.matches("  continuation of old"));
+            assert!(!filter.matches("02/01/2024 03:04:05 [INFO] Launching command"));
+            assert!(filter.matches("02/01/2024 03:04:05 [DEBUG] #1 stdout: \"new\""));
+            assert!(filter.matches("  continuation of new"));
+        }
+
+        it "understands UTC timestamps" {
+            let since = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z").unwrap();
+            let timestamps = Timestamps::new(None, LogTimezone::Utc);
+            let mut filter = Filter::new(timestamps, Some(since.with_timezone(&chrono::Local)), None, false);
+            assert!(!filter.matches("2024-01-02 03:04:04.999 [INFO] old"));
+            assert!(filter.matches("2024-01-02 03:04:05.000 [INFO] new"));
+        }
+
+        it "selects the command's output" {
+            let mut filter = Filter::new(Timestamps::default(), None, None, true);
+            assert!(!filter.matches("2024-01-02 03:04:05.000 [INFO] Launching command"));
+            assert!(filter.matches("2024-01-02 03:04:05.000 [DEBUG] #1 stdout: \"hi\""));
+            assert!(filter.matches("2024-01-02 03:04:05.000 [DEBUG] stderr: \"from an older version\""));
+            assert!(filter.matches("2024-01-02 03:04:05.000 [DEBUG] stdout: last line repeated 3 times"));
+        }
+
+        it "selects lines matching a pattern" {
+            let mut filter = Filter::new(Timestamps::default(), None, Some(regex::Regex::new("(?i)error").unwrap()), false);
+            assert!(filter.matches("2024-01-02 03:04:05.000 [ERROR] Error!"));
+            assert!(!filter.matches("2024-01-02 03:04:05.000 [INFO] Fine"));
+        }
+    }
+
+    describe "follower" {
+        it "reads rotated and compressed files in order" {
+            let dir = temp_dir("read");
+            write_gz(&dir, "shawl_for_foo_r2024-01-01_00-00-00.log.gz", "1\n2\n");
+            write(&dir, "shawl_for_foo_r2024-01-02_00-00-00.log", "3\n");
+            write(&dir, "shawl_for_foo_rCURRENT.log", "4\n5");
+
+            let mut follower = Follower::new(dir.clone(), s("shawl_for_foo"), None);
+            assert_eq!(collect(&mut follower), vec!["1", "2", "3", "4"]);
+        }
+
+        it "keeps up with new lines and rotations" {
+            let dir = temp_dir("follow");
+            write(&dir, "shawl_for_foo_rCURRENT.log", "1\n");
+            let mut follower = Follower::new(dir.clone(), s("shawl_for_foo"), None);
+            assert_eq!(collect(&mut follower), vec!["1"]);
+            assert!(collect(&mut follower).is_empty());
+
+            write(&dir, "shawl_for_foo_rCURRENT.log", "1\n2\n3");
+            assert_eq!(collect(&mut follower), vec!["2"]);
+
+            write(&dir, "shawl_for_foo_rCURRENT.log", "1\n2\n3\n4\n");
+            std::fs::rename(
+                dir.join("shawl_for_foo_rCURRENT.log"),
+                dir.join("shawl_for_foo_r2024-01-02_00-00-00.log"),
+            )
+            .unwrap();
+            write(&dir, "shawl_for_foo_rCURRENT.log", "5\n");
+            assert_eq!(collect(&mut follower), vec!["3", "4", "5"]);
+        }
+
+        it "skips files that are older than --since" {
+            let dir = temp_dir("since");
+            write(&dir, "shawl_for_foo_r2024-01-01_00-00-00.log", "old\n");
+            let since = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+            let mut follower = Follower::new(dir.clone(), s("shawl_for_foo"), Some(since));
+            assert!(collect(&mut follower).is_empty());
+        }
+    }
+
+    describe "show" {
+        it "finds the logs for a service by name" {
+            let dir = temp_dir("show");
+            write(&dir, &format!("{}_rCURRENT.log", main_basename("foo", None)), "2024-01-02 03:04:05.000 [INFO] hi\n");
+            write(&dir, "custom_rCURRENT.log", "2024-01-02 03:04:05.000 [DEBUG] #1 stdout: \"custom\"\n");
+            write(&dir, "cmd_rCURRENT.log", "raw output\n");
+
+            let run = |opts: LogsOpts| {
+                let mut out = vec![];
+                show(&LogsOpts { log_dir: Some(dir.to_string_lossy().to_string()), ..opts }, &mut out).unwrap();
+                String::from_utf8(out).unwrap()
+            };
+            assert_eq!(run(LogsOpts { name: s("foo"), ..Default::default() }), "2024-01-02 03:04:05.000 [INFO] hi\n");
+            assert_eq!(
+                run(LogsOpts { log_as: Some(s("custom")), cmd: true, ..Default::default() }),
+                "2024-01-02 03:04:05.000 [DEBUG] #1 stdout: \"custom\"\n",
+            );
+            assert_eq!(
+                run(LogsOpts { log_cmd_as: Some(s("cmd")), cmd: true, ..Default::default() }),
+                "raw output\n",
+            );
+        }
+
+        it "shows everything for --since beyond the start of time" {
+            let dir = temp_dir("since-forever");
+            write(&dir, "foo_rCURRENT.log", "2024-01-02 03:04:05.000 [INFO] hi\n");
+            for days in [999_999_999_999, 99_999_999_999_999] {
+                let opts = LogsOpts {
+                    log_dir: Some(dir.to_string_lossy().to_string()),
+                    log_as: Some(s("foo")),
+                    since: Some(std::time::Duration::from_secs(days * 24 * 60 * 60)),
+                    ..Default::default()
+                };
+                let mut out = vec![];
+                show(&opts, &mut out).unwrap();
+                assert_eq!(String::from_utf8(out).unwrap(), "2024-01-02 03:04:05.000 [INFO] hi\n");
+            }
+        }
+
+        it "reports when there are no logs" {
+            let dir = temp_dir("missing");
+            let opts = LogsOpts { name: s("foo"), log_dir: Some(dir.to_string_lossy().to_string()), ..Default::default() };
+            assert!(show(&opts, &mut vec![]).is_err());
+        }
+    }
+
+    fn local(text: &str) -> chrono::DateTime<chrono::Local> {
+        use chrono::TimeZone;
+        let time = chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap();
+        chrono::Local.from_local_datetime(&time).unwrap()
+    }
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+}
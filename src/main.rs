@@ -1,9 +1,34 @@
+// Only `shawl logs`, `shawl history`, `shawl status`, and `shawl ctl` are available on other platforms.
+// The modules that only the service uses are still built there for their tests.
+
 mod cli;
+#[cfg(any(windows, test))]
 mod control;
+mod ctl;
+mod events;
+mod history;
+mod logs;
+#[cfg(any(windows, test))]
+mod metrics;
+#[cfg(any(windows, test))]
+mod output;
+#[cfg(any(windows, test))]
+mod redact;
+mod schedule;
 #[cfg(windows)]
 mod service;
+#[cfg(any(windows, test))]
+mod syslog;
+mod template;
+#[cfg(any(windows, test))]
+mod tree;
+#[cfg(any(windows, test))]
+mod usage;
+#[cfg(any(windows, test))]
+mod watchdog;
 
 use crate::cli::{evaluate_cli, Subcommand};
+#[cfg(windows)]
 use log::{debug, error};
 
 /// Simplify local UNC paths since some programs (notably cmd.exe) don't like them.
@@ -13,67 +38,127 @@ pub fn simplify_path(path: &str) -> String {
         .to_string()
 }
 
-fn prepare_logging(
-    name: &str,
-    log_dir: Option<&String>,
-    console: bool,
-    rotation: cli::LogRotation,
-    retention: usize,
-    log_as: Option<&String>,
-    log_cmd_as: Option<&String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut exe_dir = std::env::current_exe()?;
-    exe_dir.pop();
-
-    let log_dir = simplify_path(&match log_dir {
-        Some(log_dir) => log_dir.to_string(),
-        None => exe_dir.to_string_lossy().to_string(),
-    });
-
-    let rotation = match rotation {
+#[cfg(windows)]
+/// Command output carries the time when it was read (in milliseconds since the epoch),
+/// which may be a little earlier than when it reaches the log.
+fn record_time(now: &mut flexi_logger::DeferredNow, record: &log::Record) -> chrono::DateTime<chrono::Local> {
+    record
+        .key_values()
+        .get(log::kv::Key::from("read_at"))
+        .and_then(|x| x.to_i64())
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|x| x.with_timezone(&chrono::Local))
+        .unwrap_or(*now.now())
+}
+
+#[cfg(windows)]
+fn format_log_line(
+    w: &mut dyn std::io::Write,
+    now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let timestamp = logs::Timestamps::installed().format(&record_time(now, record));
+    write!(w, "{} [{}] {}", timestamp, record.level(), &record.args())
+}
+
+#[cfg(windows)]
+fn format_cmd_line(
+    w: &mut dyn std::io::Write,
+    _now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    write!(w, "{}", &record.args())
+}
+
+#[cfg(windows)]
+fn format_cmd_line_with_timestamp(
+    w: &mut dyn std::io::Write,
+    now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let timestamp = logs::Timestamps::installed().format(&record_time(now, record));
+    write!(w, "{} {}", timestamp, &record.args())
+}
+
+#[cfg(windows)]
+static LOGGER: std::sync::OnceLock<flexi_logger::LoggerHandle> = std::sync::OnceLock::new();
+
+#[cfg(windows)]
+/// Start new log files now, as if the rotation limit had been reached.
+pub fn rotate_logs() -> Result<(), String> {
+    match LOGGER.get() {
+        Some(logger) => logger.trigger_rotation().map_err(|e| e.to_string()),
+        None => Err("logging is disabled".to_string()),
+    }
+}
+
+#[cfg(windows)]
+fn prepare_logging(name: &str, console: bool, opts: &cli::CommonOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let names = logs::LogNames::resolve(
+        name,
+        opts.log_dir.as_deref(),
+        opts.log_as.as_deref(),
+        opts.log_cmd_as.as_deref(),
+        &template::Vars::current(name),
+    )?;
+    std::fs::create_dir_all(&names.dir)?;
+    logs::Timestamps::new(opts.log_timestamp.as_deref(), opts.log_timezone.unwrap_or_default()).install();
+    if !opts.no_log_events {
+        events::install(events::Journal::new(
+            events::Journal::path(&names.dir, &names.main),
+            name,
+        ));
+    }
+    let history_size = opts.history_size.unwrap_or(history::DEFAULT_SIZE);
+    if history_size > 0 {
+        history::install(history::History::new(history::path(&names.dir, name), history_size));
+    }
+    let retention = opts.log_retain.unwrap_or(2);
+
+    let rotation = match opts.log_rotate.unwrap_or_default() {
         cli::LogRotation::Bytes(bytes) => flexi_logger::Criterion::Size(bytes),
         cli::LogRotation::Daily => flexi_logger::Criterion::Age(flexi_logger::Age::Day),
         cli::LogRotation::Hourly => flexi_logger::Criterion::Age(flexi_logger::Age::Hour),
     };
 
-    let mut logger = flexi_logger::Logger::try_with_env_or_str("debug")?
-        .log_to_file({
-            let spec = flexi_logger::FileSpec::default().directory(log_dir.clone());
+    let file_spec = flexi_logger::FileSpec::default()
+        .directory(&names.dir)
+        .basename(&names.main);
 
-            if let Some(log_as) = log_as {
-                spec.basename(log_as)
-            } else {
-                spec.discriminant(format!("for_{}", name))
-            }
-        })
+    let mut logger = flexi_logger::Logger::try_with_env_or_str("debug")?;
+    logger = match &opts.syslog {
+        Some(target) => {
+            let syslog = syslog::install(syslog::Syslog::start(syslog::SyslogOpts {
+                target: target.clone(),
+                facility: opts.syslog_facility.unwrap_or_default(),
+                app_name: name.to_string(),
+                buffer: opts.syslog_buffer.unwrap_or(1000),
+            }));
+            logger.log_to_file_and_writer(file_spec, Box::new(syslog::LifecycleWriter(syslog)))
+        }
+        None => logger.log_to_file(file_spec),
+    };
+    logger = logger
         .append()
         .rotate(
             rotation,
             flexi_logger::Naming::Timestamps,
             flexi_logger::Cleanup::KeepLogFiles(retention),
         )
-        .format_for_files(|w, now, record| {
-            write!(
-                w,
-                "{} [{}] {}",
-                now.now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                &record.args()
-            )
-        })
+        .format_for_files(format_log_line)
         .format_for_stderr(|w, _now, record| write!(w, "[{}] {}", record.level(), &record.args()));
 
     if console {
         logger = logger.duplicate_to_stderr(flexi_logger::Duplicate::Info);
     }
 
-    if let Some(log_cmd_as) = log_cmd_as {
+    if let Some(log_cmd_as) = &names.cmd {
         logger = logger.add_writer(
             "shawl-cmd",
             Box::new(
                 flexi_logger::writers::FileLogWriter::builder(
                     flexi_logger::FileSpec::default()
-                        .directory(log_dir)
+                        .directory(&names.dir)
                         .basename(log_cmd_as),
                 )
                 .append()
@@ -82,13 +167,33 @@ fn prepare_logging(
                     flexi_logger::Naming::Timestamps,
                     flexi_logger::Cleanup::KeepLogFiles(retention),
                 )
-                .format(|w, _now, record| write!(w, "{}", &record.args()))
+                .format(if opts.log_cmd_timestamp {
+                    format_cmd_line_with_timestamp
+                } else {
+                    format_cmd_line
+                })
                 .try_build()?,
             ),
         );
     }
 
-    logger.start()?;
+    let _ = LOGGER.set(logger.start()?);
+    Ok(())
+}
+
+fn show_status(opts: &cli::StatusOpts) -> Result<(), Box<dyn std::error::Error>> {
+    if let Err(e) = ctl::show_status(opts, &mut std::io::stdout().lock()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_ctl(opts: &cli::CtlOpts) -> Result<(), Box<dyn std::error::Error>> {
+    if let Err(e) = ctl::run(opts, &mut std::io::stdout().lock()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
     Ok(())
 }
 
@@ -97,27 +202,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = evaluate_cli();
     let console = !matches!(cli.sub, Subcommand::Run { .. });
 
-    let should_log = match cli.clone().sub {
-        Subcommand::Add { common: opts, .. } => !opts.no_log,
-        Subcommand::Run { common: opts, .. } => !opts.no_log,
+    let (name, common) = match &cli.sub {
+        Subcommand::Add { name, common, .. } | Subcommand::Run { name, common, .. } => (name, common),
+        Subcommand::Logs { opts } => return logs::show(opts, &mut std::io::stdout().lock()),
+        Subcommand::History { opts } => return history::show(opts, &mut std::io::stdout().lock()),
+        Subcommand::Status { opts } => return show_status(opts),
+        Subcommand::Ctl { opts } => return run_ctl(opts),
     };
-    if should_log {
-        let (name, common) = match &cli.sub {
-            Subcommand::Add { name, common, .. } | Subcommand::Run { name, common, .. } => (name, common),
-        };
-        prepare_logging(
-            name,
-            common.log_dir.as_ref(),
-            console,
-            common.log_rotate.unwrap_or_default(),
-            common.log_retain.unwrap_or(2),
-            common.log_as.as_ref(),
-            common.log_cmd_as.as_ref(),
-        )?;
+    if !common.no_log {
+        prepare_logging(name, console, common)?;
     }
 
     debug!("********** LAUNCH **********");
-    debug!("{:?}", cli);
+    let redactor = redact::Redactor::new(&common.redact_env, &common.redact);
+    debug!("{}", redactor.redact_cli(&cli));
 
     match cli.sub {
         Subcommand::Add {
@@ -140,12 +238,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 std::process::exit(1)
             }
         },
+        Subcommand::Logs { .. } | Subcommand::History { .. } | Subcommand::Status { .. } | Subcommand::Ctl { .. } => {
+            unreachable!()
+        }
     }
     debug!("Finished successfully");
+    if let Some(syslog) = syslog::global() {
+        syslog.flush(std::time::Duration::from_secs(2));
+    }
     Ok(())
 }
 
 #[cfg(not(windows))]
-fn main() {
-    panic!("This program is only intended to run on Windows.");
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match evaluate_cli().sub {
+        Subcommand::Logs { opts } => logs::show(&opts, &mut std::io::stdout().lock()),
+        Subcommand::History { opts } => history::show(&opts, &mut std::io::stdout().lock()),
+        Subcommand::Status { opts } => show_status(&opts),
+        Subcommand::Ctl { opts } => run_ctl(&opts),
+        _ => panic!("This program is only intended to run on Windows."),
+    }
 }
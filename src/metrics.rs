@@ -0,0 +1,610 @@
+use crate::{
+    events::{Decision, Event},
+    output,
+};
+use std::{
+    fmt::Write as _,
+    io::{BufRead, Write},
+};
+
+static GLOBAL: std::sync::OnceLock<Metrics> = std::sync::OnceLock::new();
+
+/// The metrics, if enabled by `--metrics-listen`.
+pub fn global() -> Option<&'static Metrics> {
+    GLOBAL.get()
+}
+
+#[cfg(windows)]
+pub fn install(metrics: Metrics) -> &'static Metrics {
+    GLOBAL.get_or_init(|| metrics)
+}
+
+/// Why Shawl restarted the command, going by how its last run ended.
+/// `threshold` covers `--max-memory` and `--max-cpu`, `scheduled` covers `--restart-every` and `--restart-at`,
+/// and `requested` covers `shawl ctl restart-child`.
+const RESTART_REASONS: [&str; 9] = [
+    "exited",
+    "terminated",
+    "killed",
+    "lost",
+    "limit",
+    "timeout",
+    "threshold",
+    "scheduled",
+    "requested",
+];
+
+fn reason_index(reason: &str) -> usize {
+    RESTART_REASONS.iter().position(|x| *x == reason).unwrap_or(0)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    restarts: [u64; RESTART_REASONS.len()],
+    /// Why the last run ended, as an index into `RESTART_REASONS`.
+    ended_by: usize,
+    /// Whether Shawl decided to restart the command when its last run ended.
+    restarting: bool,
+    stopping: bool,
+    generation: u64,
+    launched_at: Option<std::time::Instant>,
+    last_exit_code: Option<i32>,
+    user_cpu_ms: u64,
+    kernel_cpu_ms: u64,
+    peak_memory_bytes: Option<u64>,
+    /// Indexed by `stream_index`.
+    lines: [u64; 2],
+    bytes: [u64; 2],
+    dropped_lines: u64,
+    suppressed_lines: u64,
+    /// Succeeded, then failed.
+    job_runs: [u64; 2],
+    /// What the current run is using, checked every second.
+    current: Option<crate::tree::Sample>,
+}
+
+/// Counters for `--metrics-listen`, kept up to date by the service loop.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: std::sync::Mutex<State>,
+}
+
+fn stream_index(stream: output::Stream) -> usize {
+    match stream {
+        output::Stream::Stdout => 0,
+        output::Stream::Stderr => 1,
+    }
+}
+
+impl Metrics {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn observe(&self, event: &Event) {
+        let mut state = self.state();
+        let ended = |state: &mut State, reason: &str, usage: Option<&crate::usage::Usage>, decision: &Decision| {
+            state.launched_at = None;
+            state.restarting = *decision == Decision::Restart;
+            state.current = None;
+            if !state.stopping {
+                state.ended_by = reason_index(reason);
+            }
+            if let Some(usage) = usage {
+                state.user_cpu_ms += usage.user_cpu_ms;
+                state.kernel_cpu_ms += usage.kernel_cpu_ms;
+                state.peak_memory_bytes = usage.peak_memory_bytes;
+            }
+        };
+        match event {
+            Event::Launched { generation, .. } => {
+                if state.restarting {
+                    let ended_by = state.ended_by;
+                    state.restarts[ended_by] += 1;
+                }
+                state.restarting = false;
+                state.generation = *generation;
+                state.launched_at = Some(std::time::Instant::now());
+                state.current = None;
+                state.stopping = false;
+            }
+            // Keep the reason if Shawl already said why it's stopping the command.
+            Event::StopRequested { .. } => {
+                if !state.stopping {
+                    state.stopping = true;
+                    state.ended_by = reason_index("requested");
+                }
+            }
+            Event::ThresholdExceeded { .. } => {
+                state.stopping = true;
+                state.ended_by = reason_index("threshold");
+            }
+            Event::RestartScheduled { .. } => {
+                state.stopping = true;
+                state.ended_by = reason_index("scheduled");
+            }
+            Event::Exited {
+                code, usage, decision, ..
+            } => {
+                state.last_exit_code = Some(*code);
+                ended(&mut state, "exited", Some(usage), decision);
+            }
+            Event::Terminated { usage, decision, .. } => ended(&mut state, "terminated", Some(usage), decision),
+            Event::Killed { usage, decision, .. } => ended(&mut state, "killed", Some(usage), decision),
+            Event::LimitReached {
+                code, usage, decision, ..
+            } => {
+                if let Some(code) = code {
+                    state.last_exit_code = Some(*code);
+                }
+                ended(&mut state, "limit", Some(usage), decision);
+            }
+            Event::Lost { decision, .. } => ended(&mut state, "lost", None, decision),
+            // Shawl stopped it, but not because anyone asked.
+            Event::TimedOut { .. } => {
+                state.ended_by = reason_index("timeout");
+            }
+            Event::JobFinished { success, .. } => state.job_runs[usize::from(!success)] += 1,
+            Event::ServiceStarted { .. } | Event::LaunchFailed { .. } | Event::ServiceStopped {} => (),
+        }
+    }
+
+    pub fn line(&self, line: &output::Line) {
+        let mut state = self.state();
+        let stream = stream_index(line.stream);
+        state.lines[stream] += 1;
+        state.bytes[stream] += line.text.len() as u64;
+    }
+
+    pub fn dropped(&self, count: u64) {
+        self.state().dropped_lines += count;
+    }
+
+    /// What the current run is using right now.
+    pub fn sample(&self, sample: crate::tree::Sample) {
+        let mut state = self.state();
+        if state.launched_at.is_some() {
+            state.current = Some(sample);
+        }
+    }
+
+    pub fn suppressed(&self, count: u64) {
+        self.state().suppressed_lines += count;
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let one = |value: String| vec![(String::new(), value)];
+        let by_stream = |values: [u64; 2]| {
+            vec![
+                (r#"{stream="stdout"}"#.to_string(), values[0].to_string()),
+                (r#"{stream="stderr"}"#.to_string(), values[1].to_string()),
+            ]
+        };
+        let seconds = |ms: u64| format!("{:.3}", ms as f64 / 1000.0);
+
+        metric(
+            "shawl_restarts_total",
+            "counter",
+            "Times Shawl restarted the command, by how its last run ended.",
+            &RESTART_REASONS
+                .iter()
+                .zip(state.restarts)
+                .map(|(reason, count)| (format!(r#"{{reason="{}"}}"#, reason), count.to_string()))
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "shawl_child_up",
+            "gauge",
+            "Whether the command is running.",
+            &one(u8::from(state.launched_at.is_some()).to_string()),
+        );
+        metric(
+            "shawl_child_generation",
+            "gauge",
+            "How many times the command has been launched since the service started.",
+            &one(state.generation.to_string()),
+        );
+        metric(
+            "shawl_child_uptime_seconds",
+            "gauge",
+            "How long the current run of the command has been going.",
+            &one(state
+                .launched_at
+                .map_or_else(|| seconds(0), |x| seconds(x.elapsed().as_millis() as u64))),
+        );
+        if let Some(code) = state.last_exit_code {
+            metric(
+                "shawl_child_last_exit_code",
+                "gauge",
+                "Exit code of the last run that exited normally.",
+                &one(code.to_string()),
+            );
+        }
+        metric(
+            "shawl_child_cpu_seconds_total",
+            "counter",
+            "CPU time used by finished runs of the command.",
+            &[
+                (r#"{mode="user"}"#.to_string(), seconds(state.user_cpu_ms)),
+                (r#"{mode="kernel"}"#.to_string(), seconds(state.kernel_cpu_ms)),
+            ],
+        );
+        if let Some(current) = state.current {
+            metric(
+                "shawl_child_memory_bytes",
+                "gauge",
+                "Memory that the current run of the command and its processes are using.",
+                &one(current.memory_bytes.to_string()),
+            );
+            metric(
+                "shawl_child_run_cpu_seconds",
+                "gauge",
+                "CPU time used so far by the current run of the command and its processes.",
+                &one(seconds(current.cpu_ms)),
+            );
+        }
+        if let Some(bytes) = state.peak_memory_bytes {
+            metric(
+                "shawl_child_peak_memory_bytes",
+                "gauge",
+                "Peak memory of the last finished run of the command.",
+                &one(bytes.to_string()),
+            );
+        }
+        metric(
+            "shawl_output_lines_total",
+            "counter",
+            "Lines of output read from the command.",
+            &by_stream(state.lines),
+        );
+        metric(
+            "shawl_output_bytes_total",
+            "counter",
+            "Bytes of output read from the command, not counting line endings.",
+            &by_stream(state.bytes),
+        );
+        metric(
+            "shawl_output_dropped_lines_total",
+            "counter",
+            "Lines of output dropped because logging could not keep up.",
+            &one(state.dropped_lines.to_string()),
+        );
+        metric(
+            "shawl_output_suppressed_lines_total",
+            "counter",
+            "Lines of output left out of the logs by --log-cmd-rate.",
+            &one(state.suppressed_lines.to_string()),
+        );
+        metric(
+            "shawl_job_runs_total",
+            "counter",
+            "Finished runs of the command with --job-every or --job-at, by result.",
+            &[
+                (r#"{result="succeeded"}"#.to_string(), state.job_runs[0].to_string()),
+                (r#"{result="failed"}"#.to_string(), state.job_runs[1].to_string()),
+            ],
+        );
+        out
+    }
+}
+
+/// Add an event to the global metrics, if there are any.
+#[cfg(windows)]
+pub fn observe(event: &Event) {
+    if let Some(metrics) = global() {
+        metrics.observe(event);
+    }
+}
+
+#[cfg(windows)]
+pub fn line(line: &output::Line) {
+    if let Some(metrics) = global() {
+        metrics.line(line);
+    }
+}
+
+pub fn dropped(count: u64) {
+    if let Some(metrics) = global() {
+        metrics.dropped(count);
+    }
+}
+
+#[cfg(windows)]
+pub fn sample(sample: crate::tree::Sample) {
+    if let Some(metrics) = global() {
+        metrics.sample(sample);
+    }
+}
+
+#[cfg(windows)]
+pub fn suppressed(count: u64) {
+    if let Some(metrics) = global() {
+        metrics.suppressed(count);
+    }
+}
+
+/// Answer one HTTP request.
+fn respond(stream: std::net::TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut reader = std::io::BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if request.starts_with("GET ") && (path == "/metrics" || path == "/") {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", "Not found\n".to_string())
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Serve `GET /metrics` in a background thread, one request at a time.
+/// Returns the address in case the port was 0.
+pub fn serve(address: std::net::SocketAddr, metrics: &'static Metrics) -> std::io::Result<std::net::SocketAddr> {
+    let listener = std::net::TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+                stream.set_write_timeout(Some(std::time::Duration::from_secs(5)))?;
+                respond(stream, metrics)
+            });
+            if let Err(e) = result {
+                log::debug!("Metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(address)
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    use crate::usage::Usage;
+
+    fn exited(generation: u64, code: i32) -> Event {
+        Event::Exited {
+            generation,
+            pid: 1,
+            code,
+            usage: Usage {
+                duration_ms: 5000,
+                user_cpu_ms: 1500,
+                kernel_cpu_ms: 250,
+                peak_memory_bytes: Some(2048),
+            },
+            decision: Decision::Restart,
+        }
+    }
+
+    fn line(stream: output::Stream, text: &str) -> output::Line {
+        output::Line {
+            seq: 1,
+            stream,
+            text: text.to_string(),
+            read_at: chrono::Local::now(),
+        }
+    }
+
+    fn sample<'a>(rendered: &'a str, name: &str) -> Option<&'a str> {
+        rendered
+            .lines()
+            .find(|x| x.starts_with(name) && x[name.len()..].starts_with(' '))
+            .map(|x| &x[name.len() + 1..])
+    }
+
+    fn get(address: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+        response
+    }
+
+    describe "metrics" {
+        it "counts restarts by how the last run ended" {
+            let metrics = Metrics::default();
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.observe(&exited(1, 3));
+            metrics.observe(&Event::Launched { generation: 2, pid: 2 });
+            metrics.observe(&Event::StopRequested { generation: 2, pid: 2 });
+            metrics.observe(&Event::Killed {
+                generation: 2,
+                pid: 2,
+                usage: Usage { user_cpu_ms: 500, peak_memory_bytes: Some(4096), ..Default::default() },
+                decision: Decision::Restart,
+            });
+            metrics.observe(&Event::Launched { generation: 3, pid: 3 });
+
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="exited"}"#), Some("1"));
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="requested"}"#), Some("1"));
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="killed"}"#), Some("0"));
+            assert_eq!(sample(&rendered, "shawl_child_up"), Some("1"));
+            assert_eq!(sample(&rendered, "shawl_child_generation"), Some("3"));
+            assert_eq!(sample(&rendered, "shawl_child_last_exit_code"), Some("3"));
+            assert_eq!(sample(&rendered, r#"shawl_child_cpu_seconds_total{mode="user"}"#), Some("2.000"));
+            assert_eq!(sample(&rendered, "shawl_child_peak_memory_bytes"), Some("4096"));
+        }
+
+        it "reports what the current run is using" {
+            let metrics = Metrics::default();
+            metrics.sample(crate::tree::Sample { memory_bytes: 1, cpu_ms: 1 });
+            assert_eq!(sample(&metrics.render(), "shawl_child_memory_bytes"), None);
+
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.sample(crate::tree::Sample { memory_bytes: 4096, cpu_ms: 1500 });
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, "shawl_child_memory_bytes"), Some("4096"));
+            assert_eq!(sample(&rendered, "shawl_child_run_cpu_seconds"), Some("1.500"));
+
+            metrics.observe(&exited(1, 0));
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, "shawl_child_memory_bytes"), None);
+            assert_eq!(sample(&rendered, "shawl_child_run_cpu_seconds"), None);
+        }
+
+        it "reports the command as down between runs" {
+            let metrics = Metrics::default();
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.observe(&exited(1, 0));
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, "shawl_child_up"), Some("0"));
+            assert_eq!(sample(&rendered, "shawl_child_uptime_seconds"), Some("0.000"));
+        }
+
+        it "counts output by stream" {
+            let metrics = Metrics::default();
+            metrics.line(&line(output::Stream::Stdout, "hello"));
+            metrics.line(&line(output::Stream::Stdout, "hi"));
+            metrics.line(&line(output::Stream::Stderr, "oops"));
+            metrics.dropped(4);
+            metrics.suppressed(5);
+
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, r#"shawl_output_lines_total{stream="stdout"}"#), Some("2"));
+            assert_eq!(sample(&rendered, r#"shawl_output_bytes_total{stream="stdout"}"#), Some("7"));
+            assert_eq!(sample(&rendered, r#"shawl_output_lines_total{stream="stderr"}"#), Some("1"));
+            assert_eq!(sample(&rendered, "shawl_output_dropped_lines_total"), Some("4"));
+            assert_eq!(sample(&rendered, "shawl_output_suppressed_lines_total"), Some("5"));
+        }
+
+        it "counts restarts after a timeout" {
+            let metrics = Metrics::default();
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.observe(&Event::StopRequested { generation: 1, pid: 1 });
+            metrics.observe(&exited(1, 1));
+            metrics.observe(&Event::TimedOut { generation: 1, pid: 1, max_runtime_ms: 60_000 });
+            metrics.observe(&Event::Launched { generation: 2, pid: 2 });
+
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="timeout"}"#), Some("1"));
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="requested"}"#), Some("0"));
+        }
+
+        it "counts scheduled restarts" {
+            let metrics = Metrics::default();
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.observe(&Event::RestartScheduled {
+                generation: 1,
+                pid: 1,
+                reason: "after 1d (--restart-every)".to_string(),
+            });
+            metrics.observe(&Event::StopRequested { generation: 1, pid: 1 });
+            metrics.observe(&exited(1, 0));
+            metrics.observe(&Event::Launched { generation: 2, pid: 2 });
+
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="scheduled"}"#), Some("1"));
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="requested"}"#), Some("0"));
+        }
+
+        it "counts restarts by the watchdog" {
+            let metrics = Metrics::default();
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.observe(&Event::ThresholdExceeded {
+                generation: 1,
+                pid: 1,
+                reason: "memory use reached 2GB".to_string(),
+            });
+            metrics.observe(&Event::StopRequested { generation: 1, pid: 1 });
+            metrics.observe(&exited(1, 0));
+            metrics.observe(&Event::Launched { generation: 2, pid: 2 });
+
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="threshold"}"#), Some("1"));
+            assert_eq!(sample(&rendered, r#"shawl_restarts_total{reason="requested"}"#), Some("0"));
+        }
+
+        it "doesn't count job runs or start-child as restarts" {
+            let metrics = Metrics::default();
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            metrics.observe(&Event::Exited {
+                generation: 1,
+                pid: 1,
+                code: 0,
+                usage: Usage::default(),
+                decision: Decision::Wait,
+            });
+            metrics.observe(&Event::JobFinished { generation: 1, pid: 1, success: true });
+            metrics.observe(&Event::Launched { generation: 2, pid: 2 });
+            metrics.observe(&Event::StopRequested { generation: 2, pid: 2 });
+            metrics.observe(&Event::Terminated {
+                generation: 2,
+                pid: 2,
+                usage: Usage::default(),
+                decision: Decision::Stop,
+            });
+            metrics.observe(&Event::Launched { generation: 3, pid: 3 });
+
+            let rendered = metrics.render();
+            for reason in RESTART_REASONS {
+                assert_eq!(sample(&rendered, &format!(r#"shawl_restarts_total{{reason="{}"}}"#, reason)), Some("0"));
+            }
+            assert_eq!(sample(&rendered, "shawl_child_generation"), Some("3"));
+        }
+
+        it "counts job runs by result" {
+            let metrics = Metrics::default();
+            for (generation, success) in [(1, true), (2, false), (3, true)] {
+                metrics.observe(&Event::JobFinished { generation, pid: 1, success });
+            }
+            let rendered = metrics.render();
+            assert_eq!(sample(&rendered, r#"shawl_job_runs_total{result="succeeded"}"#), Some("2"));
+            assert_eq!(sample(&rendered, r#"shawl_job_runs_total{result="failed"}"#), Some("1"));
+        }
+
+        it "leaves out values it doesn't know yet" {
+            let rendered = Metrics::default().render();
+            assert_eq!(sample(&rendered, "shawl_child_last_exit_code"), None);
+            assert_eq!(sample(&rendered, "shawl_child_peak_memory_bytes"), None);
+            assert!(rendered.contains("# TYPE shawl_restarts_total counter\n"));
+        }
+    }
+
+    describe "endpoint" {
+        it "can be scraped over HTTP" {
+            let metrics: &'static Metrics = Box::leak(Box::default());
+            metrics.observe(&Event::Launched { generation: 1, pid: 1 });
+            let address = serve("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+
+            let response = get(address, "/metrics");
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+            let body = response.split("\r\n\r\n").nth(1).unwrap();
+            assert!(body.starts_with("# HELP shawl_restarts_total "));
+            assert_eq!(sample(body, "shawl_child_up"), Some("1"));
+            assert_eq!(sample(body, r#"shawl_output_lines_total{stream="stdout"}"#), Some("0"));
+        }
+
+        it "rejects other paths" {
+            let metrics: &'static Metrics = Box::leak(Box::default());
+            let address = serve("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+            assert!(get(address, "/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        }
+    }
+}
@@ -0,0 +1,1268 @@
+use crate::cli::{CarriageReturn, Overflow};
+use std::io::Read;
+
+/// Log target for the command's output when it shares the main log file.
+#[cfg(windows)]
+pub const TARGET: &str = "shawl::cmd";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FramingOpts {
+    pub keep_blank: bool,
+    pub carriage_return: CarriageReturn,
+    pub flush_after: Option<std::time::Duration>,
+    pub max_line: Option<usize>,
+}
+
+/// Splits raw command output into log lines.
+#[derive(Debug, Default)]
+pub struct LineFramer {
+    opts: FramingOpts,
+    buffer: Vec<u8>,
+    /// Text replaced by a lone carriage return in `collapse` mode.
+    overwritten: Vec<u8>,
+    truncated: usize,
+    pending_cr: bool,
+}
+
+impl LineFramer {
+    pub fn new(opts: FramingOpts) -> Self {
+        Self {
+            opts,
+            ..Default::default()
+        }
+    }
+
+    /// Consume a chunk of output and return any lines that it completed.
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = vec![];
+        for &byte in data {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if byte == b'\n' {
+                    self.end_line(&mut lines);
+                    continue;
+                }
+                self.lone_cr(&mut lines);
+            }
+            match byte {
+                b'\n' => self.end_line(&mut lines),
+                b'\r' => self.pending_cr = true,
+                _ => self.append(byte),
+            }
+        }
+        lines
+    }
+
+    /// Return the current partial line, if any, as if it had been terminated.
+    pub fn flush(&mut self) -> Option<String> {
+        if self.pending_cr {
+            self.pending_cr = false;
+            if self.opts.carriage_return == CarriageReturn::Collapse {
+                self.overwritten = std::mem::take(&mut self.buffer);
+            }
+        }
+        if self.buffer.is_empty() && self.overwritten.is_empty() && self.truncated == 0 {
+            return None;
+        }
+        let mut lines = vec![];
+        self.end_line(&mut lines);
+        lines.pop()
+    }
+
+    fn lone_cr(&mut self, lines: &mut Vec<String>) {
+        match self.opts.carriage_return {
+            CarriageReturn::Keep => self.append(b'\r'),
+            CarriageReturn::Split => self.end_line(lines),
+            CarriageReturn::Collapse => {
+                if !self.buffer.is_empty() {
+                    self.overwritten = std::mem::take(&mut self.buffer);
+                }
+                self.truncated = 0;
+            }
+        }
+    }
+
+    fn append(&mut self, byte: u8) {
+        match self.opts.max_line {
+            Some(max) if self.buffer.len() >= max => self.truncated += 1,
+            _ => self.buffer.push(byte),
+        }
+    }
+
+    fn end_line(&mut self, lines: &mut Vec<String>) {
+        if self.buffer.is_empty() {
+            std::mem::swap(&mut self.buffer, &mut self.overwritten);
+        }
+        self.overwritten.clear();
+
+        let line = if self.truncated > 0 {
+            // Don't leave half of a multi-byte character at the cut.
+            let valid = match std::str::from_utf8(&self.buffer) {
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                _ => self.buffer.len(),
+            };
+            let removed = self.truncated + self.buffer.len() - valid;
+            format!(
+                "{} [truncated {} bytes]",
+                String::from_utf8_lossy(&self.buffer[..valid]),
+                removed
+            )
+        } else {
+            String::from_utf8_lossy(&self.buffer).to_string()
+        };
+        self.buffer.clear();
+        self.truncated = 0;
+
+        if !line.is_empty() || self.opts.keep_blank {
+            lines.push(line);
+        }
+    }
+}
+
+pub type Timestamp = chrono::DateTime<chrono::Local>;
+
+/// Numbers the lines from both of the command's streams in the order they were read.
+#[derive(Clone, Debug, Default)]
+pub struct Sequence(std::sync::Arc<std::sync::atomic::AtomicU64>);
+
+impl Sequence {
+    /// Number lines that were just read and stamp them with the time.
+    fn stamp(&self, lines: Vec<String>) -> Vec<(String, u64, Timestamp)> {
+        let first = self
+            .0
+            .fetch_add(lines.len() as u64, std::sync::atomic::Ordering::SeqCst);
+        let read_at = chrono::Local::now();
+        lines
+            .into_iter()
+            .zip(first..)
+            .map(|(line, seq)| (line, seq, read_at))
+            .collect()
+    }
+}
+
+/// Read from the command's stdout or stderr until it closes,
+/// passing each framed line to `emit` along with its number in the `sequence`
+/// and the time when it was read.
+pub fn pump<R: Read + Send + 'static>(
+    reader: R,
+    opts: FramingOpts,
+    sequence: &Sequence,
+    mut emit: impl FnMut(String, u64, Timestamp),
+) {
+    // Lines are framed and numbered as soon as they're read, so that the numbers agree
+    // with the other stream. The framer is shared so that a quiet command's partial line
+    // can still be flushed, and whoever holds it sends everything it numbered before letting go.
+    let framer = std::sync::Arc::new(std::sync::Mutex::new(LineFramer::new(opts)));
+    let (tx, rx) = std::sync::mpsc::channel::<Vec<(String, u64, Timestamp)>>();
+    let reader_thread = {
+        let framer = framer.clone();
+        let sequence = sequence.clone();
+        std::thread::spawn(move || {
+            let mut reader = reader;
+            let mut buffer = [0; 8192];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let mut framer = framer.lock().unwrap();
+                        if tx.send(sequence.stamp(framer.push(&buffer[..n]))).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+        })
+    };
+
+    loop {
+        let lines = match opts.flush_after {
+            Some(timeout) => match rx.recv_timeout(timeout) {
+                Ok(lines) => lines,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    let mut framer = framer.lock().unwrap();
+                    // Anything read in the meantime comes first.
+                    for (line, seq, read_at) in rx.try_iter().flatten() {
+                        emit(line, seq, read_at);
+                    }
+                    for (line, seq, read_at) in sequence.stamp(framer.flush().into_iter().collect()) {
+                        emit(line, seq, read_at);
+                    }
+                    continue;
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(lines) => lines,
+                Err(_) => break,
+            },
+        };
+        for (line, seq, read_at) in lines {
+            emit(line, seq, read_at);
+        }
+    }
+
+    let _ = reader_thread.join();
+    let line = framer.lock().unwrap().flush();
+    for (line, seq, read_at) in sequence.stamp(line.into_iter().collect()) {
+        emit(line, seq, read_at);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    /// Position among all lines from both streams, in the order they were read.
+    pub seq: u64,
+    pub stream: Stream,
+    pub text: String,
+    pub read_at: Timestamp,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    lines: std::collections::VecDeque<Line>,
+    producers: usize,
+    dropped: u64,
+}
+
+/// Bounded hand-off between the threads that drain the command's pipes
+/// and the thread that writes the logs. Pushing never blocks, so a slow log
+/// can't fill up the pipes and stall the command.
+///
+/// Both streams share one queue and its `Sequence`, so lines come out in the order they were read.
+#[derive(Clone, Debug)]
+pub struct Queue {
+    state: std::sync::Arc<(std::sync::Mutex<QueueState>, std::sync::Condvar)>,
+    sequence: Sequence,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Queue {
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        Self {
+            state: Default::default(),
+            sequence: Sequence::default(),
+            capacity: capacity.max(1),
+            overflow,
+        }
+    }
+
+    /// Register a source of lines. `drain_ticking` keeps waiting until every producer is dropped.
+    pub fn producer(&self, stream: Stream) -> Producer {
+        self.state.0.lock().unwrap().producers += 1;
+        Producer {
+            queue: self.clone(),
+            stream,
+        }
+    }
+
+    /// Pass queued lines to `handle` until every producer is gone,
+    /// and return how many lines had to be dropped.
+    /// With a `tick`, also call `handle` with `None` whenever it passes without any new lines.
+    pub fn drain_ticking(&self, tick: Option<std::time::Duration>, mut handle: impl FnMut(Option<Line>)) -> u64 {
+        let (lock, condvar) = &*self.state;
+        let mut total_dropped = 0;
+        loop {
+            let (mut lines, dropped, done) = {
+                let mut state = lock.lock().unwrap();
+                while state.lines.is_empty() && state.dropped == 0 && state.producers > 0 {
+                    match tick {
+                        Some(tick) => {
+                            let (next, timeout) = condvar.wait_timeout(state, tick).unwrap();
+                            state = next;
+                            if timeout.timed_out() {
+                                break;
+                            }
+                        }
+                        None => state = condvar.wait(state).unwrap(),
+                    }
+                }
+                (
+                    Vec::from(std::mem::take(&mut state.lines)),
+                    std::mem::take(&mut state.dropped),
+                    state.producers == 0,
+                )
+            };
+
+            if dropped > 0 {
+                total_dropped += dropped;
+                crate::metrics::dropped(dropped);
+                log::warn!(
+                    "Dropped {} lines of command output because logging could not keep up",
+                    dropped
+                );
+            }
+            // The streams push independently, so a line can arrive after one that was read later.
+            lines.sort_by_key(|x| x.seq);
+            let finished = done && lines.is_empty();
+            if lines.is_empty() && !finished {
+                handle(None);
+            }
+            for line in lines {
+                handle(Some(line));
+            }
+            if finished {
+                return total_dropped;
+            }
+        }
+    }
+
+    fn push(&self, line: Line) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if state.lines.len() >= self.capacity {
+            state.dropped += 1;
+            match self.overflow {
+                Overflow::DropOldest => {
+                    state.lines.pop_front();
+                }
+                Overflow::DropNewest => return,
+            }
+        }
+        state.lines.push_back(line);
+        condvar.notify_one();
+    }
+}
+
+pub struct Producer {
+    queue: Queue,
+    stream: Stream,
+}
+
+impl Producer {
+    /// What to number lines with before pushing them.
+    pub fn sequence(&self) -> &Sequence {
+        &self.queue.sequence
+    }
+
+    /// Dropped lines still use up their number, so gaps show where they were.
+    pub fn push(&self, text: String, seq: u64, read_at: Timestamp) {
+        self.queue.push(Line {
+            seq,
+            stream: self.stream,
+            text,
+            read_at,
+        });
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.queue.state;
+        lock.lock().unwrap().producers -= 1;
+        condvar.notify_all();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GroupOpts {
+    /// Lines matching this continue the previous event.
+    pub continuation: Option<regex::Regex>,
+    pub max_lines: usize,
+    pub flush_after: std::time::Duration,
+}
+
+impl Default for GroupOpts {
+    fn default() -> Self {
+        Self {
+            continuation: None,
+            max_lines: 500,
+            flush_after: std::time::Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Group {
+    line: Line,
+    lines: usize,
+    updated: std::time::Instant,
+}
+
+/// Joins multi-line events like stack traces so that they're logged as one record.
+/// Each event keeps the sequence number and timestamp of its first line.
+#[derive(Debug)]
+pub struct Grouper {
+    opts: GroupOpts,
+    groups: [Option<Group>; 2],
+}
+
+impl Grouper {
+    pub fn new(opts: GroupOpts) -> Self {
+        Self {
+            opts,
+            groups: [None, None],
+        }
+    }
+
+    pub fn push(&mut self, line: Line, now: std::time::Instant, mut emit: impl FnMut(Line)) {
+        if self.opts.continuation.is_none() {
+            emit(line);
+            return;
+        }
+        // Steady output can keep `tick` from running, so check the timeout here too.
+        self.tick(now, &mut emit);
+
+        let continuation = self.opts.continuation.as_ref().unwrap();
+        let slot = &mut self.groups[line.stream as usize];
+        if let Some(group) = slot {
+            if group.lines < self.opts.max_lines && continuation.is_match(&line.text) {
+                group.line.text.push('\n');
+                group.line.text.push_str(&line.text);
+                group.lines += 1;
+                group.updated = now;
+                return;
+            }
+        }
+        if let Some(group) = slot.take() {
+            emit(group.line);
+        }
+        *slot = Some(Group {
+            line,
+            lines: 1,
+            updated: now,
+        });
+    }
+
+    /// Emit any events that haven't grown within the flush timeout.
+    pub fn tick(&mut self, now: std::time::Instant, mut emit: impl FnMut(Line)) {
+        for slot in self.groups.iter_mut() {
+            if slot
+                .as_ref()
+                .is_some_and(|x| now.saturating_duration_since(x.updated) >= self.opts.flush_after)
+            {
+                emit(slot.take().unwrap().line);
+            }
+        }
+    }
+
+    pub fn finish(&mut self, mut emit: impl FnMut(Line)) {
+        let mut pending: Vec<_> = self.groups.iter_mut().filter_map(|x| x.take()).collect();
+        pending.sort_by_key(|x| x.line.seq);
+        for group in pending {
+            emit(group.line);
+        }
+    }
+}
+
+/// How often to report lines that were folded or suppressed while the flood continues.
+pub const SUMMARY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LimitOpts {
+    /// Lines per second for each stream.
+    pub rate: Option<u32>,
+    pub burst: Option<u32>,
+    pub fold: bool,
+}
+
+/// What the limiter decided to log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Line(Line),
+    /// The last line from this stream came again this many more times.
+    Repeated {
+        stream: Stream,
+        count: u64,
+    },
+    /// This many lines from the stream went over the rate limit.
+    Suppressed {
+        stream: Stream,
+        count: u64,
+    },
+}
+
+#[derive(Debug)]
+struct StreamLimit {
+    tokens: f64,
+    refilled: std::time::Instant,
+    last_text: Option<String>,
+    repeated: u64,
+    suppressed: u64,
+    reported: std::time::Instant,
+}
+
+/// Keeps a flood of output from pushing everything else out of the logs.
+/// Each stream has its own token bucket and its own idea of the last line.
+#[derive(Debug)]
+pub struct Limiter {
+    opts: LimitOpts,
+    streams: [StreamLimit; 2],
+}
+
+impl Limiter {
+    pub fn new(opts: LimitOpts, now: std::time::Instant) -> Self {
+        let stream = || StreamLimit {
+            tokens: Self::burst(&opts),
+            refilled: now,
+            last_text: None,
+            repeated: 0,
+            suppressed: 0,
+            reported: now,
+        };
+        Self {
+            opts,
+            streams: [stream(), stream()],
+        }
+    }
+
+    fn burst(opts: &LimitOpts) -> f64 {
+        opts.burst.or(opts.rate).unwrap_or(0).max(1) as f64
+    }
+
+    /// Decide what to log for a new line.
+    pub fn push(&mut self, line: Line, now: std::time::Instant, mut emit: impl FnMut(Event)) {
+        let burst = Self::burst(&self.opts);
+        let stream = line.stream;
+        let limit = &mut self.streams[stream as usize];
+
+        if self.opts.fold && limit.last_text.as_ref() == Some(&line.text) {
+            limit.repeated += 1;
+        } else {
+            if limit.repeated > 0 {
+                emit(Event::Repeated {
+                    stream,
+                    count: std::mem::take(&mut limit.repeated),
+                });
+            }
+
+            let allowed = match self.opts.rate {
+                Some(rate) => {
+                    let elapsed = now.saturating_duration_since(limit.refilled).as_secs_f64();
+                    limit.tokens = (limit.tokens + elapsed * rate as f64).min(burst);
+                    limit.refilled = now;
+                    if limit.tokens >= 1.0 {
+                        limit.tokens -= 1.0;
+                        true
+                    } else {
+                        false
+                    }
+                }
+                None => true,
+            };
+            if allowed {
+                limit.last_text = Some(line.text.clone());
+                emit(Event::Line(line));
+            } else {
+                limit.suppressed += 1;
+            }
+        }
+
+        self.tick(now, emit);
+    }
+
+    /// Report anything that has been held back for at least `SUMMARY_INTERVAL`.
+    pub fn tick(&mut self, now: std::time::Instant, mut emit: impl FnMut(Event)) {
+        for (stream, limit) in [Stream::Stdout, Stream::Stderr]
+            .into_iter()
+            .zip(self.streams.iter_mut())
+        {
+            if now.saturating_duration_since(limit.reported) < SUMMARY_INTERVAL {
+                continue;
+            }
+            Self::report(stream, limit, &mut emit);
+            limit.reported = now;
+        }
+    }
+
+    /// Report anything still held back, such as when the command exits.
+    pub fn finish(&mut self, mut emit: impl FnMut(Event)) {
+        for (stream, limit) in [Stream::Stdout, Stream::Stderr]
+            .into_iter()
+            .zip(self.streams.iter_mut())
+        {
+            Self::report(stream, limit, &mut emit);
+        }
+    }
+
+    fn report(stream: Stream, limit: &mut StreamLimit, emit: &mut impl FnMut(Event)) {
+        if limit.repeated > 0 {
+            emit(Event::Repeated {
+                stream,
+                count: std::mem::take(&mut limit.repeated),
+            });
+        }
+        if limit.suppressed > 0 {
+            emit(Event::Suppressed {
+                stream,
+                count: std::mem::take(&mut limit.suppressed),
+            });
+        }
+    }
+}
+
+/// The most recent lines of output from a run, kept for a crash report.
+#[derive(Debug, Default)]
+pub struct Recent {
+    capacity: usize,
+    lines: std::collections::VecDeque<Line>,
+}
+
+impl Recent {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: std::collections::VecDeque::with_capacity(capacity.min(1000)),
+        }
+    }
+
+    pub fn push(&mut self, line: &Line) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.clone());
+    }
+
+    /// The last `count` lines of each stream, in the order they were read.
+    pub fn tail(&self, count: usize) -> Vec<&Line> {
+        let mut taken = [0; 2];
+        let mut tail: Vec<_> = self
+            .lines
+            .iter()
+            .rev()
+            .filter(|line| {
+                let taken = &mut taken[line.stream as usize];
+                *taken += 1;
+                *taken <= count
+            })
+            .collect();
+        tail.reverse();
+        tail
+    }
+
+    /// Lines for the log, like `#42 stderr: "text"`.
+    pub fn summary(&self, count: usize, redactor: &crate::redact::Redactor) -> String {
+        self.tail(count)
+            .iter()
+            .map(|line| format!("#{} {}: {:?}", line.seq, line.stream, redactor.redact(&line.text)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Every line with its timestamp, for a crash file.
+    pub fn dump(&self, redactor: &crate::redact::Redactor, timestamps: &crate::logs::Timestamps) -> String {
+        self.lines
+            .iter()
+            .map(|line| {
+                format!(
+                    "{} #{} {}: {}\n",
+                    timestamps.format(&line.read_at),
+                    line.seq,
+                    line.stream,
+                    redactor.redact(&line.text)
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    /// Drain the queue without ticking.
+    fn drain(queue: &Queue, mut handle: impl FnMut(Line)) -> u64 {
+        queue.drain_ticking(None, |line| {
+            if let Some(line) = line {
+                handle(line);
+            }
+        })
+    }
+
+    fn frame(opts: FramingOpts, chunks: &[&str]) -> Vec<String> {
+        let mut framer = LineFramer::new(opts);
+        let mut lines = vec![];
+        for chunk in chunks {
+            lines.extend(framer.push(chunk.as_bytes()));
+        }
+        lines.extend(framer.flush());
+        lines
+    }
+
+    describe "line_framer" {
+        it "splits lines on LF and CRLF" {
+            assert_eq!(
+                frame(FramingOpts::default(), &["a\nb\r\nc\n"]),
+                vec!["a", "b", "c"],
+            );
+        }
+
+        it "joins lines split across chunks" {
+            assert_eq!(
+                frame(FramingOpts::default(), &["ab", "c\r", "\nd", "\n"]),
+                vec!["abc", "d"],
+            );
+        }
+
+        it "skips blank lines by default" {
+            assert_eq!(
+                frame(FramingOpts::default(), &["a\n\n\r\nb\n"]),
+                vec!["a", "b"],
+            );
+        }
+
+        it "keeps blank lines when requested" {
+            assert_eq!(
+                frame(FramingOpts { keep_blank: true, ..Default::default() }, &["a\n\n\r\nb\n"]),
+                vec!["a", "", "", "b"],
+            );
+        }
+
+        it "keeps lone carriage returns in keep mode" {
+            assert_eq!(
+                frame(FramingOpts::default(), &["10%\r50%\r100%\n"]),
+                vec!["10%\r50%\r100%"],
+            );
+        }
+
+        it "splits on lone carriage returns in split mode" {
+            let opts = FramingOpts { carriage_return: CarriageReturn::Split, ..Default::default() };
+            assert_eq!(
+                frame(opts, &["10%\r50%\r", "100%\r\ndone\n"]),
+                vec!["10%", "50%", "100%", "done"],
+            );
+        }
+
+        it "keeps only the last update in collapse mode" {
+            let opts = FramingOpts { carriage_return: CarriageReturn::Collapse, ..Default::default() };
+            assert_eq!(
+                frame(opts, &["10%\r50%\r", "100%\r\ndone\n"]),
+                vec!["100%", "done"],
+            );
+        }
+
+        it "keeps an overwritten update in collapse mode if nothing follows it" {
+            let opts = FramingOpts { carriage_return: CarriageReturn::Collapse, ..Default::default() };
+            assert_eq!(frame(opts, &["10%\r50%\r"]), vec!["50%"]);
+        }
+
+        it "flushes a partial line" {
+            let mut framer = LineFramer::new(FramingOpts::default());
+            assert!(framer.push(b"Continue? [y/n] ").is_empty());
+            assert_eq!(framer.flush(), Some("Continue? [y/n] ".to_string()));
+            assert_eq!(framer.flush(), None);
+            assert_eq!(framer.push(b"y\n"), vec!["y"]);
+        }
+
+        it "truncates long lines" {
+            let opts = FramingOpts { max_line: Some(4), ..Default::default() };
+            assert_eq!(
+                frame(opts, &["abcdefgh\nab\n", "cdefg"]),
+                vec!["abcd [truncated 4 bytes]", "ab", "cdef [truncated 1 bytes]"],
+            );
+        }
+
+        it "truncates long lines without splitting characters" {
+            let opts = FramingOpts { max_line: Some(4), ..Default::default() };
+            assert_eq!(
+                frame(opts, &["aéé\n"]),
+                vec!["aé [truncated 2 bytes]"],
+            );
+        }
+
+        it "replaces invalid UTF-8" {
+            let mut framer = LineFramer::new(FramingOpts::default());
+            assert_eq!(framer.push(b"a\xffb\n"), vec!["a\u{fffd}b"]);
+        }
+    }
+
+    describe "pump" {
+        it "emits every line including the unterminated last one" {
+            let mut lines = vec![];
+            pump(
+                std::io::Cursor::new(b"a\nb\nc".to_vec()),
+                FramingOpts::default(),
+                &Sequence::default(),
+                |line, seq, _| lines.push((line, seq)),
+            );
+            assert_eq!(lines, vec![("a".to_string(), 0), ("b".to_string(), 1), ("c".to_string(), 2)]);
+        }
+
+        it "stamps lines with the time they were read" {
+            let (reader, mut writer) = std::io::pipe().unwrap();
+            let (tx, rx) = std::sync::mpsc::channel();
+            let pumper = std::thread::spawn(move || {
+                pump(reader, FramingOpts::default(), &Sequence::default(), |line, _, read_at| {
+                    tx.send((line, read_at)).unwrap()
+                })
+            });
+
+            std::io::Write::write_all(&mut writer, b"first\n").unwrap();
+            let (_, first) = rx.recv().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            let before = chrono::Local::now();
+            std::io::Write::write_all(&mut writer, b"second\n").unwrap();
+            drop(writer);
+            let (_, second) = rx.recv().unwrap();
+            pumper.join().unwrap();
+
+            assert!(second >= before);
+            assert!(second - first >= chrono::Duration::milliseconds(50));
+        }
+
+        it "numbers lines across streams in the order they were read" {
+            let sequence = Sequence::default();
+            let (tx, rx) = std::sync::mpsc::channel();
+            let mut writers = vec![];
+            let mut pumpers = vec![];
+            for stream in [Stream::Stdout, Stream::Stderr] {
+                let (reader, writer) = std::io::pipe().unwrap();
+                let (sequence, tx) = (sequence.clone(), tx.clone());
+                writers.push(writer);
+                pumpers.push(std::thread::spawn(move || {
+                    pump(reader, FramingOpts::default(), &sequence, |line, seq, read_at| {
+                        tx.send((stream, line, seq, read_at)).unwrap()
+                    })
+                }));
+            }
+
+            let mut lines = vec![];
+            for (index, text) in [(0, "out 1\n"), (1, "err 1\n"), (0, "out 2\n"), (1, "err 2\n")] {
+                std::io::Write::write_all(&mut writers[index], text.as_bytes()).unwrap();
+                lines.push(rx.recv().unwrap());
+            }
+            drop(writers);
+            for pumper in pumpers {
+                pumper.join().unwrap();
+            }
+
+            assert_eq!(
+                lines.iter().map(|x| (x.0, x.1.as_str(), x.2)).collect::<Vec<_>>(),
+                vec![
+                    (Stream::Stdout, "out 1", 0),
+                    (Stream::Stderr, "err 1", 1),
+                    (Stream::Stdout, "out 2", 2),
+                    (Stream::Stderr, "err 2", 3),
+                ],
+            );
+            assert!(lines.windows(2).all(|x| x[0].3 <= x[1].3));
+        }
+
+        it "numbers a partial line when it's flushed" {
+            let (reader, mut writer) = std::io::pipe().unwrap();
+            let (tx, rx) = std::sync::mpsc::channel();
+            let opts = FramingOpts {
+                flush_after: Some(std::time::Duration::from_millis(20)),
+                ..Default::default()
+            };
+            let pumper = std::thread::spawn(move || {
+                pump(reader, opts, &Sequence::default(), |line, seq, _| tx.send((line, seq)).unwrap())
+            });
+
+            std::io::Write::write_all(&mut writer, b"a\nprompt> ").unwrap();
+            assert_eq!(rx.recv().unwrap(), ("a".to_string(), 0));
+            assert_eq!(rx.recv().unwrap(), ("prompt> ".to_string(), 1));
+            std::io::Write::write_all(&mut writer, b"b\n").unwrap();
+            assert_eq!(rx.recv().unwrap(), ("b".to_string(), 2));
+            drop(writer);
+            pumper.join().unwrap();
+        }
+    }
+
+    describe "queue" {
+        fn fill(overflow: Overflow) -> (Vec<(u64, String)>, u64) {
+            let queue = Queue::new(2, overflow);
+            let producer = queue.producer(Stream::Stdout);
+            for (seq, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+                producer.push(text.to_string(), seq as u64, chrono::Local::now());
+            }
+            drop(producer);
+
+            let mut lines = vec![];
+            let dropped = drain(&queue, |line| lines.push((line.seq, line.text)));
+            (lines, dropped)
+        }
+
+        it "drops the oldest lines when full" {
+            assert_eq!(fill(Overflow::DropOldest), (vec![(2, "c".to_string()), (3, "d".to_string())], 2));
+        }
+
+        it "drops the newest lines when full" {
+            assert_eq!(fill(Overflow::DropNewest), (vec![(0, "a".to_string()), (1, "b".to_string())], 2));
+        }
+
+        it "finishes right away without producers" {
+            assert_eq!(drain(&Queue::new(2, Overflow::DropOldest), |_| ()), 0);
+        }
+
+        it "ticks while waiting for lines" {
+            let queue = Queue::new(10, Overflow::DropOldest);
+            let producer = queue.producer(Stream::Stdout);
+            let consumer = {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    let mut events = vec![];
+                    queue.drain_ticking(Some(std::time::Duration::from_millis(10)), |line| {
+                        events.push(line.map(|x| x.text));
+                    });
+                    events
+                })
+            };
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            producer.push("a".to_string(), 0, chrono::Local::now());
+            drop(producer);
+
+            let events = consumer.join().unwrap();
+            let position = events.iter().position(|x| x.is_some()).unwrap();
+            assert!(position > 0);
+            assert!(events[..position].iter().all(|x| x.is_none()));
+            assert_eq!(events.iter().flatten().collect::<Vec<_>>(), vec!["a"]);
+        }
+
+        it "keeps the order across streams" {
+            let queue = Queue::new(10, Overflow::DropOldest);
+            let stdout = queue.producer(Stream::Stdout);
+            let stderr = queue.producer(Stream::Stderr);
+            let now = chrono::Local::now();
+            // Numbered when they were read, but stderr got to the queue last.
+            stdout.push("1".to_string(), 0, now);
+            stdout.push("3".to_string(), 2, now);
+            stderr.push("2".to_string(), 1, now);
+            drop(stdout);
+            drop(stderr);
+
+            let mut lines = vec![];
+            drain(&queue, |line| lines.push((line.seq, line.stream, line.text)));
+            assert_eq!(
+                lines,
+                vec![
+                    (0, Stream::Stdout, "1".to_string()),
+                    (1, Stream::Stderr, "2".to_string()),
+                    (2, Stream::Stdout, "3".to_string()),
+                ],
+            );
+        }
+
+        it "does not hold up a command that floods its output" {
+            let total = 200_000;
+            let (reader, mut writer) = std::io::pipe().unwrap();
+            let queue = Queue::new(1000, Overflow::DropOldest);
+            let producer = queue.producer(Stream::Stdout);
+            let pumper = std::thread::spawn(move || {
+                pump(reader, FramingOpts::default(), producer.sequence(), |text, seq, read_at| {
+                    producer.push(text, seq, read_at)
+                })
+            });
+
+            // Simulate a log that can only take about 1000 lines per second.
+            let consumer = {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    let mut logged = 0;
+                    let dropped = drain(&queue, |_| {
+                        logged += 1;
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                    });
+                    (logged, dropped)
+                })
+            };
+
+            let start = std::time::Instant::now();
+            for i in 0..total {
+                std::io::Write::write_all(&mut writer, format!("flood line {}\n", i).as_bytes()).unwrap();
+            }
+            drop(writer);
+            let elapsed = start.elapsed();
+
+            pumper.join().unwrap();
+            let (logged, dropped) = consumer.join().unwrap();
+            assert!(elapsed < std::time::Duration::from_secs(10));
+            assert!(dropped > 0);
+            assert_eq!(logged + dropped, total);
+        }
+    }
+
+    describe "limiter" {
+        fn line(stream: Stream, text: &str) -> Line {
+            Line { seq: 0, stream, text: text.to_string(), read_at: chrono::Local::now() }
+        }
+
+        fn describe(event: Event) -> String {
+            match event {
+                Event::Line(line) => format!("{}: {}", line.stream, line.text),
+                Event::Repeated { stream, count } => format!("{}: repeated {}", stream, count),
+                Event::Suppressed { stream, count } => format!("{}: suppressed {}", stream, count),
+            }
+        }
+
+        fn run(opts: LimitOpts, lines: &[(u64, Stream, &str)]) -> Vec<String> {
+            let start = std::time::Instant::now();
+            let mut limiter = Limiter::new(opts, start);
+            let mut events = vec![];
+            for &(ms, stream, text) in lines {
+                let now = start + std::time::Duration::from_millis(ms);
+                limiter.push(line(stream, text), now, |x| events.push(describe(x)));
+            }
+            limiter.finish(|x| events.push(describe(x)));
+            events
+        }
+
+        it "passes everything through by default" {
+            assert_eq!(
+                run(LimitOpts::default(), &[(0, Stream::Stdout, "a"), (0, Stream::Stdout, "a")]),
+                vec!["stdout: a", "stdout: a"],
+            );
+        }
+
+        it "folds consecutive identical lines" {
+            let opts = LimitOpts { fold: true, ..Default::default() };
+            assert_eq!(
+                run(opts, &[
+                    (0, Stream::Stdout, "a"),
+                    (0, Stream::Stdout, "a"),
+                    (0, Stream::Stdout, "a"),
+                    (0, Stream::Stdout, "b"),
+                    (0, Stream::Stdout, "b"),
+                ]),
+                vec!["stdout: a", "stdout: repeated 2", "stdout: b", "stdout: repeated 1"],
+            );
+        }
+
+        it "folds each stream separately" {
+            let opts = LimitOpts { fold: true, ..Default::default() };
+            assert_eq!(
+                run(opts, &[
+                    (0, Stream::Stdout, "a"),
+                    (0, Stream::Stderr, "a"),
+                    (0, Stream::Stdout, "a"),
+                    (0, Stream::Stderr, "b"),
+                ]),
+                vec!["stdout: a", "stderr: a", "stderr: b", "stdout: repeated 1"],
+            );
+        }
+
+        it "allows a burst and then limits the rate" {
+            let opts = LimitOpts { rate: Some(2), burst: Some(3), ..Default::default() };
+            assert_eq!(
+                run(opts, &[
+                    (0, Stream::Stdout, "1"),
+                    (0, Stream::Stdout, "2"),
+                    (0, Stream::Stdout, "3"),
+                    (0, Stream::Stdout, "4"),
+                    (100, Stream::Stdout, "5"),
+                    (500, Stream::Stdout, "6"),
+                ]),
+                vec!["stdout: 1", "stdout: 2", "stdout: 3", "stdout: 6", "stdout: suppressed 2"],
+            );
+        }
+
+        it "limits each stream separately" {
+            let opts = LimitOpts { rate: Some(1), ..Default::default() };
+            assert_eq!(
+                run(opts, &[
+                    (0, Stream::Stdout, "a"),
+                    (0, Stream::Stdout, "b"),
+                    (0, Stream::Stderr, "c"),
+                ]),
+                vec!["stdout: a", "stderr: c", "stdout: suppressed 1"],
+            );
+        }
+
+        it "reports suppressed lines periodically" {
+            let opts = LimitOpts { rate: Some(1), ..Default::default() };
+            let start = std::time::Instant::now();
+            let mut limiter = Limiter::new(opts, start);
+            let mut events = vec![];
+            limiter.push(line(Stream::Stderr, "a"), start, |x| events.push(describe(x)));
+            limiter.push(line(Stream::Stderr, "b"), start, |x| events.push(describe(x)));
+            limiter.tick(start + std::time::Duration::from_secs(1), |x| events.push(describe(x)));
+            assert_eq!(events, vec!["stderr: a"]);
+
+            limiter.tick(start + SUMMARY_INTERVAL, |x| events.push(describe(x)));
+            assert_eq!(events, vec!["stderr: a", "stderr: suppressed 1"]);
+
+            limiter.finish(|x| events.push(describe(x)));
+            assert_eq!(events, vec!["stderr: a", "stderr: suppressed 1"]);
+        }
+    }
+
+    describe "grouper" {
+        fn run(opts: GroupOpts, lines: &[(Stream, &str)]) -> Vec<(u64, String)> {
+            let now = std::time::Instant::now();
+            let mut grouper = Grouper::new(opts);
+            let mut events = vec![];
+            for (seq, &(stream, text)) in lines.iter().enumerate() {
+                let line = Line { seq: seq as u64, stream, text: text.to_string(), read_at: chrono::Local::now() };
+                grouper.push(line, now, |x| events.push((x.seq, x.text)));
+            }
+            grouper.finish(|x| events.push((x.seq, x.text)));
+            events
+        }
+
+        fn stack_traces() -> GroupOpts {
+            GroupOpts {
+                continuation: Some(regex::Regex::new(r"^(\s|Caused by:)").unwrap()),
+                ..Default::default()
+            }
+        }
+
+        it "passes lines through without a rule" {
+            assert_eq!(
+                run(GroupOpts::default(), &[(Stream::Stdout, "a"), (Stream::Stdout, "  b")]),
+                vec![(0, "a".to_string()), (1, "  b".to_string())],
+            );
+        }
+
+        it "groups continuation lines with the previous line" {
+            assert_eq!(
+                run(stack_traces(), &[
+                    (Stream::Stderr, "Exception in thread main"),
+                    (Stream::Stderr, "\tat Foo.bar(Foo.java:1)"),
+                    (Stream::Stderr, "Caused by: java.io.IOException"),
+                    (Stream::Stderr, "\tat Foo.baz(Foo.java:2)"),
+                    (Stream::Stderr, "next"),
+                ]),
+                vec![
+                    (
+                        0,
+                        "Exception in thread main\n\tat Foo.bar(Foo.java:1)\nCaused by: java.io.IOException\n\tat Foo.baz(Foo.java:2)"
+                            .to_string()
+                    ),
+                    (4, "next".to_string()),
+                ],
+            );
+        }
+
+        it "groups each stream separately" {
+            assert_eq!(
+                run(stack_traces(), &[
+                    (Stream::Stderr, "error"),
+                    (Stream::Stdout, "request"),
+                    (Stream::Stderr, " detail"),
+                    (Stream::Stdout, "response"),
+                ]),
+                vec![
+                    (1, "request".to_string()),
+                    (0, "error\n detail".to_string()),
+                    (3, "response".to_string()),
+                ],
+            );
+        }
+
+        it "starts a new event after the maximum size" {
+            let opts = GroupOpts { max_lines: 2, ..stack_traces() };
+            assert_eq!(
+                run(opts, &[(Stream::Stdout, "a"), (Stream::Stdout, " b"), (Stream::Stdout, " c")]),
+                vec![(0, "a\n b".to_string()), (2, " c".to_string())],
+            );
+        }
+
+        it "flushes an event after the timeout" {
+            let start = std::time::Instant::now();
+            let mut grouper = Grouper::new(stack_traces());
+            let mut events = vec![];
+            let line = Line { seq: 0, stream: Stream::Stdout, text: "a".to_string(), read_at: chrono::Local::now() };
+            grouper.push(line, start, |x| events.push(x.text));
+            grouper.tick(start + std::time::Duration::from_millis(999), |x| events.push(x.text));
+            assert!(events.is_empty());
+            grouper.tick(start + std::time::Duration::from_secs(1), |x| events.push(x.text));
+            assert_eq!(events, vec!["a"]);
+        }
+
+        it "flushes an event after the timeout while other output continues" {
+            let start = std::time::Instant::now();
+            let mut grouper = Grouper::new(stack_traces());
+            let mut events = vec![];
+            let lines = [
+                (0, Stream::Stderr, "error"),
+                (300, Stream::Stdout, "request"),
+                (600, Stream::Stderr, " detail"),
+                (900, Stream::Stdout, "response"),
+                (1500, Stream::Stdout, " more"),
+                (1700, Stream::Stdout, "next"),
+            ];
+            for (seq, &(ms, stream, text)) in lines.iter().enumerate() {
+                let line = Line { seq: seq as u64, stream, text: text.to_string(), read_at: chrono::Local::now() };
+                let now = start + std::time::Duration::from_millis(ms);
+                grouper.push(line, now, |x| events.push((ms, x.text)));
+            }
+            // Without any ticks, the error still comes out with the first line after its timeout.
+            assert_eq!(
+                events,
+                vec![
+                    (900, "request".to_string()),
+                    (1700, "error\n detail".to_string()),
+                    (1700, "response\n more".to_string()),
+                ],
+            );
+        }
+    }
+
+    describe "recent" {
+        fn recent(capacity: usize, lines: &[(Stream, &str)]) -> Recent {
+            let mut recent = Recent::new(capacity);
+            for (seq, &(stream, text)) in lines.iter().enumerate() {
+                recent.push(&Line { seq: seq as u64, stream, text: text.to_string(), read_at: chrono::Local::now() });
+            }
+            recent
+        }
+
+        fn texts(lines: Vec<&Line>) -> Vec<&str> {
+            lines.iter().map(|x| x.text.as_str()).collect()
+        }
+
+        it "keeps only the most recent lines" {
+            let recent = recent(2, &[(Stream::Stdout, "a"), (Stream::Stdout, "b"), (Stream::Stdout, "c")]);
+            assert_eq!(texts(recent.tail(10)), vec!["b", "c"]);
+        }
+
+        it "keeps nothing without capacity" {
+            let recent = recent(0, &[(Stream::Stdout, "a")]);
+            assert!(recent.tail(10).is_empty());
+        }
+
+        it "takes the tail of each stream in order" {
+            let recent = recent(
+                10,
+                &[
+                    (Stream::Stderr, "e1"),
+                    (Stream::Stdout, "o1"),
+                    (Stream::Stderr, "e2"),
+                    (Stream::Stdout, "o2"),
+                    (Stream::Stdout, "o3"),
+                ],
+            );
+            assert_eq!(texts(recent.tail(2)), vec!["e1", "e2", "o2", "o3"]);
+        }
+
+        it "summarizes with redaction" {
+            let recent = recent(10, &[(Stream::Stdout, "ok"), (Stream::Stderr, "password=hunter2 failed")]);
+            let redactor = crate::redact::Redactor::new(&[], &[r"password=(\S+)".to_string()]);
+            assert_eq!(
+                recent.summary(5, &redactor),
+                "#0 stdout: \"ok\"\n#1 stderr: \"password=******** failed\"",
+            );
+        }
+
+        it "dumps every line with a timestamp" {
+            let mut recent = Recent::new(10);
+            let read_at = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678Z")
+                .unwrap()
+                .with_timezone(&chrono::Local);
+            recent.push(&Line { seq: 7, stream: Stream::Stderr, text: "boom".to_string(), read_at });
+            let timestamps = crate::logs::Timestamps::new(Some("rfc3339"), crate::cli::LogTimezone::Utc);
+            assert_eq!(
+                recent.dump(&crate::redact::Redactor::new(&[], &[]), &timestamps),
+                "2024-01-02T03:04:05.678+00:00 #7 stderr: boom\n",
+            );
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::cli::{Cli, Subcommand};
+
+pub const MASK: &str = "********";
+
+/// Environment variable names whose values are always hidden in logs.
+pub const DEFAULT_ENV_PATTERNS: &[&str] = &[
+    "*PASSWORD*",
+    "*PASSWD*",
+    "*SECRET*",
+    "*TOKEN*",
+    "*API_KEY*",
+    "*APIKEY*",
+    "*CREDENTIAL*",
+    "*PRIVATE_KEY*",
+];
+
+/// Hides secrets before they reach the logs.
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    env_patterns: Vec<String>,
+    rules: Vec<regex::Regex>,
+}
+
+impl Redactor {
+    /// The rules should already have been validated by the CLI parser,
+    /// so any that don't compile are skipped.
+    pub fn new(env_patterns: &[String], rules: &[String]) -> Self {
+        Self {
+            env_patterns: DEFAULT_ENV_PATTERNS
+                .iter()
+                .map(|x| x.to_string())
+                .chain(env_patterns.iter().cloned())
+                .collect(),
+            rules: rules.iter().filter_map(|x| regex::Regex::new(x).ok()).collect(),
+        }
+    }
+
+    pub fn is_secret_env(&self, key: &str) -> bool {
+        self.env_patterns.iter().any(|pattern| glob_matches(pattern, key))
+    }
+
+    /// Apply the rules to a line of text. When a rule has capture groups,
+    /// only the captured parts are hidden; otherwise, the whole match is.
+    pub fn redact<'a>(&self, text: &'a str) -> std::borrow::Cow<'a, str> {
+        if self.rules.is_empty() {
+            return std::borrow::Cow::Borrowed(text);
+        }
+
+        let mut text = std::borrow::Cow::Borrowed(text);
+        for rule in &self.rules {
+            let mut ranges = vec![];
+            for captures in rule.captures_iter(&text) {
+                if captures.len() > 1 {
+                    ranges.extend(captures.iter().skip(1).flatten().map(|x| x.range()));
+                } else if let Some(whole) = captures.get(0) {
+                    ranges.push(whole.range());
+                }
+            }
+            if ranges.is_empty() {
+                continue;
+            }
+
+            let mut redacted = String::with_capacity(text.len());
+            let mut last = 0;
+            for range in ranges {
+                if range.start < last || range.is_empty() {
+                    continue;
+                }
+                redacted.push_str(&text[last..range.start]);
+                redacted.push_str(MASK);
+                last = range.end;
+            }
+            redacted.push_str(&text[last..]);
+            text = std::borrow::Cow::Owned(redacted);
+        }
+        text
+    }
+
+    /// Render the parsed CLI for the log with secrets hidden.
+    pub fn redact_cli(&self, cli: &Cli) -> String {
+        let mut cli = cli.clone();
+        if let Subcommand::Add { common, .. } | Subcommand::Run { common, .. } = &mut cli.sub {
+            for (key, value) in common.env.iter_mut() {
+                if self.is_secret_env(key) {
+                    *value = MASK.to_string();
+                }
+            }
+        }
+        self.redact(&format!("{:?}", cli)).to_string()
+    }
+}
+
+/// Case-insensitive match where `*` stands for any text and `?` for any one character.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<_> = pattern.to_uppercase().chars().collect();
+    let text: Vec<_> = text.to_uppercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    backtrack = Some((bp, bt + 1));
+                    p = bp + 1;
+                    t = bt + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    use crate::cli::CommonOpts;
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    describe "glob_matches" {
+        it "matches literally" {
+            assert!(glob_matches("FOO", "FOO"));
+            assert!(!glob_matches("FOO", "FOOD"));
+        }
+
+        it "ignores case" {
+            assert!(glob_matches("*password*", "Db_Password"));
+        }
+
+        it "handles wildcards" {
+            assert!(glob_matches("*TOKEN*", "TOKEN"));
+            assert!(glob_matches("*TOKEN*", "GITHUB_TOKEN_RO"));
+            assert!(glob_matches("DB_*_KEY", "DB_MAIN_KEY"));
+            assert!(!glob_matches("DB_*_KEY", "DB_MAIN_KEYS"));
+            assert!(glob_matches("A?C", "ABC"));
+            assert!(!glob_matches("A?C", "AC"));
+        }
+    }
+
+    describe "redactor" {
+        it "recognizes secret environment variables by default" {
+            let redactor = Redactor::new(&[], &[]);
+            assert!(redactor.is_secret_env("DB_PASSWORD"));
+            assert!(redactor.is_secret_env("github_token"));
+            assert!(!redactor.is_secret_env("PATH"));
+        }
+
+        it "accepts extra environment variable patterns" {
+            let redactor = Redactor::new(&[s("LICENSE_*")], &[]);
+            assert!(redactor.is_secret_env("LICENSE_SERIAL"));
+            assert!(redactor.is_secret_env("API_KEY"));
+        }
+
+        it "leaves text alone without rules" {
+            let redactor = Redactor::new(&[], &[]);
+            assert_eq!(redactor.redact("password=hunter2"), "password=hunter2");
+        }
+
+        it "hides whole matches" {
+            let redactor = Redactor::new(&[], &[s(r"\d{4}-\d{4}-\d{4}-\d{4}")]);
+            assert_eq!(
+                redactor.redact("card 1234-5678-1234-5678 and 8765-4321-8765-4321 declined"),
+                "card ******** and ******** declined",
+            );
+        }
+
+        it "hides only capture groups" {
+            let redactor = Redactor::new(&[], &[s(r"(?i)password=(\S+)")]);
+            assert_eq!(
+                redactor.redact("login user=bob Password=hunter2 ok"),
+                "login user=bob Password=******** ok",
+            );
+        }
+
+        it "applies every rule" {
+            let redactor = Redactor::new(&[], &[s("alpha"), s("beta=(.)")]);
+            assert_eq!(redactor.redact("alpha beta=1"), "******** beta=********");
+        }
+
+        it "masks secrets in the CLI dump" {
+            let redactor = Redactor::new(&[], &[s("--key=(\\w+)")]);
+            let cli = Cli {
+                sub: Subcommand::Run {
+                    name: s("foo"),
+                    cwd: None,
+                    common: CommonOpts {
+                        env: vec![(s("DB_PASSWORD"), s("hunter2")), (s("MODE"), s("fast"))],
+                        command: vec![s("foo"), s("--key=abc123")],
+                        ..Default::default()
+                    },
+                },
+            };
+            let dump = redactor.redact_cli(&cli);
+            assert!(!dump.contains("hunter2"));
+            assert!(!dump.contains("abc123"));
+            assert!(dump.contains("\"DB_PASSWORD\", \"********\""));
+            assert!(dump.contains("\"MODE\", \"fast\""));
+        }
+    }
+}
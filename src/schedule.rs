@@ -0,0 +1,499 @@
+//! Cron schedules for `--restart-at`, and when to launch the command in job mode.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone};
+
+/// How far ahead to look for the next time before giving up,
+/// which is enough to find February 29 on the right weekday.
+const SEARCH_DAYS: i64 = 366 * 28;
+
+/// A standard five-field cron expression: minute, hour, day of month, month, and day of week.
+/// Each field is `*`, a number, a range like `1-5`, a step like `*/15` or `0-30/10`,
+/// or a comma-separated list of those. Sunday is 0 or 7.
+/// Like in cron, if both the day of month and the day of week are restricted,
+/// then either one can match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parse one field into a bit mask of the values it allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |x: &str| match x.parse::<u32>() {
+        Ok(x) if (min..=max).contains(&x) => Ok(x),
+        _ => Err(format!("{} is not a number from {} to {}", x, min, max)),
+    };
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step in {}", part)),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // Like `5/15`, meaning from 5 to the end.
+                None if step > 1 => (number(range)?, max),
+                None => {
+                    let x = number(range)?;
+                    (x, x)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("range {} goes backward", range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl Cron {
+    #[cfg(any(windows, test))]
+    pub fn to_cli(&self) -> String {
+        self.source.clone()
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        let day = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        };
+        day && self.months & (1 << date.month()) != 0
+    }
+
+    /// The first time on the schedule strictly after `after`, in `after`'s time zone.
+    /// A time that's skipped when the clocks go forward happens as soon as they do,
+    /// and a time that's repeated when they go back only happens the first time.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let start = after.naive_local().date();
+        for offset in 0..SEARCH_DAYS {
+            let date = start + Duration::days(offset);
+            if !self.matches_date(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|x| self.hours & (1 << x) != 0) {
+                for minute in (0..60).filter(|x| self.minutes & (1 << x) != 0) {
+                    let local = date.and_hms_opt(hour, minute, 0)?;
+                    if let Some(time) = resolve(&tz, local) {
+                        if time > *after {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The moment that a local time refers to, or the end of the gap if it's skipped.
+fn resolve<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    if let Some(time) = tz.from_local_datetime(&local).earliest() {
+        return Some(time);
+    }
+    // Gaps are rarely more than an hour, but some places have skipped a whole day.
+    let mut later = local;
+    for _ in 0..(2 * 24 * 60) {
+        later += Duration::minutes(1);
+        if let Some(time) = tz.from_local_datetime(&later).earliest() {
+            return Some(time);
+        }
+    }
+    None
+}
+
+impl std::str::FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "cron schedule must have 5 fields (minute hour day month weekday), not: {}",
+                s
+            ));
+        };
+        let with_field = |name: &str, result: Result<u64, String>| result.map_err(|e| format!("{} field: {}", name, e));
+        let mut weekdays = with_field("weekday", parse_field(weekdays, 0, 7))?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        let cron = Self {
+            source: fields.join(" "),
+            minutes: with_field("minute", parse_field(minutes, 0, 59))?,
+            hours: with_field("hour", parse_field(hours, 0, 23))?,
+            days: with_field("day", parse_field(days, 1, 31))?,
+            months: with_field("month", parse_field(months, 1, 12))?,
+            weekdays,
+            any_day: days.starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        };
+        if cron.next_after(&chrono::Utc::now()).is_none() {
+            return Err(format!("cron schedule never happens: {}", s));
+        }
+        Ok(cron)
+    }
+}
+
+/// What to do when the next job run is due while the last one is still going.
+#[cfg(any(windows, test))]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Overlap<Tz: TimeZone> {
+    /// The next run isn't due yet.
+    Continue,
+    /// Let the last run finish and wait for the one due at `next`, if there is one.
+    Skip { next: Option<DateTime<Tz>> },
+    /// Stop the last run and start the one that's due.
+    Replace,
+}
+
+/// When to launch the command with `--job-every` or `--job-at`.
+#[cfg(any(windows, test))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Every(std::time::Duration),
+    At(Cron),
+}
+
+#[cfg(any(windows, test))]
+impl Trigger {
+    #[cfg(windows)]
+    pub fn from_opts(opts: &crate::cli::CommonOpts) -> Option<Self> {
+        match (opts.job_every, &opts.job_at) {
+            (Some(every), _) => Some(Self::Every(every)),
+            (None, Some(cron)) => Some(Self::At(cron.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// The first run, which is right away for an interval.
+    pub fn first<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Self::Every(_) => Some(now.clone()),
+            Self::At(cron) => cron.next_after(now),
+        }
+    }
+
+    /// The run after the one that was due at `due`, skipping any that are already late at `now`.
+    /// Intervals count from when each run was due rather than when it started,
+    /// so they don't drift.
+    pub fn after<Tz: TimeZone>(&self, due: &DateTime<Tz>, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Self::Every(every) => {
+                let every = Duration::from_std(*every).ok()?.max(Duration::seconds(1));
+                let late = (now.clone() - due.clone()).max(Duration::zero());
+                let skipped = late.num_milliseconds() / every.num_milliseconds();
+                let step = every.checked_mul(i32::try_from(skipped + 1).ok()?)?;
+                due.clone().checked_add_signed(step)
+            }
+            Self::At(cron) => cron.next_after(if due > now { due } else { now }),
+        }
+    }
+
+    /// What `--job-overlap` says to do at `now` about a run that's still going
+    /// when the next one is due at `due`.
+    pub fn overlap<Tz: TimeZone>(
+        &self,
+        due: Option<&DateTime<Tz>>,
+        now: &DateTime<Tz>,
+        policy: crate::cli::JobOverlap,
+    ) -> Overlap<Tz> {
+        match due {
+            Some(due) if due <= now => match policy {
+                crate::cli::JobOverlap::Skip => Overlap::Skip {
+                    next: self.after(due, now),
+                },
+                crate::cli::JobOverlap::Replace => Overlap::Replace,
+            },
+            _ => Overlap::Continue,
+        }
+    }
+}
+
+#[cfg(any(windows, test))]
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Every(every) => write!(f, "every {}", crate::cli::format_duration(*every)),
+            Self::At(cron) => write!(f, "at {}", cron.to_cli()),
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    use crate::cli::JobOverlap;
+    use chrono::{FixedOffset, LocalResult, Utc};
+
+    /// US Eastern time in 2024, when the clocks went forward at 02:00 on March 10
+    /// and back at 02:00 on November 3.
+    #[derive(Clone, Copy, Debug)]
+    struct Eastern;
+
+    fn est() -> FixedOffset {
+        FixedOffset::west_opt(5 * 3600).unwrap()
+    }
+
+    fn edt() -> FixedOffset {
+        FixedOffset::west_opt(4 * 3600).unwrap()
+    }
+
+    impl TimeZone for Eastern {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Eastern
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let fits = |offset: FixedOffset| {
+                self.offset_from_utc_datetime(&(*local - Duration::seconds(offset.local_minus_utc().into()))) == offset
+            };
+            match (fits(edt()), fits(est())) {
+                (true, true) => LocalResult::Ambiguous(edt(), est()),
+                (true, false) => LocalResult::Single(edt()),
+                (false, true) => LocalResult::Single(est()),
+                (false, false) => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let start = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap().and_hms_opt(7, 0, 0).unwrap();
+            let end = NaiveDate::from_ymd_opt(2024, 11, 3).unwrap().and_hms_opt(6, 0, 0).unwrap();
+            if (start..end).contains(utc) {
+                edt()
+            } else {
+                est()
+            }
+        }
+    }
+
+    fn cron(s: &str) -> Cron {
+        s.parse().unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn eastern(s: &str) -> DateTime<Eastern> {
+        utc(s).with_timezone(&Eastern)
+    }
+
+    /// The next few times, in RFC 3339.
+    fn following<Tz: TimeZone>(cron: &Cron, mut after: DateTime<Tz>, count: usize) -> Vec<String> {
+        let mut times = vec![];
+        for _ in 0..count {
+            after = cron.next_after(&after).unwrap();
+            times.push(after.fixed_offset().to_rfc3339());
+        }
+        times
+    }
+
+    describe "parsing" {
+        it "accepts the usual syntax" {
+            let cron = cron("*/15 0-6/3 1,15 * 1-5");
+            assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+            assert_eq!(cron.hours, 1 | 1 << 3 | 1 << 6);
+            assert_eq!(cron.days, 1 << 1 | 1 << 15);
+            assert_eq!(cron.months, 0b1_1111_1111_1110);
+            assert_eq!(cron.weekdays, 0b11_1110);
+            assert!(!cron.any_day && !cron.any_weekday);
+        }
+
+        it "treats 7 as Sunday" {
+            assert_eq!(cron("0 0 * * 7").weekdays, 1 | 1 << 7);
+        }
+
+        it "keeps the expression for forwarding" {
+            assert_eq!(cron("0  3 * *   *").to_cli(), "0 3 * * *");
+        }
+
+        it "rejects invalid expressions" {
+            for value in [
+                "",
+                "0 3 * *",
+                "0 3 * * * *",
+                "60 3 * * *",
+                "0 24 * * *",
+                "0 3 0 * *",
+                "0 3 * 13 *",
+                "0 3 * * 8",
+                "0 3 * * x",
+                "*/0 3 * * *",
+                "5-1 3 * * *",
+                "0 3 30 2 *",
+            ] {
+                assert!(value.parse::<Cron>().is_err(), "{}", value);
+            }
+        }
+    }
+
+    describe "next_after" {
+        it "finds the next daily time" {
+            let cron = cron("0 3 * * *");
+            assert_eq!(
+                following(&cron, utc("2024-05-01T02:59:00Z"), 3),
+                vec!["2024-05-01T03:00:00+00:00", "2024-05-02T03:00:00+00:00", "2024-05-03T03:00:00+00:00"],
+            );
+        }
+
+        it "is strictly after the given time" {
+            let cron = cron("0 3 * * *");
+            assert_eq!(following(&cron, utc("2024-05-01T03:00:00Z"), 1), vec!["2024-05-02T03:00:00+00:00"]);
+        }
+
+        it "crosses months and years" {
+            let cron = cron("30 12 1 */6 *");
+            assert_eq!(
+                following(&cron, utc("2024-06-15T00:00:00Z"), 2),
+                vec!["2024-07-01T12:30:00+00:00", "2025-01-01T12:30:00+00:00"],
+            );
+        }
+
+        it "matches either the day or the weekday when both are set" {
+            // The 13th, or any Friday.
+            let cron = cron("0 0 13 * 5");
+            assert_eq!(
+                following(&cron, utc("2024-09-09T00:00:00Z"), 3),
+                vec!["2024-09-13T00:00:00+00:00", "2024-09-20T00:00:00+00:00", "2024-09-27T00:00:00+00:00"],
+            );
+        }
+
+        it "finds leap days" {
+            let cron = cron("0 0 29 2 *");
+            assert_eq!(following(&cron, utc("2024-03-01T00:00:00Z"), 1), vec!["2028-02-29T00:00:00+00:00"]);
+        }
+
+        it "keeps local time across daylight saving time" {
+            let cron = cron("0 3 * * *");
+            assert_eq!(
+                following(&cron, eastern("2024-03-09T12:00:00-05:00"), 2),
+                vec!["2024-03-10T03:00:00-04:00", "2024-03-11T03:00:00-04:00"],
+            );
+            assert_eq!(
+                following(&cron, eastern("2024-11-02T12:00:00-04:00"), 2),
+                vec!["2024-11-03T03:00:00-05:00", "2024-11-04T03:00:00-05:00"],
+            );
+        }
+
+        it "runs skipped times when the clocks go forward" {
+            let cron = cron("*/20 2 * * *");
+            assert_eq!(
+                following(&cron, eastern("2024-03-10T01:00:00-05:00"), 2),
+                vec!["2024-03-10T03:00:00-04:00", "2024-03-11T02:00:00-04:00"],
+            );
+        }
+
+        it "runs repeated times once when the clocks go back" {
+            let cron = cron("30 1 * * *");
+            assert_eq!(
+                following(&cron, eastern("2024-11-03T00:00:00-04:00"), 2),
+                vec!["2024-11-03T01:30:00-04:00", "2024-11-04T01:30:00-05:00"],
+            );
+        }
+    }
+
+    describe "trigger" {
+        it "starts an interval right away" {
+            let trigger = Trigger::Every(std::time::Duration::from_secs(15 * 60));
+            let now = utc("2024-05-01T10:07:00Z");
+            assert_eq!(trigger.first(&now), Some(now));
+        }
+
+        it "runs out of intervals past the end of time" {
+            let trigger = Trigger::Every(std::time::Duration::from_secs(99_999_999_999_999 * 24 * 60 * 60));
+            let now = utc("2024-05-01T10:07:00Z");
+            assert_eq!(trigger.after(&now, &now), None);
+            let trigger = Trigger::Every(std::time::Duration::from_secs(100_000_000 * 24 * 60 * 60));
+            assert_eq!(trigger.after(&now, &now), None);
+        }
+
+        it "waits for the first time on a schedule" {
+            let trigger = Trigger::At(cron("0 * * * *"));
+            assert_eq!(trigger.first(&utc("2024-05-01T10:07:00Z")), Some(utc("2024-05-01T11:00:00Z")));
+        }
+
+        it "counts intervals from when each run was due" {
+            let trigger = Trigger::Every(std::time::Duration::from_secs(15 * 60));
+            let due = utc("2024-05-01T10:00:00Z");
+            assert_eq!(trigger.after(&due, &utc("2024-05-01T10:00:03Z")), Some(utc("2024-05-01T10:15:00Z")));
+        }
+
+        it "skips intervals that are already late" {
+            let trigger = Trigger::Every(std::time::Duration::from_secs(15 * 60));
+            let due = utc("2024-05-01T10:00:00Z");
+            assert_eq!(trigger.after(&due, &utc("2024-05-01T10:40:00Z")), Some(utc("2024-05-01T10:45:00Z")));
+            assert_eq!(trigger.after(&due, &utc("2024-05-01T10:45:00Z")), Some(utc("2024-05-01T11:00:00Z")));
+        }
+
+        it "skips scheduled times that are already late" {
+            let trigger = Trigger::At(cron("*/10 * * * *"));
+            let due = utc("2024-05-01T10:00:00Z");
+            assert_eq!(trigger.after(&due, &utc("2024-05-01T10:00:01Z")), Some(utc("2024-05-01T10:10:00Z")));
+            assert_eq!(trigger.after(&due, &utc("2024-05-01T10:25:00Z")), Some(utc("2024-05-01T10:30:00Z")));
+        }
+
+        it "lets a run continue until the next one is due" {
+            let trigger = Trigger::Every(std::time::Duration::from_secs(15 * 60));
+            let due = utc("2024-05-01T10:15:00Z");
+            let now = utc("2024-05-01T10:14:59Z");
+            assert_eq!(trigger.overlap(Some(&due), &now, JobOverlap::Skip), Overlap::Continue);
+            assert_eq!(trigger.overlap(Some(&due), &now, JobOverlap::Replace), Overlap::Continue);
+            assert_eq!(trigger.overlap(None, &now, JobOverlap::Replace), Overlap::Continue);
+        }
+
+        it "skips a run that's due while the last one is going" {
+            let trigger = Trigger::Every(std::time::Duration::from_secs(15 * 60));
+            let due = utc("2024-05-01T10:15:00Z");
+            assert_eq!(
+                trigger.overlap(Some(&due), &utc("2024-05-01T10:15:00Z"), JobOverlap::Skip),
+                Overlap::Skip { next: Some(utc("2024-05-01T10:30:00Z")) },
+            );
+            assert_eq!(
+                trigger.overlap(Some(&due), &utc("2024-05-01T10:31:00Z"), JobOverlap::Skip),
+                Overlap::Skip { next: Some(utc("2024-05-01T10:45:00Z")) },
+            );
+        }
+
+        it "replaces the last run when the next one is due" {
+            let trigger = Trigger::At(cron("0 * * * *"));
+            let due = utc("2024-05-01T11:00:00Z");
+            assert_eq!(
+                trigger.overlap(Some(&due), &utc("2024-05-01T11:00:01Z"), JobOverlap::Replace),
+                Overlap::Replace,
+            );
+        }
+
+        it "describes itself" {
+            assert_eq!(Trigger::Every(std::time::Duration::from_secs(900)).to_string(), "every 15m");
+            assert_eq!(Trigger::At(cron("0 3 * * *")).to_string(), "at 0 3 * * *");
+        }
+    }
+}
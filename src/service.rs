@@ -1,6 +1,8 @@
-use crate::cli;
-use log::{debug, error, info};
-use std::{io::BufRead, os::windows::process::CommandExt};
+use crate::{
+    cli, ctl, events, history, logs, metrics, output, redact, schedule, syslog, template, tree, usage, watchdog,
+};
+use log::{debug, error, info, warn};
+use std::os::windows::process::CommandExt;
 use windows_service::{
     define_windows_service,
     service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType},
@@ -14,16 +16,19 @@ define_windows_service!(ffi_service_main, service_main);
 
 enum ProcessStatus {
     Running,
-    Exited(i32),
-    Terminated,
+    Exited(i32, usage::Usage),
+    Terminated(usage::Usage),
 }
 
-fn check_process(child: &mut std::process::Child) -> Result<ProcessStatus, Box<dyn std::error::Error>> {
-    match child.try_wait() {
+fn check_process(
+    child: &mut std::process::Child,
+    launched_at: std::time::Instant,
+) -> Result<ProcessStatus, Box<dyn std::error::Error>> {
+    match usage::try_wait(child, launched_at) {
         Ok(None) => Ok(ProcessStatus::Running),
-        Ok(Some(status)) => match status.code() {
-            Some(code) => Ok(ProcessStatus::Exited(code)),
-            None => Ok(ProcessStatus::Terminated),
+        Ok(Some((status, usage))) => match status.code() {
+            Some(code) => Ok(ProcessStatus::Exited(code, usage)),
+            None => Ok(ProcessStatus::Terminated(usage)),
         },
         Err(e) => Err(Box::new(e)),
     }
@@ -49,6 +54,544 @@ fn should_restart_terminated_command(restart: bool, _no_restart: bool) -> bool {
     restart
 }
 
+/// A timeout is a failure without an exit code, so only `--restart-if` leaves it out.
+fn should_restart_timed_out_command(restart: bool, no_restart: bool, restart_if: &[i32]) -> bool {
+    restart || (!no_restart && restart_if.is_empty())
+}
+
+/// `--restart-on` or `--stop-on`, if either covers how the run ended.
+fn should_restart_on(
+    condition: Option<cli::Condition>,
+    restart_on: &[cli::Condition],
+    stop_on: &[cli::Condition],
+) -> Option<bool> {
+    let condition = condition?;
+    if restart_on.contains(&condition) {
+        Some(true)
+    } else if stop_on.contains(&condition) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn stdin_for(mode: &cli::StdinMode) -> std::io::Result<std::process::Stdio> {
+    Ok(match mode {
+        cli::StdinMode::Inherit => std::process::Stdio::inherit(),
+        cli::StdinMode::Null => std::process::Stdio::null(),
+        cli::StdinMode::File(path) => std::fs::File::open(path)?.into(),
+        cli::StdinMode::Pipe => std::process::Stdio::piped(),
+    })
+}
+
+fn write_stdin(stdin: &mut Option<std::process::ChildStdin>, line: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let Some(stdin) = stdin else {
+        return Err(std::io::Error::other("stdin is not a pipe (use --stdin pipe)"));
+    };
+    stdin.write_all(format!("{}\r\n", line).as_bytes())?;
+    stdin.flush()
+}
+
+/// Write `--stop-stdin` and/or close the pipe for `--stop-stdin-close`.
+fn stop_stdin(stdin: &mut Option<std::process::ChildStdin>, text: Option<&str>, close: bool) -> std::io::Result<()> {
+    if let Some(text) = text {
+        write_stdin(stdin, text)?;
+    }
+    if close && stdin.take().is_none() {
+        return Err(std::io::Error::other("stdin is not a pipe (use --stdin pipe)"));
+    }
+    Ok(())
+}
+
+/// Give the command up to `timeout` to exit. It's still `Running` if it didn't.
+fn wait_for_exit(
+    child: &mut std::process::Child,
+    launched_at: std::time::Instant,
+    timeout: std::time::Duration,
+) -> Result<ProcessStatus, Box<dyn std::error::Error>> {
+    let start = std::time::Instant::now();
+    loop {
+        match check_process(child, launched_at) {
+            Ok(ProcessStatus::Running) if start.elapsed() < timeout => {
+                std::thread::sleep(std::time::Duration::from_millis(50))
+            }
+            status => return status,
+        }
+    }
+}
+
+/// Sent to the main service loop by the service manager or `shawl ctl`.
+enum Control {
+    Shutdown,
+    RestartChild,
+    StopChild,
+    StartChild,
+}
+
+/// What to do once the command has exited.
+enum Next {
+    Relaunch,
+    /// Wait for `shawl ctl start-child`.
+    Pause,
+    /// Wait for the next job run.
+    Wait,
+    Exit,
+}
+
+/// Everything needed to stop the command.
+struct Running<'a> {
+    child: &'a mut std::process::Child,
+    /// Unless `--no-kill-tree`.
+    tree: Option<&'a tree::Tree>,
+    stdin: &'a mut Option<std::process::ChildStdin>,
+    launched_at: std::time::Instant,
+    generation: u64,
+    pid: u32,
+}
+
+/// Ask the command to stop through stdin and/or ctrl-C, then kill it
+/// if it's still running after `--stop-timeout`. Returns the exit code
+/// for the service if the command's exit should determine it.
+fn stop_command(
+    run: Running,
+    status: &std::sync::Mutex<ctl::Status>,
+    opts: &cli::CommonOpts,
+    ignore_ctrlc: &std::sync::atomic::AtomicBool,
+    decision: events::Decision,
+) -> Option<ServiceExitCode> {
+    let Running {
+        child,
+        tree,
+        stdin,
+        launched_at,
+        generation,
+        pid,
+    } = run;
+    let pass = opts.pass.clone().unwrap_or_else(|| vec![0]);
+    let stop_by_stdin = opts.stop_stdin.is_some() || opts.stop_stdin_close;
+
+    record(status, events::Event::StopRequested { generation, pid });
+    let start_time = std::time::Instant::now();
+    let timeout = std::time::Duration::from_millis(opts.stop_timeout.unwrap_or(3000_u64));
+    let mut process = Ok(ProcessStatus::Running);
+
+    if stop_by_stdin {
+        info!("Asking command to stop through stdin");
+        match stop_stdin(stdin, opts.stop_stdin.as_deref(), opts.stop_stdin_close) {
+            Ok(()) => process = wait_for_exit(child, launched_at, timeout),
+            Err(e) => warn!("Unable to ask command to stop through stdin: {}", e),
+        }
+    }
+
+    if matches!(process, Ok(ProcessStatus::Running)) {
+        ignore_ctrlc.store(true, std::sync::atomic::Ordering::SeqCst);
+        info!("Sending ctrl-C to command");
+        unsafe {
+            if windows::Win32::System::Console::GenerateConsoleCtrlEvent(
+                windows::Win32::System::Console::CTRL_C_EVENT,
+                0,
+            )
+            .is_err()
+            {
+                error!(
+                    "Windows GenerateConsoleCtrlEvent failed with code {:?}",
+                    windows::Win32::Foundation::GetLastError()
+                );
+            };
+        }
+        process = wait_for_exit(child, launched_at, timeout);
+    }
+
+    let exit_code = match process {
+        Ok(ProcessStatus::Running) => {
+            info!("Killing command because stop timeout expired",);
+            match tree.map(|x| x.kill()) {
+                Some(Ok(count)) => info!("Killed {} processes in the command's tree", count),
+                Some(Err(e)) => {
+                    warn!("Unable to kill the command's tree: {}", e);
+                    let _ = child.kill();
+                }
+                None => {
+                    let _ = child.kill();
+                }
+            }
+            let usage = match usage::wait(child, launched_at) {
+                Ok((_, usage)) => usage,
+                Err(_) => usage::Usage::wall(launched_at),
+            };
+            info!("Command was killed ({})", usage);
+            record(
+                status,
+                events::Event::Killed {
+                    generation,
+                    pid,
+                    usage,
+                    decision,
+                },
+            );
+            Some(ServiceExitCode::NO_ERROR)
+        }
+        Ok(ProcessStatus::Exited(code, usage)) => {
+            info!(
+                "Command exited after {:?} ms with code {:?} ({})",
+                start_time.elapsed().as_millis(),
+                code,
+                usage
+            );
+            record(
+                status,
+                events::Event::Exited {
+                    generation,
+                    pid,
+                    code,
+                    usage,
+                    decision,
+                },
+            );
+            Some(if pass.contains(&code) {
+                ServiceExitCode::NO_ERROR
+            } else {
+                ServiceExitCode::ServiceSpecific(code as u32)
+            })
+        }
+        Ok(ProcessStatus::Terminated(usage)) => {
+            info!("Command exited within stop timeout ({})", usage);
+            record(
+                status,
+                events::Event::Terminated {
+                    generation,
+                    pid,
+                    usage,
+                    decision,
+                },
+            );
+            None
+        }
+        Err(e) => {
+            info!("Command exited within stop timeout");
+            record(
+                status,
+                events::Event::Lost {
+                    generation,
+                    pid,
+                    error: e.to_string(),
+                    decision,
+                },
+            );
+            None
+        }
+    };
+
+    ignore_ctrlc.store(false, std::sync::atomic::Ordering::SeqCst);
+    reap(tree);
+    exit_code
+}
+
+/// Note the first resource limit that the command reaches during a run.
+fn check_limits(tree: Option<&tree::Tree>, limit: &mut Option<tree::Limit>) {
+    if limit.is_none() {
+        *limit = tree.and_then(|x| x.limit_reached());
+        if let Some(limit) = limit {
+            warn!("Command reached its {} limit", limit);
+        }
+    }
+}
+
+/// Check what the command is using against `--max-memory` and `--max-cpu`,
+/// and keep the metrics up to date with it.
+fn watch(tree: Option<&tree::Tree>, watchdog: &mut watchdog::Watchdog) -> Option<watchdog::Breach> {
+    if !watchdog.is_enabled() && metrics::global().is_none() {
+        return None;
+    }
+    match tree?.sample() {
+        Ok(sample) => {
+            metrics::sample(sample);
+            watchdog
+                .is_enabled()
+                .then(|| watchdog.check(std::time::Instant::now(), sample))
+                .flatten()
+        }
+        Err(e) => {
+            debug!("Unable to check what the command is using: {}", e);
+            None
+        }
+    }
+}
+
+/// Why the command is due for a restart with `--restart-every` or `--restart-at`, if it is.
+fn restart_due(
+    opts: &cli::CommonOpts,
+    launched_at: std::time::Instant,
+    scheduled: Option<&chrono::DateTime<chrono::Local>>,
+) -> Option<String> {
+    if let Some(every) = opts.restart_every {
+        if launched_at.elapsed() >= every {
+            return Some(format!("after {} (--restart-every)", cli::format_duration(every)));
+        }
+    }
+    match scheduled {
+        Some(at) if chrono::Local::now() >= *at => Some(format!(
+            "as scheduled for {} (--restart-at)",
+            at.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+        )),
+        _ => None,
+    }
+}
+
+/// Wait until the next job run is due, or until `shawl ctl start-child`
+/// asks for one early. Returns false if the service should stop instead.
+fn wait_for_job(
+    due: Option<&chrono::DateTime<chrono::Local>>,
+    status: &std::sync::Mutex<ctl::Status>,
+    control_rx: &std::sync::mpsc::Receiver<Control>,
+) -> bool {
+    let Some(due) = due else {
+        info!("Stopping because no more job runs are scheduled");
+        return false;
+    };
+    let next_run = due.to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+    info!("Waiting for the next job run at {}", next_run);
+    {
+        let mut status = status.lock().unwrap();
+        status.state = ctl::ChildState::Waiting;
+        status.pid = None;
+        status.launched_at = None;
+        if let Some(jobs) = &mut status.jobs {
+            jobs.next_run = Some(next_run);
+        }
+    }
+    loop {
+        let Ok(remaining) = (*due - chrono::Local::now()).to_std() else {
+            return true;
+        };
+        // Check the clock at least every second in case it jumps, like after sleep.
+        match control_rx.recv_timeout(remaining.min(std::time::Duration::from_secs(1))) {
+            Ok(Control::StartChild | Control::RestartChild) => return true,
+            Ok(Control::StopChild) | Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+            Ok(Control::Shutdown) | Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// The event for a run that ended on its own, which is `LimitReached`
+/// if it ran into a limit along the way.
+fn ended(
+    generation: u64,
+    pid: u32,
+    code: Option<i32>,
+    usage: usage::Usage,
+    limit: Option<tree::Limit>,
+    decision: events::Decision,
+) -> events::Event {
+    match (limit, code) {
+        (Some(limit), code) => events::Event::LimitReached {
+            generation,
+            pid,
+            limit,
+            code,
+            usage,
+            decision,
+        },
+        (None, Some(code)) => events::Event::Exited {
+            generation,
+            pid,
+            code,
+            usage,
+            decision,
+        },
+        (None, None) => events::Event::Terminated {
+            generation,
+            pid,
+            usage,
+            decision,
+        },
+    }
+}
+
+/// Kill anything that the command left running after it exited.
+fn reap(tree: Option<&tree::Tree>) {
+    match tree.map(|x| x.kill()) {
+        Some(Ok(0)) | None => (),
+        Some(Ok(count)) => info!("Killed {} leftover processes from the command's tree", count),
+        Some(Err(e)) => warn!("Unable to kill leftover processes from the command's tree: {}", e),
+    }
+}
+
+/// Answer a request from `shawl ctl`.
+fn handle_request(
+    request: ctl::Request,
+    status: &std::sync::Mutex<ctl::Status>,
+    policy: &std::sync::Mutex<cli::RestartPolicy>,
+    control_tx: &std::sync::mpsc::Sender<Control>,
+) -> ctl::Response {
+    let state = status.lock().unwrap().state;
+    let send = |control| match control_tx.send(control) {
+        Ok(()) => ctl::Response::ok(),
+        Err(_) => ctl::Response::error("service is stopping"),
+    };
+    match request {
+        ctl::Request::Status => {
+            let mut status = status.lock().unwrap().clone();
+            status.restart_policy = policy.lock().unwrap().to_cli();
+            ctl::Response::status(status)
+        }
+        ctl::Request::RestartChild => {
+            info!("Received request to restart command");
+            send(Control::RestartChild)
+        }
+        ctl::Request::StopChild if state == ctl::ChildState::Stopped => {
+            ctl::Response::error("command is already stopped")
+        }
+        ctl::Request::StopChild if state == ctl::ChildState::Waiting => {
+            ctl::Response::error("command is waiting for the next job run")
+        }
+        ctl::Request::StopChild => {
+            info!("Received request to stop command");
+            send(Control::StopChild)
+        }
+        ctl::Request::StartChild if !matches!(state, ctl::ChildState::Stopped | ctl::ChildState::Waiting) => {
+            ctl::Response::error("command is already running")
+        }
+        ctl::Request::StartChild => {
+            info!("Received request to start command");
+            send(Control::StartChild)
+        }
+        ctl::Request::RotateLogs => match crate::rotate_logs() {
+            Ok(()) => ctl::Response::ok(),
+            Err(e) => ctl::Response::error(e),
+        },
+        ctl::Request::SetRestartPolicy { policy: new } => match new.parse::<cli::RestartPolicy>() {
+            Ok(new) => {
+                info!("Changing restart policy to {}", new.to_cli());
+                *policy.lock().unwrap() = new;
+                ctl::Response::ok()
+            }
+            Err(e) => ctl::Response::error(e),
+        },
+    }
+}
+
+/// Add an event to the journal and keep the status, metrics, and run history up to date.
+fn record(status: &std::sync::Mutex<ctl::Status>, event: events::Event) {
+    status.lock().unwrap().observe(&event);
+    metrics::observe(&event);
+    history::observe(&event);
+    events::record(event);
+}
+
+/// What happens next, as recorded with the end of a run.
+fn decision_for(next: &Next) -> events::Decision {
+    match next {
+        Next::Relaunch => events::Decision::Restart,
+        Next::Wait => events::Decision::Wait,
+        Next::Pause | Next::Exit => events::Decision::Stop,
+    }
+}
+
+fn decision(restart: bool) -> events::Decision {
+    if restart {
+        events::Decision::Restart
+    } else {
+        events::Decision::Stop
+    }
+}
+
+fn log_event(event: output::Event, redactor: &redact::Redactor, output_logs_need_target: bool) {
+    match event {
+        output::Event::Line(line) => log_output(line, redactor, output_logs_need_target),
+        output::Event::Repeated { stream, count } => {
+            let text = format!("last line repeated {} times", count);
+            if output_logs_need_target {
+                debug!(target: "{shawl-cmd}", "{}", text);
+            } else {
+                debug!(target: output::TARGET, "{}: {}", stream, text);
+            }
+            if let Some(syslog) = syslog::global() {
+                syslog.send(stream.into(), syslog::severity(log::Level::Info), &text);
+            }
+        }
+        output::Event::Suppressed { stream, count } => {
+            metrics::suppressed(count);
+            warn!(
+                "Suppressed {} lines of {} because the command exceeded --log-cmd-rate",
+                count, stream
+            );
+        }
+    }
+}
+
+fn log_output(line: output::Line, redactor: &redact::Redactor, output_logs_need_target: bool) {
+    let text = redactor.redact(&line.text);
+    let read_at = line.read_at.timestamp_millis();
+    if output_logs_need_target {
+        debug!(target: "{shawl-cmd}", read_at; "{}", text);
+    } else {
+        debug!(target: output::TARGET, read_at; "#{} {}: {:?}", line.seq, line.stream, text);
+    }
+    if let Some(syslog) = syslog::global() {
+        syslog.send_output(&line, &text);
+    }
+}
+
+/// Log the last lines of output after a failed run, and save the whole buffer if requested.
+fn report_crash(
+    name: &str,
+    opts: &cli::CommonOpts,
+    failure: &str,
+    recent: &output::Recent,
+    redactor: &redact::Redactor,
+) {
+    if opts.crash_buffer == Some(0) {
+        return;
+    }
+
+    let summary = recent.summary(opts.crash_tail.unwrap_or(20), redactor);
+    if summary.is_empty() {
+        error!("Command {} without any recent output", failure);
+    } else {
+        error!("Command {}, last output:\n{}", failure, summary);
+    }
+
+    if opts.crash_save {
+        match save_crash(name, opts, failure, recent, redactor) {
+            Ok(path) => info!("Saved crash report to {:?}", path),
+            Err(e) => error!("Unable to save crash report: {}", e),
+        }
+    }
+}
+
+fn save_crash(
+    name: &str,
+    opts: &cli::CommonOpts,
+    failure: &str,
+    recent: &output::Recent,
+    redactor: &redact::Redactor,
+) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    let names = logs::LogNames::resolve(
+        name,
+        opts.log_dir.as_deref(),
+        opts.log_as.as_deref(),
+        opts.log_cmd_as.as_deref(),
+        &template::Vars::current(name),
+    )?;
+    std::fs::create_dir_all(&names.dir)?;
+    let path = std::path::Path::new(&names.dir).join(format!(
+        "crash_{}_{}.log",
+        name,
+        chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+    ));
+    std::fs::write(
+        &path,
+        format!(
+            "Command {}\n{}",
+            failure,
+            recent.dump(redactor, logs::Timestamps::installed())
+        ),
+    )?;
+    Ok(path)
+}
+
 pub fn run(name: String) -> windows_service::Result<()> {
     service_dispatcher::start(name, ffi_service_main)
 }
@@ -73,7 +616,7 @@ fn service_main(mut arguments: Vec<std::ffi::OsString>) {
 
 #[allow(clippy::cognitive_complexity)]
 pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service::Result<()> {
-    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
+    let (control_tx, control_rx) = std::sync::mpsc::channel();
     let cli = cli::evaluate_cli();
     let (name, cwd, opts) = match cli.sub {
         cli::Subcommand::Run {
@@ -86,8 +629,9 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
             return Ok(());
         }
     };
-    let pass = &opts.pass.unwrap_or_else(|| vec![0]);
+    let pass = &opts.pass.clone().unwrap_or_else(|| vec![0]);
     let stop_timeout = &opts.stop_timeout.unwrap_or(3000_u64);
+    let stop_by_stdin = opts.stop_stdin.is_some() || opts.stop_stdin_close;
     let mut service_exit_code = ServiceExitCode::NO_ERROR;
 
     let ignore_ctrlc = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
@@ -99,24 +643,54 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
     })
     .expect("Unable to create ctrl-C handler");
 
+    let trigger = schedule::Trigger::from_opts(&opts);
+    let status = std::sync::Arc::new(std::sync::Mutex::new(ctl::Status {
+        name: name.clone(),
+        shawl_pid: std::process::id(),
+        jobs: trigger.as_ref().map(|x| ctl::Jobs {
+            schedule: x.to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }));
+    let policy = std::sync::Arc::new(std::sync::Mutex::new(cli::RestartPolicy::from_opts(&opts)));
+    {
+        let status = status.clone();
+        let policy = policy.clone();
+        let control_tx = control_tx.clone();
+        let served = ctl::serve(&name, move |request| {
+            handle_request(request, &status, &policy, &control_tx)
+        });
+        if let Err(e) = served {
+            warn!("Unable to listen for `shawl ctl` requests: {}", e);
+        }
+    }
+
+    if let Some(address) = opts.metrics_listen {
+        match metrics::serve(address, metrics::install(metrics::Metrics::default())) {
+            Ok(address) => info!("Serving metrics at http://{}/metrics", address),
+            Err(e) => warn!("Unable to serve metrics at {}: {}", address, e),
+        }
+    }
+
     let event_handler = move |control_event| -> ServiceControlHandlerResult {
         match control_event {
             ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
             ServiceControl::Stop => {
                 info!("Received stop event");
-                shutdown_tx.send(()).unwrap();
+                control_tx.send(Control::Shutdown).unwrap();
                 ServiceControlHandlerResult::NoError
             }
             ServiceControl::Shutdown => {
                 info!("Received shutdown event");
-                shutdown_tx.send(()).unwrap();
+                control_tx.send(Control::Shutdown).unwrap();
                 ServiceControlHandlerResult::NoError
             }
             _ => ServiceControlHandlerResult::NotImplemented,
         }
     };
 
-    let status_handle = service_control_handler::register(name, event_handler)?;
+    let status_handle = service_control_handler::register(&name, event_handler)?;
 
     status_handle.set_service_status(ServiceStatus {
         service_type: SERVICE_TYPE,
@@ -128,7 +702,7 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
         process_id: None,
     })?;
 
-    let mut command = opts.command.into_iter();
+    let mut command = opts.command.clone().into_iter();
     let program = command.next().unwrap();
     let mut args: Vec<_> = command.map(std::ffi::OsString::from).collect();
     if opts.pass_start_args {
@@ -140,16 +714,68 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
         None => windows::Win32::System::Threading::INHERIT_CALLER_PRIORITY.0,
     };
 
+    let framing = output::FramingOpts {
+        keep_blank: opts.log_cmd_keep_blank,
+        carriage_return: opts.log_cmd_cr.unwrap_or_default(),
+        flush_after: opts.log_cmd_flush.map(std::time::Duration::from_millis),
+        max_line: opts.log_cmd_max_line,
+    };
+    let redactor = redact::Redactor::new(&opts.redact_env, &opts.redact);
+    let log_cmd_buffer = opts.log_cmd_buffer.unwrap_or(10_000);
+    let log_cmd_overflow = opts.log_cmd_overflow.unwrap_or_default();
+    let limits = output::LimitOpts {
+        rate: opts.log_cmd_rate,
+        burst: opts.log_cmd_burst,
+        fold: opts.log_cmd_fold,
+    };
+    let grouping = output::GroupOpts {
+        continuation: opts
+            .log_cmd_multiline
+            .as_ref()
+            .map(|x| regex::Regex::new(x).expect("--log-cmd-multiline is checked when parsing the CLI")),
+        max_lines: opts.log_cmd_multiline_max.unwrap_or(500),
+        flush_after: std::time::Duration::from_millis(opts.log_cmd_multiline_flush.unwrap_or(1000)),
+    };
+    let tick = grouping.flush_after.min(std::time::Duration::from_secs(1));
+    let crash_buffer = opts.crash_buffer.unwrap_or(1000);
+    let stdin_mode = opts.stdin.clone().unwrap_or_default();
+    let resource_limits = tree::Limits::from_opts(&opts);
+    let mut watchdog = watchdog::Watchdog::from_opts(&opts);
+
     debug!("Entering main service loop");
+    events::record(events::Event::ServiceStarted {
+        shawl_pid: std::process::id(),
+    });
+    let mut generation = 0;
+    let mut due = trigger.as_ref().and_then(|x| x.first(&chrono::Local::now()));
+    let mut waiting = trigger.is_some();
     'outer: loop {
+        if std::mem::take(&mut waiting) && !wait_for_job(due.as_ref(), &status, &control_rx) {
+            break 'outer;
+        }
+        if let (Some(trigger), Some(at)) = (&trigger, due) {
+            let now = chrono::Local::now();
+            if at <= now {
+                due = trigger.after(&at, &now);
+            }
+        }
+        generation += 1;
         info!("Launching command");
         let should_log_cmd = !&opts.no_log_cmd;
         let mut child_cmd = std::process::Command::new(&program);
         let mut path_env = std::env::var("PATH").ok();
+        let track =
+            !opts.no_kill_tree || !resource_limits.is_empty() || watchdog.is_enabled() || metrics::global().is_some();
+        // Hold the command until it's in the job so that anything it starts joins too.
+        let suspended = if track {
+            windows::Win32::System::Threading::CREATE_SUSPENDED.0
+        } else {
+            0
+        };
 
         child_cmd
             .args(&args)
-            .creation_flags(priority)
+            .creation_flags(priority | suspended)
             .stdout(if should_log_cmd {
                 std::process::Stdio::piped()
             } else {
@@ -189,10 +815,17 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
             child_cmd.env("PATH", path_env);
         }
 
-        let mut child = match child_cmd.spawn() {
+        status.lock().unwrap().state = ctl::ChildState::Starting;
+        let launched_at = std::time::Instant::now();
+        let spawned = stdin_for(&stdin_mode).and_then(|x| child_cmd.stdin(x).spawn());
+        let mut child = match spawned {
             Ok(c) => c,
             Err(e) => {
                 error!("Unable to launch command: {}", e);
+                events::record(events::Event::LaunchFailed {
+                    generation,
+                    error: e.to_string(),
+                });
                 service_exit_code = match e.raw_os_error() {
                     Some(win_code) => ServiceExitCode::Win32(win_code as u32),
                     None => ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0),
@@ -200,162 +833,375 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
                 break;
             }
         };
-
-        // Log stdout.
-        let output_logs_need_target = opts.log_cmd_as.is_some();
-        let stdout_option = child.stdout.take();
-        let stdout_logger = std::thread::spawn(move || {
-            if !should_log_cmd {
-                return;
+        let pid = child.id();
+        let tree = if !track {
+            None
+        } else {
+            match tree::Tree::attach(&child, &resource_limits, !opts.no_kill_tree) {
+                Ok(tree) => Some(tree),
+                Err(e) if resource_limits.is_empty() => {
+                    warn!("Unable to track the processes that the command starts: {}", e);
+                    None
+                }
+                Err(e) => {
+                    warn!("Unable to apply resource limits to the command: {}", e);
+                    None
+                }
             }
-            if let Some(stdout) = stdout_option {
-                std::io::BufReader::new(stdout).lines().for_each(|line| match line {
-                    Ok(ref x) if !x.is_empty() => {
-                        if output_logs_need_target {
-                            debug!(target: "{shawl-cmd}", "{}", x);
-                        } else {
-                            debug!("stdout: {:?}", x);
-                        }
-                    }
-                    _ => (),
-                });
+        };
+        if track {
+            if let Err(e) = tree::resume(&child) {
+                // It can't do anything suspended, so let the usual checks handle it as a failed run.
+                error!("Unable to start the command after launching it: {}", e);
+                let _ = child.kill();
             }
-        });
+        }
+        // With `--no-kill-tree`, the job is only there for the limits, the watchdog, and the metrics.
+        let kill_tree = tree.as_ref().filter(|_| !opts.no_kill_tree);
+        record(&status, events::Event::Launched { generation, pid });
+        let scheduled_restart = opts
+            .restart_at
+            .as_ref()
+            .and_then(|x| x.next_after(&chrono::Local::now()));
+        if let Some(at) = &scheduled_restart {
+            info!(
+                "Command will be restarted at {}",
+                at.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+            );
+        }
 
-        // Log stderr.
-        let stderr_option = child.stderr.take();
-        let stderr_logger = std::thread::spawn(move || {
-            if !should_log_cmd {
-                return;
+        // Hold on to the pipe until the end of the run so that the command doesn't see EOF.
+        let mut stdin = child.stdin.take();
+        for line in &opts.stdin_line {
+            if let Err(e) = write_stdin(&mut stdin, line) {
+                warn!("Unable to write to command's stdin: {}", e);
+                break;
             }
-            if let Some(stderr) = stderr_option {
-                std::io::BufReader::new(stderr).lines().for_each(|line| match line {
-                    Ok(ref x) if !x.is_empty() => {
-                        if output_logs_need_target {
-                            debug!(target: "{shawl-cmd}", "{}", x);
-                        } else {
-                            debug!("stderr: {:?}", x);
+        }
+
+        // Drain stdout and stderr into a queue so that the command never
+        // waits on the logs, and write them from a separate thread.
+        let output_queue = output::Queue::new(log_cmd_buffer, log_cmd_overflow);
+        let stdout_logger = child.stdout.take().map(|stdout| {
+            let producer = output_queue.producer(output::Stream::Stdout);
+            std::thread::spawn(move || {
+                output::pump(stdout, framing, producer.sequence(), |line, seq, read_at| {
+                    producer.push(line, seq, read_at)
+                })
+            })
+        });
+        let stderr_logger = child.stderr.take().map(|stderr| {
+            let producer = output_queue.producer(output::Stream::Stderr);
+            std::thread::spawn(move || {
+                output::pump(stderr, framing, producer.sequence(), |line, seq, read_at| {
+                    producer.push(line, seq, read_at)
+                })
+            })
+        });
+        let recent = std::sync::Arc::new(std::sync::Mutex::new(output::Recent::new(crash_buffer)));
+        let output_logger = {
+            let redactor = redactor.clone();
+            let recent = recent.clone();
+            let output_logs_need_target = opts.log_cmd_as.is_some();
+            let grouping = grouping.clone();
+            std::thread::spawn(move || {
+                let mut grouper = output::Grouper::new(grouping);
+                let mut limiter = output::Limiter::new(limits, std::time::Instant::now());
+                let log = |event| log_event(event, &redactor, output_logs_need_target);
+                output_queue.drain_ticking(Some(tick), |line| {
+                    let now = std::time::Instant::now();
+                    match line {
+                        Some(line) => {
+                            recent.lock().unwrap().push(&line);
+                            metrics::line(&line);
+                            grouper.push(line, now, |line| limiter.push(line, now, log));
+                        }
+                        None => {
+                            grouper.tick(now, |line| limiter.push(line, now, log));
+                            limiter.tick(now, log);
                         }
                     }
-                    _ => (),
                 });
-            }
-        });
+                let now = std::time::Instant::now();
+                grouper.finish(|line| limiter.push(line, now, log));
+                limiter.finish(log);
+            })
+        };
 
-        'inner: loop {
-            match shutdown_rx.recv_timeout(std::time::Duration::from_secs(1)) {
-                Ok(_) | Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+        let mut failure = None;
+        let mut limit = None;
+        let mut succeeded = false;
+        watchdog.reset();
+        let next = 'inner: loop {
+            let control = match control_rx.recv_timeout(std::time::Duration::from_secs(1)) {
+                Ok(control) => Some(control),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => Some(Control::Shutdown),
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => None,
+            };
+            let run = Running {
+                child: &mut child,
+                tree: kill_tree,
+                stdin: &mut stdin,
+                launched_at,
+                generation,
+                pid,
+            };
+            match control {
+                Some(Control::Shutdown) => {
                     status_handle.set_service_status(ServiceStatus {
                         service_type: SERVICE_TYPE,
                         current_state: ServiceState::StopPending,
                         controls_accepted: ServiceControlAccept::empty(),
                         exit_code: ServiceExitCode::NO_ERROR,
                         checkpoint: 0,
-                        wait_hint: std::time::Duration::from_millis(opts.stop_timeout.unwrap_or(3000) + 1000),
+                        wait_hint: std::time::Duration::from_millis(
+                            stop_timeout * if stop_by_stdin { 2 } else { 1 } + 1000,
+                        ),
                         process_id: None,
                     })?;
 
-                    ignore_ctrlc.store(true, std::sync::atomic::Ordering::SeqCst);
-                    info!("Sending ctrl-C to command");
-                    unsafe {
-                        if windows::Win32::System::Console::GenerateConsoleCtrlEvent(
-                            windows::Win32::System::Console::CTRL_C_EVENT,
-                            0,
-                        )
-                        .is_err()
-                        {
-                            error!(
-                                "Windows GenerateConsoleCtrlEvent failed with code {:?}",
-                                windows::Win32::Foundation::GetLastError()
-                            );
-                        };
+                    if let Some(code) = stop_command(run, &status, &opts, &ignore_ctrlc, events::Decision::Stop) {
+                        service_exit_code = code;
                     }
+                    break 'outer;
+                }
+                Some(Control::RestartChild) => {
+                    stop_command(run, &status, &opts, &ignore_ctrlc, events::Decision::Restart);
+                    break 'inner Next::Relaunch;
+                }
+                Some(Control::StopChild) => {
+                    stop_command(run, &status, &opts, &ignore_ctrlc, events::Decision::Stop);
+                    break 'inner Next::Pause;
+                }
+                Some(Control::StartChild) | None => (),
+            };
 
-                    let start_time = std::time::Instant::now();
-                    loop {
-                        match check_process(&mut child) {
-                            Ok(ProcessStatus::Running) => {
-                                if start_time.elapsed().as_millis() < (*stop_timeout).into() {
-                                    std::thread::sleep(std::time::Duration::from_millis(50))
-                                } else {
-                                    info!("Killing command because stop timeout expired",);
-                                    let _ = child.kill();
-                                    service_exit_code = ServiceExitCode::NO_ERROR;
-                                    break;
-                                }
-                            }
-                            Ok(ProcessStatus::Exited(code)) => {
-                                info!(
-                                    "Command exited after {:?} ms with code {:?}",
-                                    start_time.elapsed().as_millis(),
-                                    code
+            check_limits(tree.as_ref(), &mut limit);
+            let process = check_process(&mut child, launched_at);
+            if !matches!(process, Ok(ProcessStatus::Running)) {
+                // The job may report the limit only just before the command ends.
+                check_limits(tree.as_ref(), &mut limit);
+            }
+            let reached = limit
+                .map(|x| format!(" after reaching its {} limit", x))
+                .unwrap_or_default();
+            let condition = limit.map(|_| cli::Condition::Limit);
+            match process {
+                Ok(ProcessStatus::Running) => {
+                    let now = chrono::Local::now();
+                    let mut timed_out = None;
+                    let planned = if let Some(breach) = watch(tree.as_ref(), &mut watchdog) {
+                        warn!("Restarting command because its {}", breach);
+                        record(
+                            &status,
+                            events::Event::ThresholdExceeded {
+                                generation,
+                                pid,
+                                reason: breach.to_string(),
+                            },
+                        );
+                        Some(Next::Relaunch)
+                    } else if let Some(reason) = restart_due(&opts, launched_at, scheduled_restart.as_ref()) {
+                        info!("Restarting command {}", reason);
+                        record(
+                            &status,
+                            events::Event::RestartScheduled {
+                                generation,
+                                pid,
+                                reason,
+                            },
+                        );
+                        Some(Next::Relaunch)
+                    } else if let Some(max) = opts.max_runtime.filter(|x| launched_at.elapsed() >= *x) {
+                        warn!(
+                            "Stopping command because it ran longer than --max-runtime {}",
+                            cli::format_duration(max)
+                        );
+                        failure = Some(format!("ran longer than --max-runtime {}", cli::format_duration(max)));
+                        timed_out = Some(max);
+                        let restart = should_restart_on(Some(cli::Condition::Timeout), &opts.restart_on, &opts.stop_on)
+                            .unwrap_or_else(|| {
+                                let policy = policy.lock().unwrap();
+                                should_restart_timed_out_command(policy.restart, policy.no_restart, &policy.restart_if)
+                            });
+                        Some(if trigger.is_some() {
+                            Next::Wait
+                        } else if restart {
+                            Next::Relaunch
+                        } else {
+                            Next::Exit
+                        })
+                    } else {
+                        let overlap = trigger.as_ref().map_or(schedule::Overlap::Continue, |x| {
+                            x.overlap(due.as_ref(), &now, opts.job_overlap.unwrap_or_default())
+                        });
+                        let at_text = || {
+                            due.map(|x| x.to_rfc3339_opts(chrono::SecondsFormat::Secs, false))
+                                .unwrap_or_default()
+                        };
+                        match overlap {
+                            schedule::Overlap::Continue => None,
+                            schedule::Overlap::Skip { next } => {
+                                warn!(
+                                    "Skipping the job run due at {} because the last one is still going",
+                                    at_text()
                                 );
-                                service_exit_code = if pass.contains(&code) {
-                                    ServiceExitCode::NO_ERROR
-                                } else {
-                                    ServiceExitCode::ServiceSpecific(code as u32)
-                                };
-                                break;
+                                due = next;
+                                None
                             }
-                            _ => {
-                                info!("Command exited within stop timeout");
-                                break;
+                            schedule::Overlap::Replace => {
+                                warn!("Replacing the last job run with the one due at {}", at_text());
+                                Some(Next::Relaunch)
                             }
                         }
+                    };
+                    if let Some(next) = planned {
+                        let run = Running {
+                            child: &mut child,
+                            tree: kill_tree,
+                            stdin: &mut stdin,
+                            launched_at,
+                            generation,
+                            pid,
+                        };
+                        stop_command(run, &status, &opts, &ignore_ctrlc, decision_for(&next));
+                        if let Some(max) = timed_out {
+                            record(
+                                &status,
+                                events::Event::TimedOut {
+                                    generation,
+                                    pid,
+                                    max_runtime_ms: max.as_millis() as u64,
+                                },
+                            );
+                            if trigger.is_none() {
+                                service_exit_code = ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_TIMEOUT.0);
+                            }
+                        }
+                        break 'inner next;
                     }
-
-                    ignore_ctrlc.store(false, std::sync::atomic::Ordering::SeqCst);
-                    break 'outer;
                 }
-                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
-            };
-
-            match check_process(&mut child) {
-                Ok(ProcessStatus::Running) => (),
-                Ok(ProcessStatus::Exited(code)) => {
-                    info!("Command exited with code {:?}", code);
-                    service_exit_code = if pass.contains(&code) {
+                Ok(ProcessStatus::Exited(code, usage)) => {
+                    info!("Command exited with code {:?}{} ({})", code, reached, usage);
+                    succeeded = pass.contains(&code);
+                    if !succeeded {
+                        failure = Some(format!("exited with code {:?}{} ({})", code, reached, usage));
+                    }
+                    if trigger.is_some() {
+                        record(
+                            &status,
+                            ended(generation, pid, Some(code), usage, limit, events::Decision::Wait),
+                        );
+                        break 'inner Next::Wait;
+                    }
+                    service_exit_code = if succeeded {
                         ServiceExitCode::NO_ERROR
                     } else {
                         ServiceExitCode::ServiceSpecific(code as u32)
                     };
-                    if should_restart_exited_command(
-                        code,
-                        opts.restart,
-                        opts.no_restart,
-                        &opts.restart_if,
-                        &opts.restart_if_not,
-                    ) {
-                        break 'inner;
-                    } else {
-                        break 'outer;
-                    }
+                    let restart = should_restart_on(condition, &opts.restart_on, &opts.stop_on).unwrap_or_else(|| {
+                        let policy = policy.lock().unwrap();
+                        should_restart_exited_command(
+                            code,
+                            policy.restart,
+                            policy.no_restart,
+                            &policy.restart_if,
+                            &policy.restart_if_not,
+                        )
+                    });
+                    record(
+                        &status,
+                        ended(generation, pid, Some(code), usage, limit, decision(restart)),
+                    );
+                    break 'inner if restart { Next::Relaunch } else { Next::Exit };
                 }
-                Ok(ProcessStatus::Terminated) => {
-                    info!("Command was terminated by a signal");
-                    service_exit_code = ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0);
-                    if should_restart_terminated_command(opts.restart, opts.no_restart) {
-                        break 'inner;
-                    } else {
-                        break 'outer;
+                Ok(ProcessStatus::Terminated(usage)) => {
+                    info!("Command was terminated by a signal{} ({})", reached, usage);
+                    failure = Some(format!("was terminated by a signal{} ({})", reached, usage));
+                    if trigger.is_some() {
+                        record(
+                            &status,
+                            ended(generation, pid, None, usage, limit, events::Decision::Wait),
+                        );
+                        break 'inner Next::Wait;
                     }
+                    service_exit_code = ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0);
+                    let restart = should_restart_on(condition, &opts.restart_on, &opts.stop_on).unwrap_or_else(|| {
+                        let policy = policy.lock().unwrap();
+                        should_restart_terminated_command(policy.restart, policy.no_restart)
+                    });
+                    record(&status, ended(generation, pid, None, usage, limit, decision(restart)));
+                    break 'inner if restart { Next::Relaunch } else { Next::Exit };
                 }
                 Err(e) => {
                     info!("Error trying to determine command status: {:?}", e);
-                    service_exit_code = ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0);
-                    break 'inner;
+                    let next = if trigger.is_some() { Next::Wait } else { Next::Relaunch };
+                    record(
+                        &status,
+                        events::Event::Lost {
+                            generation,
+                            pid,
+                            error: e.to_string(),
+                            decision: decision_for(&next),
+                        },
+                    );
+                    if trigger.is_none() {
+                        service_exit_code = ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0);
+                    }
+                    break 'inner next;
                 }
             }
+        };
+        // Any job run that ends while the service keeps going counts, even if it was cut short.
+        if trigger.is_some() {
+            record(
+                &status,
+                events::Event::JobFinished {
+                    generation,
+                    pid,
+                    success: succeeded,
+                },
+            );
         }
 
-        if let Err(e) = stdout_logger.join() {
+        // Leftover processes could keep the output pipes open.
+        reap(kill_tree);
+        if let Some(Err(e)) = stdout_logger.map(|x| x.join()) {
             error!("Unable to join stdout logger thread: {:?}", e);
         }
-        if let Err(e) = stderr_logger.join() {
+        if let Some(Err(e)) = stderr_logger.map(|x| x.join()) {
             error!("Unable to join stderr logger thread: {:?}", e);
         }
+        if let Err(e) = output_logger.join() {
+            error!("Unable to join output logger thread: {:?}", e);
+        }
+        if let Some(failure) = failure {
+            report_crash(&name, &opts, &failure, &recent.lock().unwrap(), &redactor);
+        }
+        match next {
+            Next::Relaunch => (),
+            Next::Wait => waiting = true,
+            Next::Exit => break 'outer,
+            Next::Pause => {
+                {
+                    let mut status = status.lock().unwrap();
+                    status.state = ctl::ChildState::Stopped;
+                    status.pid = None;
+                    status.launched_at = None;
+                }
+                info!("Waiting for a request to start the command again");
+                loop {
+                    match control_rx.recv() {
+                        Ok(Control::StartChild | Control::RestartChild) => break,
+                        Ok(Control::StopChild) => (),
+                        Ok(Control::Shutdown) | Err(_) => break 'outer,
+                    }
+                }
+            }
+        }
     }
     debug!("Exited main service loop");
+    events::record(events::Event::ServiceStopped {});
 
     status_handle.set_service_status(ServiceStatus {
         service_type: SERVICE_TYPE,
@@ -397,6 +1243,25 @@ speculate::speculate! {
         }
     }
 
+    describe "should_restart_on" {
+        it "overrides the policy for matching conditions" {
+            let limit = Some(cli::Condition::Limit);
+            assert_eq!(should_restart_on(limit, &[cli::Condition::Limit], &[]), Some(true));
+            assert_eq!(should_restart_on(limit, &[], &[cli::Condition::Limit]), Some(false));
+            assert_eq!(should_restart_on(limit, &[], &[]), None);
+            assert_eq!(should_restart_on(None, &[cli::Condition::Limit], &[]), None);
+        }
+    }
+
+    describe "should_restart_timed_out_command" {
+        it "restarts unless told otherwise" {
+            assert!(should_restart_timed_out_command(false, false, &[]));
+            assert!(!should_restart_timed_out_command(false, true, &[]));
+            assert!(!should_restart_timed_out_command(false, false, &[1]));
+            assert!(should_restart_timed_out_command(true, false, &[1]));
+        }
+    }
+
     describe "should_restart_terminated_command" {
         it "only restarts with --restart" {
             assert!(!should_restart_terminated_command(false, false));
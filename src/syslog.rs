@@ -0,0 +1,456 @@
+use crate::cli::{SyslogFacility, SyslogProtocol, SyslogTarget};
+use std::{
+    io::Write,
+    net::ToSocketAddrs,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// How long to wait before trying to reach the collector again after a failure.
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+const WRITE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Structured data ID, using the example enterprise number from RFC 5612.
+const SD_ID: &str = "shawl@32473";
+
+#[cfg(windows)]
+static GLOBAL: std::sync::OnceLock<Syslog> = std::sync::OnceLock::new();
+
+/// The syslog sender configured by `--syslog`, if any.
+#[cfg(windows)]
+pub fn global() -> Option<&'static Syslog> {
+    GLOBAL.get()
+}
+
+#[cfg(windows)]
+pub fn install(syslog: Syslog) -> &'static Syslog {
+    GLOBAL.get_or_init(|| syslog)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    Lifecycle,
+}
+
+impl Stream {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+            Self::Lifecycle => "lifecycle",
+        }
+    }
+}
+
+impl From<crate::output::Stream> for Stream {
+    fn from(stream: crate::output::Stream) -> Self {
+        match stream {
+            crate::output::Stream::Stdout => Self::Stdout,
+            crate::output::Stream::Stderr => Self::Stderr,
+        }
+    }
+}
+
+/// Numeric severity from RFC 5424.
+#[cfg(windows)]
+pub fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SyslogOpts {
+    pub target: SyslogTarget,
+    pub facility: SyslogFacility,
+    pub app_name: String,
+    pub buffer: usize,
+}
+
+/// Sends log messages to a syslog collector on a background thread,
+/// so that a slow or missing collector never holds up the caller.
+#[derive(Clone, Debug)]
+pub struct Syslog {
+    facility: SyslogFacility,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+    sender: std::sync::mpsc::SyncSender<Vec<u8>>,
+    dropped: std::sync::Arc<AtomicU64>,
+    in_flight: std::sync::Arc<AtomicU64>,
+}
+
+impl Syslog {
+    pub fn start(opts: SyslogOpts) -> Self {
+        Self::spawn(opts, RETRY_INTERVAL)
+    }
+
+    fn spawn(opts: SyslogOpts, retry_interval: std::time::Duration) -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(opts.buffer.max(1));
+        let dropped = std::sync::Arc::new(AtomicU64::new(0));
+        let in_flight = std::sync::Arc::new(AtomicU64::new(0));
+
+        let mut connection = Connection::new(opts.target);
+        let buffer = opts.buffer;
+        let dropped2 = dropped.clone();
+        let in_flight2 = in_flight.clone();
+        std::thread::spawn(move || connection.run(receiver, buffer, &dropped2, &in_flight2, retry_interval));
+
+        Self {
+            facility: opts.facility,
+            hostname: header_field(&crate::template::hostname().unwrap_or_default(), 255),
+            app_name: header_field(&opts.app_name, 48),
+            pid: std::process::id(),
+            sender,
+            dropped,
+            in_flight,
+        }
+    }
+
+    pub fn send(&self, stream: Stream, severity: u8, message: &str) {
+        let timestamp = flexi_logger::DeferredNow::new().format_rfc3339();
+        self.enqueue(self.format(&timestamp, stream, None, severity, message));
+    }
+
+    /// Send a line of command output, keeping the time when it was read
+    /// and its sequence number.
+    #[cfg(windows)]
+    pub fn send_output(&self, line: &crate::output::Line, message: &str) {
+        let timestamp = line.read_at.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string();
+        self.enqueue(self.format(
+            &timestamp,
+            line.stream.into(),
+            Some(line.seq),
+            severity(log::Level::Info),
+            message,
+        ));
+    }
+
+    fn enqueue(&self, line: String) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if self.sender.try_send(line.into_bytes()).is_err() {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Wait a little while for queued messages to reach the collector.
+    pub fn flush(&self, timeout: std::time::Duration) {
+        let start = std::time::Instant::now();
+        while self.in_flight.load(Ordering::SeqCst) > 0 && start.elapsed() < timeout {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    fn format(&self, timestamp: &str, stream: Stream, seq: Option<u64>, severity: u8, message: &str) -> String {
+        let seq = match seq {
+            Some(seq) => format!(" seq=\"{}\"", seq),
+            None => String::new(),
+        };
+        format!(
+            "<{}>1 {} {} {} {} - [{} stream=\"{}\"{}] {}",
+            self.facility.code() * 8 + severity,
+            timestamp,
+            self.hostname,
+            self.app_name,
+            self.pid,
+            SD_ID,
+            stream.as_str(),
+            seq,
+            message
+        )
+    }
+}
+
+/// Forwards Shawl's own log records to syslog.
+#[cfg(windows)]
+pub struct LifecycleWriter(pub &'static Syslog);
+
+#[cfg(windows)]
+impl flexi_logger::writers::LogWriter for LifecycleWriter {
+    fn write(&self, _now: &mut flexi_logger::DeferredNow, record: &log::Record) -> std::io::Result<()> {
+        // Command output is sent separately so that it can be tagged with its stream.
+        if record.target() != crate::output::TARGET {
+            self.0
+                .send(Stream::Lifecycle, severity(record.level()), &record.args().to_string());
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Header fields may only contain printable ASCII without spaces.
+fn header_field(value: &str, max: usize) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max)
+        .collect();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
+    }
+}
+
+struct Connection {
+    target: SyslogTarget,
+    udp: Option<std::net::UdpSocket>,
+    tcp: Option<std::net::TcpStream>,
+}
+
+impl Connection {
+    fn new(target: SyslogTarget) -> Self {
+        Self {
+            target,
+            udp: None,
+            tcp: None,
+        }
+    }
+
+    fn run(
+        &mut self,
+        receiver: std::sync::mpsc::Receiver<Vec<u8>>,
+        buffer: usize,
+        dropped: &AtomicU64,
+        in_flight: &AtomicU64,
+        retry_interval: std::time::Duration,
+    ) {
+        let mut pending = std::collections::VecDeque::new();
+        let mut retry_at: Option<std::time::Instant> = None;
+
+        loop {
+            let received = match retry_at {
+                Some(at) => receiver.recv_timeout(at.saturating_duration_since(std::time::Instant::now())),
+                None => receiver
+                    .recv()
+                    .map_err(|_| std::sync::mpsc::RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(message) => {
+                    pending.push_back(message);
+                    if pending.len() > buffer {
+                        pending.pop_front();
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            if retry_at.is_some_and(|at| std::time::Instant::now() < at) {
+                continue;
+            }
+            retry_at = None;
+
+            while let Some(message) = pending.front() {
+                match self.send(message) {
+                    Ok(()) => {
+                        pending.pop_front();
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    Err(_) => {
+                        self.tcp = None;
+                        retry_at = Some(std::time::Instant::now() + retry_interval);
+                        break;
+                    }
+                }
+            }
+
+            if retry_at.is_none() {
+                let count = dropped.swap(0, Ordering::Relaxed);
+                if count > 0 {
+                    log::warn!("Dropped {} syslog messages while the collector was unavailable", count);
+                }
+            }
+        }
+
+        for message in pending {
+            if self.send(&message).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> std::io::Result<()> {
+        match self.target.protocol {
+            SyslogProtocol::Udp => {
+                let address = self.resolve()?;
+                if self.udp.is_none() {
+                    let local = if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                    self.udp = Some(std::net::UdpSocket::bind(local)?);
+                }
+                if let Some(socket) = &self.udp {
+                    socket.send_to(message, address)?;
+                }
+            }
+            SyslogProtocol::Tcp | SyslogProtocol::TcpLf => {
+                if self.tcp.is_none() {
+                    let stream = std::net::TcpStream::connect_timeout(&self.resolve()?, WRITE_TIMEOUT)?;
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    self.tcp = Some(stream);
+                }
+                if let Some(stream) = &mut self.tcp {
+                    let mut frame = Vec::with_capacity(message.len() + 8);
+                    if self.target.protocol == SyslogProtocol::Tcp {
+                        write!(frame, "{} ", message.len())?;
+                        frame.extend_from_slice(message);
+                    } else {
+                        // Newlines would split the message, so escape them the way rsyslog does.
+                        for &byte in message {
+                            match byte {
+                                b'\n' => frame.extend_from_slice(b"#012"),
+                                _ => frame.push(byte),
+                            }
+                        }
+                        frame.push(b'\n');
+                    }
+                    stream.write_all(&frame)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.target.address.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Unable to resolve {}", self.target.address),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    use std::io::Read;
+
+    fn opts(protocol: SyslogProtocol, address: String) -> SyslogOpts {
+        SyslogOpts {
+            target: SyslogTarget { protocol, address },
+            facility: SyslogFacility::Local0,
+            app_name: "my service".to_string(),
+            buffer: 10,
+        }
+    }
+
+    fn read_octet_frame(stream: &mut std::net::TcpStream) -> String {
+        let mut length = vec![];
+        let mut byte = [0];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b' ' {
+                break;
+            }
+            length.push(byte[0]);
+        }
+        let length: usize = String::from_utf8(length).unwrap().parse().unwrap();
+        let mut message = vec![0; length];
+        stream.read_exact(&mut message).unwrap();
+        String::from_utf8(message).unwrap()
+    }
+
+    describe "syslog" {
+        it "formats RFC 5424 messages" {
+            let syslog = Syslog::start(opts(SyslogProtocol::Udp, "127.0.0.1:9".to_string()));
+            assert_eq!(
+                syslog.format("2024-01-02T03:04:05.678+00:00", Stream::Stderr, None, 4, "oops"),
+                format!(
+                    "<132>1 2024-01-02T03:04:05.678+00:00 {} my_service {} - [shawl@32473 stream=\"stderr\"] oops",
+                    header_field(&crate::template::hostname().unwrap_or_default(), 255),
+                    std::process::id(),
+                ),
+            );
+        }
+
+        it "includes the sequence number of command output" {
+            let syslog = Syslog::start(opts(SyslogProtocol::Udp, "127.0.0.1:9".to_string()));
+            assert!(syslog
+                .format("2024-01-02T03:04:05.678+00:00", Stream::Stdout, Some(42), 6, "hi")
+                .ends_with("[shawl@32473 stream=\"stdout\" seq=\"42\"] hi"));
+        }
+
+        it "sends over UDP" {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+            let syslog = Syslog::start(opts(SyslogProtocol::Udp, socket.local_addr().unwrap().to_string()));
+
+            syslog.send(Stream::Stdout, 6, "hello");
+            syslog.flush(std::time::Duration::from_secs(5));
+
+            let mut buffer = [0; 1024];
+            let size = socket.recv(&mut buffer).unwrap();
+            let message = String::from_utf8_lossy(&buffer[..size]);
+            assert!(message.starts_with("<134>1 "));
+            assert!(message.ends_with(" [shawl@32473 stream=\"stdout\"] hello"));
+        }
+
+        it "sends over TCP with octet counting" {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let syslog = Syslog::start(opts(SyslogProtocol::Tcp, listener.local_addr().unwrap().to_string()));
+
+            syslog.send(Stream::Stdout, 6, "first");
+            syslog.send(Stream::Lifecycle, 6, "second\nline");
+
+            let (mut stream, _) = listener.accept().unwrap();
+            assert!(read_octet_frame(&mut stream).ends_with("] first"));
+            assert!(read_octet_frame(&mut stream).ends_with("] second\nline"));
+        }
+
+        it "sends over TCP with newlines" {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let syslog = Syslog::start(opts(SyslogProtocol::TcpLf, listener.local_addr().unwrap().to_string()));
+
+            syslog.send(Stream::Stdout, 6, "first");
+            syslog.send(Stream::Stdout, 6, "second");
+
+            let (stream, _) = listener.accept().unwrap();
+            let mut lines = std::io::BufRead::lines(std::io::BufReader::new(stream));
+            assert!(lines.next().unwrap().unwrap().ends_with("] first"));
+            assert!(lines.next().unwrap().unwrap().ends_with("] second"));
+        }
+
+        it "escapes newlines inside a message over TCP with newlines" {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let syslog = Syslog::start(opts(SyslogProtocol::TcpLf, listener.local_addr().unwrap().to_string()));
+
+            syslog.send(Stream::Stderr, 3, "Error\n  at main");
+            syslog.send(Stream::Stdout, 6, "next");
+
+            let (stream, _) = listener.accept().unwrap();
+            let mut lines = std::io::BufRead::lines(std::io::BufReader::new(stream));
+            assert!(lines.next().unwrap().unwrap().ends_with("] Error#012  at main"));
+            assert!(lines.next().unwrap().unwrap().ends_with("] next"));
+        }
+
+        it "buffers messages until the collector is available" {
+            let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+            let syslog = Syslog::spawn(
+                SyslogOpts { buffer: 2, ..opts(SyslogProtocol::Tcp, address.to_string()) },
+                std::time::Duration::from_millis(100),
+            );
+
+            for message in ["one", "two", "three"] {
+                syslog.send(Stream::Stdout, 6, message);
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            assert_eq!(syslog.dropped.load(Ordering::Relaxed), 1);
+
+            let listener = std::net::TcpListener::bind(address).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            assert!(read_octet_frame(&mut stream).ends_with("] two"));
+            assert!(read_octet_frame(&mut stream).ends_with("] three"));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert_eq!(syslog.dropped.load(Ordering::Relaxed), 0);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+/// Values for the placeholders in `--log-dir`, `--log-as`, and `--log-cmd-as`.
+/// A value of `None` stands for any text, for when we're looking for
+/// existing logs and can't know what it was.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Vars {
+    pub name: String,
+    pub date: Option<String>,
+    pub hostname: String,
+    pub pid: Option<String>,
+}
+
+impl Vars {
+    /// Values for the service that is starting now.
+    #[cfg(windows)]
+    pub fn current(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            date: Some(chrono::Local::now().format("%Y-%m-%d").to_string()),
+            hostname: hostname().unwrap_or_default(),
+            pid: Some(std::process::id().to_string()),
+        }
+    }
+
+    /// Values for finding the logs of a service that may have run at any time.
+    pub fn any(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            date: None,
+            hostname: hostname().unwrap_or_default(),
+            pid: None,
+        }
+    }
+}
+
+pub fn hostname() -> Option<String> {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .chain(std::fs::read_to_string("/etc/hostname").ok())
+        .map(|x| x.trim().to_string())
+        .find(|x| !x.is_empty())
+}
+
+/// Marks where `Vars::any` could not fill in a placeholder.
+pub const WILDCARD: char = '*';
+
+pub fn is_template(text: &str) -> bool {
+    text.contains(['{', '%'])
+}
+
+/// Fill in `{name}`, `{date}`, `{hostname}`, and `{pid}`,
+/// plus environment variables like `%ProgramData%`. Use `%%` for a literal `%`.
+pub fn expand(template: &str, vars: &Vars) -> Result<String, String> {
+    expand_with(template, vars, |key| std::env::var(key).ok())
+}
+
+/// Check the syntax without looking up any values.
+pub fn validate(template: &str) -> Result<String, String> {
+    expand_with(template, &Vars::default(), |_| Some(String::new()))?;
+    Ok(template.to_string())
+}
+
+fn expand_with(template: &str, vars: &Vars, env: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '%']) {
+        expanded.push_str(&rest[..start]);
+        let close = if rest[start..].starts_with('{') { '}' } else { '%' };
+        let Some(length) = rest[start + 1..].find(close) else {
+            return Err(format!("missing closing `{}` in: {}", close, template));
+        };
+        let key = &rest[start + 1..start + 1 + length];
+        rest = &rest[start + 1 + length + 1..];
+
+        if close == '%' {
+            if key.is_empty() {
+                expanded.push('%');
+            } else {
+                match env(key) {
+                    Some(value) => expanded.push_str(&value),
+                    None => return Err(format!("environment variable `{}` is not set", key)),
+                }
+            }
+            continue;
+        }
+
+        match key {
+            "name" => expanded.push_str(&vars.name),
+            "hostname" => expanded.push_str(&vars.hostname),
+            "date" => match &vars.date {
+                Some(date) => expanded.push_str(date),
+                None => expanded.push(WILDCARD),
+            },
+            "pid" => match &vars.pid {
+                Some(pid) => expanded.push_str(pid),
+                None => expanded.push(WILDCARD),
+            },
+            _ => {
+                return Err(format!(
+                    "unknown placeholder `{{{}}}` (expected `{{name}}`, `{{date}}`, `{{hostname}}`, or `{{pid}}`)",
+                    key
+                ))
+            }
+        }
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn vars() -> Vars {
+        Vars {
+            name: "my-app".to_string(),
+            date: Some("2024-01-02".to_string()),
+            hostname: "host1".to_string(),
+            pid: Some("123".to_string()),
+        }
+    }
+
+    fn env(key: &str) -> Option<String> {
+        match key {
+            "ProgramData" => Some(r"C:\ProgramData".to_string()),
+            _ => None,
+        }
+    }
+
+    describe "expand" {
+        it "leaves plain text alone" {
+            assert_eq!(expand_with("shawl", &vars(), env), Ok("shawl".to_string()));
+        }
+
+        it "fills in placeholders" {
+            assert_eq!(
+                expand_with("{name}_{hostname}_{date}_{pid}", &vars(), env),
+                Ok("my-app_host1_2024-01-02_123".to_string()),
+            );
+        }
+
+        it "fills in environment variables" {
+            assert_eq!(
+                expand_with(r"%ProgramData%\myapp\logs\100%%", &vars(), env),
+                Ok(r"C:\ProgramData\myapp\logs\100%".to_string()),
+            );
+        }
+
+        it "uses wildcards for unknown values" {
+            let vars = Vars { date: None, pid: None, ..vars() };
+            assert_eq!(expand_with("{name}_{date}_{pid}", &vars, env), Ok("my-app_*_*".to_string()));
+        }
+
+        it "rejects unknown placeholders" {
+            assert!(expand_with("{user}", &vars(), env).is_err());
+        }
+
+        it "rejects missing environment variables" {
+            assert!(expand_with("%NOPE%", &vars(), env).is_err());
+        }
+
+        it "rejects unclosed placeholders" {
+            assert!(expand_with("{name", &vars(), env).is_err());
+            assert!(expand_with("%ProgramData", &vars(), env).is_err());
+        }
+    }
+
+    describe "validate" {
+        it "checks the syntax without the environment" {
+            assert_eq!(validate("%NOPE%/{name}"), Ok("%NOPE%/{name}".to_string()));
+            assert!(validate("{user}").is_err());
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! The command and every process it starts, so that stopping the command
+//! doesn't leave orphans holding on to ports and files.
+
+use std::process::Child;
+
+/// Resources that the command's processes may use, from `--limit-*` and `--cpu-affinity`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub memory: Option<u64>,
+    /// Percentage of all CPUs.
+    pub cpu: Option<u8>,
+    pub processes: Option<u32>,
+    pub affinity: Vec<usize>,
+}
+
+impl Limits {
+    #[cfg(windows)]
+    pub fn from_opts(opts: &crate::cli::CommonOpts) -> Self {
+        Self {
+            memory: opts.limit_memory,
+            cpu: opts.limit_cpu,
+            processes: opts.limit_processes,
+            affinity: opts.cpu_affinity.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn affinity_mask(&self) -> usize {
+        self.affinity.iter().fold(0, |mask, cpu| mask | 1 << cpu)
+    }
+}
+
+/// A limit that the command ran into, as opposed to one that just slows it down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    Memory,
+    Processes,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Memory => "memory",
+            Self::Processes => "process count",
+        })
+    }
+}
+
+/// What the processes in the tree are using right now.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// Combined working set or resident set size.
+    pub memory_bytes: u64,
+    /// User and kernel CPU time so far. On Unix, this only counts processes that are still running.
+    pub cpu_ms: u64,
+}
+
+/// On Windows, this is a job object, which also enforces the limits.
+/// Unless it was attached with `kill_on_close` false, it kills its processes
+/// once it's closed, which happens when this is dropped.
+#[cfg(windows)]
+#[derive(Debug)]
+pub struct Tree {
+    job: windows::Win32::Foundation::HANDLE,
+    /// Where the job reports limits that the command reaches.
+    port: windows::Win32::Foundation::HANDLE,
+}
+
+/// On Unix, this is the process group that the command leads.
+/// Shawl only runs commands on Windows, so this is just for the tests,
+/// and it doesn't enforce any limits.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Tree {
+    pgid: libc::pid_t,
+}
+
+#[cfg(windows)]
+impl Tree {
+    /// Put the command in a new job with these limits.
+    /// Anything it starts from now on will join the job too, so it should be launched
+    /// with `CREATE_SUSPENDED` and only continue with `resume` once it's attached.
+    pub fn attach(child: &Child, limits: &Limits, kill_on_close: bool) -> std::io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use windows::Win32::{
+            Foundation::{HANDLE, INVALID_HANDLE_VALUE},
+            System::{
+                JobObjects::{
+                    AssignProcessToJobObject, CreateJobObjectW, JobObjectAssociateCompletionPortInformation,
+                    JobObjectCpuRateControlInformation, JobObjectExtendedLimitInformation, SetInformationJobObject,
+                    JOBOBJECT_ASSOCIATE_COMPLETION_PORT, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION,
+                    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE,
+                    JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOB_OBJECT_LIMIT_ACTIVE_PROCESS, JOB_OBJECT_LIMIT_AFFINITY,
+                    JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+                },
+                IO::CreateIoCompletionPort,
+            },
+        };
+
+        let set = |tree: &Self, class, info: *const std::ffi::c_void, size: usize| unsafe {
+            SetInformationJobObject(tree.job, class, info, size as u32)
+        };
+
+        let mut tree = Self {
+            job: unsafe { CreateJobObjectW(None, windows::core::PCWSTR::null()) }?,
+            port: HANDLE::default(),
+        };
+        tree.port = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, None, 0, 1) }?;
+        let port = JOBOBJECT_ASSOCIATE_COMPLETION_PORT {
+            CompletionKey: std::ptr::null_mut(),
+            CompletionPort: tree.port,
+        };
+        set(
+            &tree,
+            JobObjectAssociateCompletionPortInformation,
+            &port as *const _ as *const std::ffi::c_void,
+            std::mem::size_of_val(&port),
+        )?;
+
+        let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        if kill_on_close {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        }
+        if let Some(bytes) = limits.memory {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            info.JobMemoryLimit = usize::try_from(bytes).unwrap_or(usize::MAX);
+        }
+        if let Some(count) = limits.processes {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+            info.BasicLimitInformation.ActiveProcessLimit = count;
+        }
+        if !limits.affinity.is_empty() {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_AFFINITY;
+            info.BasicLimitInformation.Affinity = limits.affinity_mask();
+        }
+        set(
+            &tree,
+            JobObjectExtendedLimitInformation,
+            &info as *const _ as *const std::ffi::c_void,
+            std::mem::size_of_val(&info),
+        )?;
+
+        if let Some(percent) = limits.cpu {
+            let mut rate = JOBOBJECT_CPU_RATE_CONTROL_INFORMATION {
+                ControlFlags: JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+                ..Default::default()
+            };
+            // In hundredths of a percent.
+            rate.Anonymous.CpuRate = u32::from(percent) * 100;
+            set(
+                &tree,
+                JobObjectCpuRateControlInformation,
+                &rate as *const _ as *const std::ffi::c_void,
+                std::mem::size_of_val(&rate),
+            )?;
+        }
+
+        unsafe { AssignProcessToJobObject(tree.job, HANDLE(child.as_raw_handle())) }?;
+        Ok(tree)
+    }
+
+    /// The first limit that the command has reached since the last check, if any.
+    pub fn limit_reached(&self) -> Option<Limit> {
+        use windows::Win32::System::{
+            SystemServices::{JOB_OBJECT_MSG_ACTIVE_PROCESS_LIMIT, JOB_OBJECT_MSG_JOB_MEMORY_LIMIT},
+            IO::{GetQueuedCompletionStatus, OVERLAPPED},
+        };
+
+        let mut reached = None;
+        loop {
+            let mut message = 0;
+            let mut key = 0;
+            let mut overlapped: *mut OVERLAPPED = std::ptr::null_mut();
+            if unsafe { GetQueuedCompletionStatus(self.port, &mut message, &mut key, &mut overlapped, 0) }.is_err() {
+                return reached;
+            }
+            let limit = match message {
+                JOB_OBJECT_MSG_JOB_MEMORY_LIMIT => Limit::Memory,
+                JOB_OBJECT_MSG_ACTIVE_PROCESS_LIMIT => Limit::Processes,
+                _ => continue,
+            };
+            reached.get_or_insert(limit);
+        }
+    }
+
+    fn accounting(
+        &self,
+    ) -> std::io::Result<windows::Win32::System::JobObjects::JOBOBJECT_BASIC_ACCOUNTING_INFORMATION> {
+        use windows::Win32::System::JobObjects::{
+            JobObjectBasicAccountingInformation, QueryInformationJobObject, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+        };
+
+        let mut info = JOBOBJECT_BASIC_ACCOUNTING_INFORMATION::default();
+        unsafe {
+            QueryInformationJobObject(
+                self.job,
+                JobObjectBasicAccountingInformation,
+                &mut info as *mut _ as *mut std::ffi::c_void,
+                std::mem::size_of::<JOBOBJECT_BASIC_ACCOUNTING_INFORMATION>() as u32,
+                None,
+            )
+        }?;
+        Ok(info)
+    }
+
+    /// How many processes in the tree are still running.
+    pub fn count(&self) -> std::io::Result<usize> {
+        Ok(self.accounting()?.ActiveProcesses as usize)
+    }
+
+    /// The job counts CPU time for processes that have already exited too.
+    pub fn sample(&self) -> std::io::Result<Sample> {
+        use windows::Win32::{
+            Foundation::CloseHandle,
+            System::{
+                JobObjects::{JobObjectBasicProcessIdList, QueryInformationJobObject},
+                ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+                Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+            },
+        };
+
+        /// `JOBOBJECT_BASIC_PROCESS_ID_LIST` with room for more than one process.
+        #[repr(C)]
+        struct ProcessIds {
+            assigned: u32,
+            listed: u32,
+            ids: [usize; 1024],
+        }
+
+        let accounting = self.accounting()?;
+        // FILETIME durations count 100-nanosecond intervals.
+        let cpu_ms = (accounting.TotalUserTime + accounting.TotalKernelTime) as u64 / 10_000;
+
+        let mut list = ProcessIds {
+            assigned: 0,
+            listed: 0,
+            ids: [0; 1024],
+        };
+        unsafe {
+            QueryInformationJobObject(
+                self.job,
+                JobObjectBasicProcessIdList,
+                &mut list as *mut _ as *mut std::ffi::c_void,
+                std::mem::size_of::<ProcessIds>() as u32,
+                None,
+            )
+        }?;
+
+        let mut memory_bytes = 0;
+        for pid in &list.ids[..list.listed as usize] {
+            // The process may have exited since the list was made.
+            let Ok(process) = (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, *pid as u32) }) else {
+                continue;
+            };
+            let mut counters = PROCESS_MEMORY_COUNTERS {
+                cb: std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+                ..Default::default()
+            };
+            if unsafe { GetProcessMemoryInfo(process, &mut counters, counters.cb) }.is_ok() {
+                memory_bytes += counters.WorkingSetSize as u64;
+            }
+            let _ = unsafe { CloseHandle(process) };
+        }
+
+        Ok(Sample { memory_bytes, cpu_ms })
+    }
+
+    /// Kill every process in the tree and report how many there were.
+    pub fn kill(&self) -> std::io::Result<usize> {
+        let count = self.count()?;
+        if count > 0 {
+            unsafe { windows::Win32::System::JobObjects::TerminateJobObject(self.job, 1) }?;
+        }
+        Ok(count)
+    }
+}
+
+/// Let a command that was launched with `CREATE_SUSPENDED` start running.
+/// `Child` doesn't keep its main thread, so look for it, since it's the only one so far.
+#[cfg(windows)]
+pub fn resume(child: &Child) -> std::io::Result<()> {
+    use windows::Win32::{
+        Foundation::CloseHandle,
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+            },
+            Threading::{OpenThread, ResumeThread, THREAD_SUSPEND_RESUME},
+        },
+    };
+
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) }?;
+    let mut entry = THREADENTRY32 {
+        dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+        ..Default::default()
+    };
+    let mut found = unsafe { Thread32First(snapshot, &mut entry) };
+    let mut resumed = Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "the command has no threads",
+    ));
+    while found.is_ok() {
+        if entry.th32OwnerProcessID == child.id() {
+            resumed = unsafe { OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID) }
+                .map_err(std::io::Error::from)
+                .and_then(|thread| {
+                    let count = unsafe { ResumeThread(thread) };
+                    let _ = unsafe { CloseHandle(thread) };
+                    match count {
+                        u32::MAX => Err(std::io::Error::last_os_error()),
+                        _ => Ok(()),
+                    }
+                });
+            break;
+        }
+        found = unsafe { Thread32Next(snapshot, &mut entry) };
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    resumed
+}
+
+#[cfg(windows)]
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = unsafe { windows::Win32::Foundation::CloseHandle(self.job) };
+        if !self.port.is_invalid() {
+            let _ = unsafe { windows::Win32::Foundation::CloseHandle(self.port) };
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Tree {
+    /// Have the command lead a new process group, which its children will join by default.
+    pub fn prepare(command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    /// The command must have been started after `Tree::prepare`.
+    pub fn attach(child: &Child) -> std::io::Result<Self> {
+        Ok(Self {
+            pgid: child.id() as libc::pid_t,
+        })
+    }
+
+    /// The fields of `/proc/<pid>/stat` after the command name
+    /// for each process in the tree, not counting zombies.
+    fn stats(&self) -> std::io::Result<Vec<Vec<String>>> {
+        let mut stats = vec![];
+        for entry in std::fs::read_dir("/proc")? {
+            let path = entry?.path().join("stat");
+            let Ok(stat) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            // The command name is in parentheses and may contain spaces,
+            // so look for the state, parent, and group after it.
+            let Some((_, fields)) = stat.rsplit_once(')') else {
+                continue;
+            };
+            let fields: Vec<_> = fields.split_whitespace().map(str::to_string).collect();
+            if fields.len() > 21 && fields[0] != "Z" && fields[2].parse() == Ok(self.pgid) {
+                stats.push(fields);
+            }
+        }
+        Ok(stats)
+    }
+
+    /// How many processes in the tree are still running, not counting zombies.
+    pub fn count(&self) -> std::io::Result<usize> {
+        Ok(self.stats()?.len())
+    }
+
+    pub fn sample(&self) -> std::io::Result<Sample> {
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+        let field = |fields: &[String], index: usize| fields[index].parse::<u64>().unwrap_or(0);
+
+        let mut sample = Sample::default();
+        for fields in self.stats()? {
+            // utime, stime, and rss.
+            sample.cpu_ms += (field(&fields, 11) + field(&fields, 12)) * 1000 / ticks_per_second;
+            sample.memory_bytes += field(&fields, 21) * page_size;
+        }
+        Ok(sample)
+    }
+
+    /// Kill every process in the tree and report how many there were.
+    pub fn kill(&self) -> std::io::Result<usize> {
+        let count = self.count()?;
+        if count > 0 && unsafe { libc::kill(-self.pgid, libc::SIGKILL) } == -1 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                return Err(e);
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_secs(5) {
+            if condition() {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        false
+    }
+
+    describe "tree" {
+        #[cfg(target_os = "linux")]
+        it "kills the command's children too" {
+            let mut command = std::process::Command::new("sh");
+            command.args(["-c", "sleep 30 & sleep 30 & wait"]);
+            Tree::prepare(&mut command);
+            let mut child = command.spawn().unwrap();
+            let tree = Tree::attach(&child).unwrap();
+
+            assert!(wait_until(|| tree.count().unwrap() == 3));
+            assert_eq!(tree.kill().unwrap(), 3);
+            child.wait().unwrap();
+            assert!(wait_until(|| tree.count().unwrap() == 0));
+        }
+
+        #[cfg(target_os = "linux")]
+        it "finds orphans after the command exits" {
+            let mut command = std::process::Command::new("sh");
+            command.args(["-c", "sleep 30 & exit 0"]);
+            Tree::prepare(&mut command);
+            let mut child = command.spawn().unwrap();
+            let tree = Tree::attach(&child).unwrap();
+            child.wait().unwrap();
+
+            assert_eq!(tree.count().unwrap(), 1);
+            assert_eq!(tree.kill().unwrap(), 1);
+            assert!(wait_until(|| tree.count().unwrap() == 0));
+            assert_eq!(tree.kill().unwrap(), 0);
+        }
+
+        #[cfg(target_os = "linux")]
+        it "samples what the processes are using" {
+            let mut command = std::process::Command::new("sh");
+            command.args(["-c", "sleep 30 & sleep 30 & wait"]);
+            Tree::prepare(&mut command);
+            let mut child = command.spawn().unwrap();
+            let tree = Tree::attach(&child).unwrap();
+
+            assert!(wait_until(|| tree.count().unwrap() == 3));
+            let sample = tree.sample().unwrap();
+            assert!(sample.memory_bytes > 0);
+            tree.kill().unwrap();
+            child.wait().unwrap();
+            assert!(wait_until(|| tree.sample().unwrap() == Sample::default()));
+        }
+
+        it "builds an affinity mask" {
+            let limits = Limits {
+                affinity: vec![0, 2, 3],
+                ..Default::default()
+            };
+            assert_eq!(limits.affinity_mask(), 0b1101);
+            assert!(!limits.is_empty());
+            assert!(Limits::default().is_empty());
+        }
+
+        #[cfg(windows)]
+        it "kills the command's children too on Windows" {
+            use std::os::windows::process::CommandExt;
+            let mut child = std::process::Command::new("cmd")
+                .args(["/c", "ping -n 30 127.0.0.1 > nul"])
+                .creation_flags(windows::Win32::System::Threading::CREATE_SUSPENDED.0)
+                .spawn()
+                .unwrap();
+            let tree = Tree::attach(&child, &Limits::default(), true).unwrap();
+            resume(&child).unwrap();
+
+            assert!(wait_until(|| tree.count().unwrap() == 2));
+            assert_eq!(tree.kill().unwrap(), 2);
+            child.wait().unwrap();
+            assert!(wait_until(|| tree.count().unwrap() == 0));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    process::{Child, ExitStatus},
+    time::{Duration, Instant},
+};
+
+/// What a run of the command cost, for spotting leaks and slow failures.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Usage {
+    /// Wall time since the command was launched.
+    pub duration_ms: u64,
+    pub user_cpu_ms: u64,
+    pub kernel_cpu_ms: u64,
+    /// Peak working set on Windows or maximum resident set size elsewhere,
+    /// if the system would tell us.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl Usage {
+    /// When we couldn't find out anything but the wall time.
+    #[cfg(windows)]
+    pub fn wall(launched_at: Instant) -> Self {
+        Self {
+            duration_ms: millis(launched_at.elapsed()),
+            ..Default::default()
+        }
+    }
+}
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = |ms: u64| ms as f64 / 1000.0;
+        write!(
+            f,
+            "ran for {:.3} s, user CPU {:.3} s, kernel CPU {:.3} s",
+            seconds(self.duration_ms),
+            seconds(self.user_cpu_ms),
+            seconds(self.kernel_cpu_ms)
+        )?;
+        if let Some(bytes) = self.peak_memory_bytes {
+            write!(f, ", peak memory {:.1} MiB", bytes as f64 / (1024.0 * 1024.0))?;
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+/// Like `Child::try_wait`, but also report what the command used if it has exited.
+#[cfg(windows)]
+pub fn try_wait(child: &mut Child, launched_at: Instant) -> std::io::Result<Option<(ExitStatus, Usage)>> {
+    Ok(child.try_wait()?.map(|status| (status, collect(child, launched_at))))
+}
+
+/// Like `Child::wait`, but also report what the command used.
+#[cfg(windows)]
+pub fn wait(child: &mut Child, launched_at: Instant) -> std::io::Result<(ExitStatus, Usage)> {
+    let status = child.wait()?;
+    Ok((status, collect(child, launched_at)))
+}
+
+/// The process handle stays valid until the `Child` is dropped,
+/// so we can still ask about the process after it has exited.
+#[cfg(windows)]
+fn collect(child: &Child, launched_at: Instant) -> Usage {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::{
+        Foundation::{FILETIME, HANDLE},
+        System::{
+            ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+            Threading::GetProcessTimes,
+        },
+    };
+
+    // FILETIME durations count 100-nanosecond intervals.
+    let filetime_ms = |x: FILETIME| ((u64::from(x.dwHighDateTime) << 32) | u64::from(x.dwLowDateTime)) / 10_000;
+
+    let handle = HANDLE(child.as_raw_handle());
+    let mut usage = Usage::wall(launched_at);
+
+    let mut creation = FILETIME::default();
+    let mut exit = FILETIME::default();
+    let mut kernel = FILETIME::default();
+    let mut user = FILETIME::default();
+    if unsafe { GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) }.is_ok() {
+        usage.user_cpu_ms = filetime_ms(user);
+        usage.kernel_cpu_ms = filetime_ms(kernel);
+    }
+
+    let mut counters = PROCESS_MEMORY_COUNTERS {
+        cb: std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+        ..Default::default()
+    };
+    if unsafe { GetProcessMemoryInfo(handle, &mut counters, counters.cb) }.is_ok() {
+        usage.peak_memory_bytes = Some(counters.PeakWorkingSetSize as u64);
+    }
+
+    usage
+}
+
+/// Like `Child::try_wait`, but also report what the command used if it has exited.
+/// This reaps the process, so don't call `Child::try_wait` or `Child::wait` afterward.
+#[cfg(unix)]
+pub fn try_wait(child: &mut Child, launched_at: Instant) -> std::io::Result<Option<(ExitStatus, Usage)>> {
+    wait4(child, launched_at, libc::WNOHANG)
+}
+
+/// Like `Child::wait`, but also report what the command used.
+#[cfg(unix)]
+pub fn wait(child: &mut Child, launched_at: Instant) -> std::io::Result<(ExitStatus, Usage)> {
+    loop {
+        match wait4(child, launched_at, 0) {
+            Ok(Some(x)) => return Ok(x),
+            Ok(None) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn wait4(child: &Child, launched_at: Instant, options: libc::c_int) -> std::io::Result<Option<(ExitStatus, Usage)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let timeval_ms = |x: libc::timeval| x.tv_sec as u64 * 1000 + x.tv_usec as u64 / 1000;
+    // Linux reports kilobytes, but macOS reports bytes.
+    let maxrss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    match unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, options, &mut rusage) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => Ok(Some((
+            ExitStatus::from_raw(status),
+            Usage {
+                duration_ms: millis(launched_at.elapsed()),
+                user_cpu_ms: timeval_ms(rusage.ru_utime),
+                kernel_cpu_ms: timeval_ms(rusage.ru_stime),
+                peak_memory_bytes: Some(rusage.ru_maxrss as u64 * maxrss_unit),
+            },
+        ))),
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "usage" {
+        it "summarizes itself" {
+            let usage = Usage {
+                duration_ms: 12345,
+                user_cpu_ms: 1200,
+                kernel_cpu_ms: 300,
+                peak_memory_bytes: Some(45 * 1024 * 1024 + 512 * 1024),
+            };
+            assert_eq!(
+                usage.to_string(),
+                "ran for 12.345 s, user CPU 1.200 s, kernel CPU 0.300 s, peak memory 45.5 MiB",
+            );
+        }
+
+        it "leaves out unknown memory" {
+            let usage = Usage { duration_ms: 5, ..Default::default() };
+            assert_eq!(usage.to_string(), "ran for 0.005 s, user CPU 0.000 s, kernel CPU 0.000 s");
+        }
+    }
+
+    describe "waiting" {
+        #[cfg(unix)]
+        it "reports the exit status and usage" {
+            let launched_at = Instant::now();
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; exit 3"])
+                .spawn()
+                .unwrap();
+            let (status, usage) = wait(&mut child, launched_at).unwrap();
+            assert_eq!(status.code(), Some(3));
+            assert!(usage.duration_ms >= usage.user_cpu_ms);
+            assert!(usage.peak_memory_bytes.unwrap() > 0);
+        }
+
+        #[cfg(unix)]
+        it "doesn't block while the command is running" {
+            let launched_at = Instant::now();
+            let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+            assert_eq!(try_wait(&mut child, launched_at).unwrap(), None);
+            child.kill().unwrap();
+            let (status, _) = wait(&mut child, launched_at).unwrap();
+            assert_eq!(status.code(), None);
+        }
+
+        #[cfg(windows)]
+        it "reports the exit status and usage on Windows" {
+            let launched_at = Instant::now();
+            let mut child = std::process::Command::new("cmd").args(["/c", "exit 3"]).spawn().unwrap();
+            let (status, usage) = wait(&mut child, launched_at).unwrap();
+            assert_eq!(status.code(), Some(3));
+            assert!(usage.peak_memory_bytes.unwrap() > 0);
+        }
+    }
+}
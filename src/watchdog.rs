@@ -0,0 +1,180 @@
+//! Planned restarts for a command that's using too much memory or CPU,
+//! as opposed to the hard limits that the job object enforces.
+
+use crate::{cli::MaxCpu, tree::Sample};
+use std::{collections::VecDeque, time::Instant};
+
+/// Why the command should be restarted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breach {
+    Memory { bytes: u64, max: u64 },
+    Cpu { percent: f64, max: MaxCpu },
+}
+
+impl std::fmt::Display for Breach {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        match self {
+            Self::Memory { bytes, max } => write!(
+                f,
+                "memory use of {:.1} MiB exceeded --max-memory {:.1} MiB",
+                mib(*bytes),
+                mib(*max)
+            ),
+            Self::Cpu { percent, max } => write!(
+                f,
+                "CPU use averaged {:.1}% over {}, above --max-cpu {}",
+                percent,
+                crate::cli::format_duration(max.window),
+                max.to_cli()
+            ),
+        }
+    }
+}
+
+/// Checks samples from one run of the command against `--max-memory` and `--max-cpu`.
+#[derive(Debug)]
+pub struct Watchdog {
+    max_memory: Option<u64>,
+    max_cpu: Option<MaxCpu>,
+    cpus: u64,
+    /// CPU time so far at each check, oldest first, going back at least one window when possible.
+    history: VecDeque<(Instant, u64)>,
+}
+
+impl Watchdog {
+    pub fn new(max_memory: Option<u64>, max_cpu: Option<MaxCpu>, cpus: usize) -> Self {
+        Self {
+            max_memory,
+            max_cpu,
+            cpus: cpus.max(1) as u64,
+            history: VecDeque::new(),
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn from_opts(opts: &crate::cli::CommonOpts) -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |x| x.get());
+        Self::new(opts.max_memory, opts.max_cpu, cpus)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_memory.is_some() || self.max_cpu.is_some()
+    }
+
+    /// Forget the previous run.
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn check(&mut self, now: Instant, sample: Sample) -> Option<Breach> {
+        if let Some(max) = self.max_memory {
+            if sample.memory_bytes > max {
+                return Some(Breach::Memory {
+                    bytes: sample.memory_bytes,
+                    max,
+                });
+            }
+        }
+
+        let max = self.max_cpu?;
+        self.history.push_back((now, sample.cpu_ms));
+        while self.history.len() > 1 && now.duration_since(self.history[1].0) >= max.window {
+            self.history.pop_front();
+        }
+        let (since, cpu_ms) = self.history[0];
+        let elapsed = now.duration_since(since);
+        if elapsed < max.window {
+            return None;
+        }
+        // Processes that exit take their CPU time with them on Unix.
+        let used = sample.cpu_ms.saturating_sub(cpu_ms);
+        let percent = used as f64 * 100.0 / (elapsed.as_millis() as f64 * self.cpus as f64);
+        if percent > f64::from(max.percent) {
+            Some(Breach::Cpu { percent, max })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    use std::time::Duration;
+
+    fn sample(memory_bytes: u64, cpu_ms: u64) -> Sample {
+        Sample { memory_bytes, cpu_ms }
+    }
+
+    fn max_cpu(percent: u8, seconds: u64) -> Option<MaxCpu> {
+        Some(MaxCpu {
+            percent,
+            window: Duration::from_secs(seconds),
+        })
+    }
+
+    describe "watchdog" {
+        it "is off without thresholds" {
+            let mut watchdog = Watchdog::new(None, None, 4);
+            assert!(!watchdog.is_enabled());
+            assert_eq!(watchdog.check(Instant::now(), sample(u64::MAX, u64::MAX)), None);
+        }
+
+        it "trips on memory right away" {
+            let mut watchdog = Watchdog::new(Some(1024 * 1024), None, 4);
+            let now = Instant::now();
+            assert_eq!(watchdog.check(now, sample(1024 * 1024, 0)), None);
+            assert_eq!(
+                watchdog.check(now, sample(3 * 1024 * 1024, 0)),
+                Some(Breach::Memory {
+                    bytes: 3 * 1024 * 1024,
+                    max: 1024 * 1024
+                }),
+            );
+        }
+
+        it "waits for a full window of CPU use" {
+            let mut watchdog = Watchdog::new(None, max_cpu(50, 10), 2);
+            let start = Instant::now();
+            // Both CPUs are busy from the start.
+            for second in 0..10 {
+                let now = start + Duration::from_secs(second);
+                assert_eq!(watchdog.check(now, sample(0, second * 2000)), None);
+            }
+            let breach = watchdog.check(start + Duration::from_secs(10), sample(0, 20_000)).unwrap();
+            assert_eq!(breach, Breach::Cpu { percent: 100.0, max: max_cpu(50, 10).unwrap() });
+            assert_eq!(breach.to_string(), "CPU use averaged 100.0% over 10s, above --max-cpu 50%:10s");
+        }
+
+        it "averages over the most recent window" {
+            let mut watchdog = Watchdog::new(None, max_cpu(50, 10), 1);
+            let start = Instant::now();
+            let mut cpu_ms = 0;
+            // Busy for 10 seconds, then idle for 10 seconds.
+            for second in 0..=20 {
+                if (1..=10).contains(&second) {
+                    cpu_ms += 1000;
+                }
+                // Carry on as if it hadn't tripped, to see the average come back down.
+                let breach = watchdog.check(start + Duration::from_secs(second), sample(0, cpu_ms));
+                assert_eq!(breach.is_some(), (10..15).contains(&second), "at {} s", second);
+            }
+        }
+
+        it "starts over for each run" {
+            let mut watchdog = Watchdog::new(None, max_cpu(50, 10), 1);
+            let start = Instant::now();
+            watchdog.check(start, sample(0, 0));
+            watchdog.reset();
+            assert_eq!(watchdog.check(start + Duration::from_secs(10), sample(0, 10_000)), None);
+        }
+
+        it "describes memory use" {
+            let breach = Breach::Memory {
+                bytes: 2200 * 1024 * 1024,
+                max: 2048 * 1024 * 1024,
+            };
+            assert_eq!(breach.to_string(), "memory use of 2200.0 MiB exceeded --max-memory 2048.0 MiB");
+        }
+    }
+}
@@ -14,6 +14,10 @@ def docs(ctx):
         "--help",
         "add --help",
         "run --help",
+        "logs --help",
+        "history --help",
+        "status --help",
+        "ctl --help",
     ]
 
     lines = [
@@ -20,10 +20,17 @@ speculate::speculate! {
         format!("{}/target/debug/log_dir", env!("CARGO_MANIFEST_DIR"))
     }
 
+    fn events_file() -> String {
+        format!("{}/target/debug/shawl_for_shawl_events.jsonl", env!("CARGO_MANIFEST_DIR"))
+    }
+
     fn delete_log() {
         if log_exists() {
             std::fs::remove_file(log_file()).unwrap();
         }
+        if std::path::Path::new(&events_file()).exists() {
+            std::fs::remove_file(events_file()).unwrap();
+        }
         if std::path::Path::new(&log_custom_dir()).is_dir() {
             std::fs::remove_dir_all(log_custom_dir()).unwrap();
         }
@@ -143,6 +150,37 @@ speculate::speculate! {
             assert!(stdout.contains("WIN32_EXIT_CODE    : 0  (0x0)"));
         }
 
+        it "stops a command by writing to its stdin" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--stdin", "pipe", "--stop-stdin", "quit",
+                "--", &child(), "--infinite", "--stop-on-stdin", "quit",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 1  STOPPED"));
+            assert!(stdout.contains("WIN32_EXIT_CODE    : 0  (0x0)"));
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.contains("Received quit on stdin"));
+            assert!(!log.contains("Sending ctrl-C to command"));
+        }
+
+        it "stops a command by closing its stdin" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--stdin", "pipe", "--stop-stdin-close",
+                "--", &child(), "--infinite", "--stop-on-stdin", "quit",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.contains("stdin was closed"));
+            assert!(!log.contains("Sending ctrl-C to command"));
+        }
+
         it "logs command output by default" {
             run_shawl(&["add", "--name", "shawl", "--", &child()]);
             run_cmd(&["sc", "start", "shawl"]);
@@ -153,6 +191,20 @@ speculate::speculate! {
             assert!(log.contains("stderr: \"shawl-child message on stderr\""));
         }
 
+        it "keeps up with a command that floods its output" {
+            run_shawl(&["add", "--name", "shawl", "--log-cmd-buffer", "1000", "--", &child(), "--flood", "500000"]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(10));
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 1  STOPPED"));
+            assert!(stdout.contains("WIN32_EXIT_CODE    : 0  (0x0)"));
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.contains("Command exited with code 0"));
+        }
+
         it "disables all logging with --no-log" {
             run_shawl(&["add", "--name", "shawl", "--no-log", "--", &child()]);
             run_cmd(&["sc", "start", "shawl"]);
@@ -232,5 +284,210 @@ speculate::speculate! {
             ).unwrap();
             assert!(pattern.is_match(&log));
         }
+
+        it "stops a command that runs longer than --max-runtime with --stop-on timeout" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--max-runtime", "2s", "--stop-on", "timeout",
+                "--", &child(), "--infinite",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(6));
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 1  STOPPED"));
+            assert!(stdout.contains("WIN32_EXIT_CODE    : 1460  (0x5b4)"));
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.contains("Stopping command because it ran longer than --max-runtime 2s"));
+            let events = std::fs::read_to_string(events_file()).unwrap();
+            assert!(events.contains(r#""event":"timed_out""#));
+            assert!(events.contains(r#""max_runtime_ms":2000"#));
+            assert!(events.contains(r#""decision":"stop""#));
+        }
+
+        it "stops a command that runs longer than --max-runtime with --no-restart" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--max-runtime", "2s", "--no-restart",
+                "--", &child(), "--infinite",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(6));
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 1  STOPPED"));
+            assert!(stdout.contains("WIN32_EXIT_CODE    : 1460  (0x5b4)"));
+        }
+
+        it "restarts a command that runs longer than --max-runtime" {
+            run_shawl(&["add", "--name", "shawl", "--max-runtime", "2s", "--", &child(), "--infinite"]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(6));
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 4  RUNNING"));
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.matches("Launching command").count() >= 2);
+            let events = std::fs::read_to_string(events_file()).unwrap();
+            assert!(events.contains(r#""event":"timed_out""#));
+            assert!(events.contains(r#""decision":"restart""#));
+        }
+
+        it "restarts a command that runs longer than --max-runtime with --restart-on timeout" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--max-runtime", "2s", "--no-restart", "--restart-on", "timeout",
+                "--", &child(), "--infinite",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(6));
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 4  RUNNING"));
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.matches("Launching command").count() >= 2);
+        }
+
+        it "lets a job that runs longer than --max-runtime wait for its next run" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--job-every", "1h", "--max-runtime", "2s",
+                "--", &child(), "--infinite",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(6));
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 4  RUNNING"));
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("WIN32_EXIT_CODE    : 0  (0x0)"));
+            let events = std::fs::read_to_string(events_file()).unwrap();
+            assert!(events.contains(r#""event":"timed_out""#));
+            assert!(events.contains(r#""decision":"wait""#));
+            assert!(events.contains(r#""success":false"#));
+        }
+
+        it "records whether each job run succeeded" {
+            run_shawl(&["add", "--name", "shawl", "--job-every", "2s", "--pass", "3", "--", &child(), "--exit", "3"]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(4));
+
+            // The service keeps going between runs.
+            let sc_output = run_cmd(&["sc", "query", "shawl"]);
+            let stdout = String::from_utf8_lossy(&sc_output.stdout);
+            assert!(stdout.contains("STATE              : 4  RUNNING"));
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let events = std::fs::read_to_string(events_file()).unwrap();
+            assert!(events.matches(r#""event":"job_finished""#).count() >= 2);
+            assert!(events.contains(r#""success":true"#));
+            assert!(!events.contains(r#""success":false"#));
+        }
+
+        it "records a failed job run" {
+            run_shawl(&["add", "--name", "shawl", "--job-every", "1h", "--", &child(), "--exit", "7"]);
+            run_cmd(&["sc", "start", "shawl"]);
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let events = std::fs::read_to_string(events_file()).unwrap();
+            assert!(events.contains(r#""decision":"wait""#));
+            assert!(events.contains(r#""success":false"#));
+        }
+
+        it "skips a job run while the last one is still going" {
+            run_shawl(&["add", "--name", "shawl", "--job-every", "1s", "--", &child(), "--infinite"]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(3));
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.contains("because the last one is still going"));
+            assert_eq!(log.matches("Launching command").count(), 1);
+        }
+
+        it "replaces a job run that's still going when the next one is due" {
+            run_shawl(&[
+                "add", "--name", "shawl", "--job-every", "2s", "--job-overlap", "replace",
+                "--", &child(), "--infinite",
+            ]);
+            run_cmd(&["sc", "start", "shawl"]);
+            std::thread::sleep(std::time::Duration::from_secs(4));
+            run_cmd(&["sc", "stop", "shawl"]);
+
+            let log = std::fs::read_to_string(log_file()).unwrap();
+            assert!(log.contains("Replacing the last job run"));
+            assert!(log.matches("Launching command").count() >= 2);
+
+            let events = std::fs::read_to_string(events_file()).unwrap();
+            assert!(events.contains(r#""success":false"#));
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "shawl-child" {
+        it "exits on a stdin keyword" {
+            use std::io::Write;
+
+            let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_shawl-child"))
+                .args(["--infinite", "--stop-on-stdin", "quit"])
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .unwrap();
+            child.stdin.as_mut().unwrap().write_all(b"not yet\r\nquit\r\n").unwrap();
+
+            let start = std::time::Instant::now();
+            while child.try_wait().unwrap().is_none() {
+                if start.elapsed() > std::time::Duration::from_secs(10) {
+                    child.kill().unwrap();
+                    panic!("shawl-child did not exit");
+                }
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            assert!(child.wait().unwrap().success());
+        }
+    }
+
+    describe "shawl logs" {
+        it "shows the logs from a directory of sample files" {
+            let dir = format!("{}/target/logs_sample", env!("CARGO_MANIFEST_DIR"));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                format!("{}/shawl_for_sample_r2024-01-01_00-00-00.log", dir),
+                "2024-01-01 00:00:00.000 [INFO] Launching command\n\
+                 2024-01-01 00:00:00.100 [DEBUG] #0 stdout: \"first\"\n",
+            )
+            .unwrap();
+            std::fs::write(
+                format!("{}/shawl_for_sample_rCURRENT.log", dir),
+                "2024-01-02 00:00:00.000 [INFO] Launching command\n\
+                 2024-01-02 00:00:00.100 [DEBUG] #0 stderr: \"second\"\n",
+            )
+            .unwrap();
+
+            let output = std::process::Command::new(env!("CARGO_BIN_EXE_shawl"))
+                .args(["logs", "--name", "sample", "--log-dir", &dir, "--cmd"])
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                "2024-01-01 00:00:00.100 [DEBUG] #0 stdout: \"first\"\n\
+                 2024-01-02 00:00:00.100 [DEBUG] #0 stderr: \"second\"\n",
+            );
+        }
     }
 }